@@ -1,54 +1,264 @@
-use anyhow::{Context, Result};
-use rnix::SyntaxNode;
+use rnix::{SyntaxElement, SyntaxKind, SyntaxNode};
 
-use crate::verify_getter::SyntaxNodeAndWhitespace;
+use crate::error::{NixEditorError, Result};
+use crate::remover::{find_dep, trailing_comment};
+use crate::sorter::dep_sort_key;
+use crate::verify_getter::{normalize_dep, SyntaxNodeAndWhitespace};
+use crate::AddPosition;
+
+// How add_dep_with places the new dep
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    // where the dep goes in a list spanning multiple lines. The front if not set,
+    // except that a dep with its own comment goes at the back
+    pub position: Option<AddPosition>,
+
+    // how far to indent the dep past the list when the list is empty, 2 if not set
+    // (or a tab if the list's line is tab indented).
+    // deps added to a list with entries get the same indentation as those
+    pub indent: Option<usize>,
+
+    // put the dep at the top of the group under the first comment containing this
+    pub group: Option<String>,
+
+    // a comment to put on the line above the dep, e.g. why it's needed
+    pub comment: Option<String>,
+
+    // what the file calls the package set, so `with p;` lists get bare names.
+    // pkgs if not set
+    pub arg_name: Option<String>,
+}
 
 pub fn add_dep(
     deps_list: SyntaxNodeAndWhitespace,
     new_dep_opt: Option<String>,
+    sorted: bool,
 ) -> Result<SyntaxNode> {
-    let new_dep = new_dep_opt.context("error: no dependency")?;
+    let options = AddOptions {
+        position: sorted.then_some(AddPosition::Sorted),
+        ..Default::default()
+    };
+    add_dep_with(deps_list, new_dep_opt, &options)
+}
+
+pub fn add_dep_with(
+    deps_list: SyntaxNodeAndWhitespace,
+    new_dep_opt: Option<String>,
+    options: &AddOptions,
+) -> Result<SyntaxNode> {
+    let new_dep =
+        new_dep_opt.ok_or_else(|| NixEditorError::InvalidOp("no dependency".to_string()))?;
     let whitespace = deps_list.whitespace;
     let deps_list = deps_list.node;
 
-    for dep in deps_list.children() {
-        if dep.to_string() == new_dep {
-            // dep is already present in the deps_list, we're done
-            return Ok(deps_list);
-        }
+    let arg_name = options.arg_name.as_deref().unwrap_or("pkgs");
+
+    // under `with pkgs;` this also catches `ncdu` when the list has `pkgs.ncdu`
+    if find_dep(&deps_list, &new_dep, arg_name).is_some() {
+        // dep is already present in the deps_list, we're done
+        return Ok(deps_list);
     }
 
-    let mut base_indent = 0;
-    if let Some(w) = whitespace {
-        base_indent = w.text().replace("\n", "").len();
+    // under `with pkgs;` the list uses bare names, so pkgs.cowsay goes in as cowsay.
+    // Bare names added anywhere else are left as they are
+    let new_dep = normalize_dep(&deps_list, &new_dep, arg_name);
+
+    // the indentation of the line the list is on, kept as is so tabs stay tabs
+    let base_indent = match &whitespace {
+        Some(w) => w.text().rsplit('\n').next().unwrap_or_default().to_string(),
+        None => String::new(),
+    };
+    // a tab indented file is indented one more tab, unless told otherwise
+    let indent_step = match options.indent {
+        None if base_indent.contains('\t') => "\t".to_string(),
+        indent => " ".repeat(indent.unwrap_or(2)),
+    };
+
+    let position = options.position.unwrap_or(AddPosition::Front);
+
+    // line up with the entry the new dep ends up next to
+    let mut entry_indents = existing_entry_indents(&deps_list);
+    let entry_indent = match position {
+        AddPosition::Back => entry_indents.last(),
+        _ => entry_indents.next(),
     }
-    let entry_indent = base_indent + 2;
+    .unwrap_or_else(|| format!("{}{}", base_indent, indent_step));
 
     let has_newline = deps_list.to_string().contains('\n');
 
-    let newline = match has_newline {
+    // a list written on one line, like `[ pkgs.a pkgs.b ]`, stays on one line.
+    // `[]` is expanded like before, and a comment needs a line of its own
+    if !has_newline && deps_list.children_with_tokens().count() > 2 && options.comment.is_none() {
+        let insert_at = match position {
+            AddPosition::Front => 1,
+            AddPosition::Back => list_end_index(&deps_list),
+            AddPosition::Sorted => sorted_insert_index(&deps_list, &new_dep).unwrap_or(1),
+        };
+        deps_list.splice_children(
+            insert_at..insert_at,
+            parse_elements(&format!(" {}", new_dep)),
+        );
+        return Ok(deps_list);
+    }
+
+    let mut newline = match has_newline {
         true => String::new(),
-        false => std::iter::once("\n")
-            .chain(std::iter::repeat(" ").take(base_indent))
-            .collect(),
+        false => format!("\n{}", base_indent),
     };
 
-    deps_list.splice_children(
-        1..1,
-        vec![rnix::NodeOrToken::Node(
-            rnix::Root::parse(&format!(
-                "\n{}{}{newline}",
-                &" ".repeat(entry_indent),
-                new_dep
-            ))
-            .syntax()
-            .clone_for_update(),
-        )],
-    );
+    let mut insert_at = 1;
+    // how many of the elements at insert_at the new dep replaces
+    let mut replaced = 0;
+    if has_newline {
+        let group_index = options
+            .group
+            .as_ref()
+            .and_then(|group| group_insert_index(&deps_list, group));
+        // the front is below any comments heading the list so the first group keeps its header
+        let position_index = match position {
+            AddPosition::Front => leading_comments_end(&deps_list),
+            AddPosition::Back => list_end_index(&deps_list),
+            AddPosition::Sorted => sorted_insert_index(&deps_list, &new_dep)
+                .unwrap_or_else(|| leading_comments_end(&deps_list)),
+        };
+        insert_at = group_index.unwrap_or(position_index);
+
+        // whatever comes next has to go on its own line, e.g. a dep sharing the
+        // opening bracket's line in `[ pkgs.a # x`
+        let next: Vec<SyntaxElement> = deps_list
+            .children_with_tokens()
+            .skip(insert_at)
+            .take(2)
+            .collect();
+        match next.as_slice() {
+            [whitespace, after, ..]
+                if whitespace.kind() == SyntaxKind::TOKEN_WHITESPACE
+                    && !whitespace.to_string().contains('\n')
+                    && after.kind() != SyntaxKind::TOKEN_R_BRACK =>
+            {
+                newline = format!("\n{}", entry_indent);
+                replaced = 1;
+            }
+            [next, ..]
+                if next.kind() != SyntaxKind::TOKEN_WHITESPACE
+                    && next.kind() != SyntaxKind::TOKEN_R_BRACK =>
+            {
+                newline = format!("\n{}", entry_indent);
+            }
+            _ => {}
+        }
+    }
+
+    // splice in the parsed whitespace and dep themselves rather than the root
+    // wrapping them, so the list's children stay plain deps for later edits
+    let comment = match &options.comment {
+        Some(comment) => comment_lines(comment, &entry_indent),
+        None => String::new(),
+    };
+    let elements = parse_elements(&format!("{comment}\n{}{}{newline}", entry_indent, new_dep));
+    deps_list.splice_children(insert_at..insert_at + replaced, elements);
 
     Ok(deps_list)
 }
 
+// The top level elements of text, detached so they can be spliced into a list
+pub(crate) fn parse_elements(text: &str) -> Vec<SyntaxElement> {
+    let parsed = rnix::Root::parse(text).syntax().clone_for_update();
+    let elements: Vec<SyntaxElement> = parsed.children_with_tokens().collect();
+    for element in &elements {
+        element.detach();
+    }
+    elements
+}
+
+// comment as `# ` lines to go above a dep, each starting on a new line
+fn comment_lines(comment: &str, indent: &str) -> String {
+    comment
+        .lines()
+        .map(|line| {
+            let line = line.trim_start_matches('#').trim();
+            format!("\n{}# {}", indent, line).trim_end().to_string()
+        })
+        .collect()
+}
+
+// The indentation of each of the deps already in the list that start a line
+pub(crate) fn existing_entry_indents(deps_list: &SyntaxNode) -> impl Iterator<Item = String> {
+    deps_list.children().filter_map(|dep| {
+        let whitespace = dep
+            .prev_sibling_or_token()
+            .filter(|prev| prev.kind() == SyntaxKind::TOKEN_WHITESPACE)?
+            .to_string();
+        whitespace
+            .rfind('\n')
+            .map(|newline| whitespace[newline + 1..].to_string())
+    })
+}
+
+// The index just past the comments between the opening bracket and the first dep
+fn leading_comments_end(deps_list: &SyntaxNode) -> usize {
+    deps_list
+        .children_with_tokens()
+        .skip(1)
+        .take_while(|element| element.as_node().is_none())
+        .filter(|element| element.kind() == SyntaxKind::TOKEN_COMMENT)
+        .last()
+        .map_or(1, |comment| comment.index() + 1)
+}
+
+// The index just past the last entry in the list, or past the leading comments
+// when it doesn't have any deps
+fn list_end_index(deps_list: &SyntaxNode) -> usize {
+    match deps_list.children().last() {
+        Some(dep) => entry_end_index(&dep),
+        None => leading_comments_end(deps_list),
+    }
+}
+
+// The index just past the first comment mentioning group, ignoring case
+fn group_insert_index(deps_list: &SyntaxNode, group: &str) -> Option<usize> {
+    let group = group.trim_start_matches('#').trim().to_lowercase();
+
+    deps_list
+        .children_with_tokens()
+        .filter(|element| element.kind() == SyntaxKind::TOKEN_COMMENT)
+        .find(|comment| comment.to_string().to_lowercase().contains(&group))
+        .map(|comment| comment.index() + 1)
+}
+
+// Where new_dep needs to go to keep the list in alphabetical order, or None if
+// the list isn't sorted to begin with
+fn sorted_insert_index(deps_list: &SyntaxNode, new_dep: &str) -> Option<usize> {
+    let deps: Vec<SyntaxNode> = deps_list.children().collect();
+
+    let is_sorted = deps
+        .windows(2)
+        .all(|pair| dep_sort_key(&pair[0].to_string()) <= dep_sort_key(&pair[1].to_string()));
+    if !is_sorted {
+        return None;
+    }
+
+    let new_key = dep_sort_key(new_dep);
+    let before = deps
+        .iter()
+        .take_while(|dep| dep_sort_key(&dep.to_string()) <= new_key)
+        .last();
+
+    // first in order still goes below any comments heading the list
+    match before {
+        Some(dep) => Some(entry_end_index(dep)),
+        None => Some(leading_comments_end(deps_list)),
+    }
+}
+
+// The index just past dep, including a comment trailing it on the same line
+fn entry_end_index(dep: &SyntaxNode) -> usize {
+    match trailing_comment(dep) {
+        Some(comment) => comment.index() + 1,
+        None => dep.index() + 1,
+    }
+}
+
 #[cfg(test)]
 mod add_tests {
     use super::*;
@@ -56,7 +266,32 @@ mod add_tests {
     use crate::DepType;
 
     fn test_add(dep_type: DepType, new_dep: &str, initial_contents: &str, expected_contents: &str) {
-        let tree = rnix::Root::parse(&initial_contents)
+        check_add(
+            dep_type,
+            new_dep,
+            false,
+            initial_contents,
+            expected_contents,
+        )
+    }
+
+    fn test_sorted_add(
+        dep_type: DepType,
+        new_dep: &str,
+        initial_contents: &str,
+        expected_contents: &str,
+    ) {
+        check_add(dep_type, new_dep, true, initial_contents, expected_contents)
+    }
+
+    fn check_add(
+        dep_type: DepType,
+        new_dep: &str,
+        sorted: bool,
+        initial_contents: &str,
+        expected_contents: &str,
+    ) {
+        let tree = rnix::Root::parse(initial_contents)
             .syntax()
             .clone_for_update();
 
@@ -65,7 +300,7 @@ mod add_tests {
 
         let deps_list = deps_list_res.unwrap();
 
-        let new_deps_list = add_dep(deps_list, Some(new_dep.to_string()));
+        let new_deps_list = add_dep(deps_list, Some(new_dep.to_string()), sorted);
         assert!(new_deps_list.is_ok());
 
         assert_eq!(tree.to_string(), expected_contents.to_string());
@@ -138,6 +373,22 @@ mod add_tests {
         )
     }
 
+    #[test]
+    fn test_duplicate_add_spaced_differently() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    (pkgs.python3.withPackages (ps: [ ps.numpy ]))
+  ];
+}
+"#;
+        test_add(
+            DepType::Regular,
+            "(pkgs.python3.withPackages (ps: [ps.numpy]))",
+            contents,
+            contents,
+        )
+    }
+
     #[test]
     fn test_with_pkgs_add() {
         test_add(
@@ -151,12 +402,19 @@ mod add_tests {
         "#,
             r#"{ pkgs }: {
   deps = with pkgs; [
-    pkgs.ncdu
+    ncdu
     test
   ];
 }
         "#,
-        )
+        );
+        // only the leading pkgs. goes
+        test_add(
+            DepType::Regular,
+            "pkgs.python3Packages.pip",
+            "{ pkgs }: {\n  deps = with pkgs; [ ncdu ];\n}",
+            "{ pkgs }: {\n  deps = with pkgs; [ python3Packages.pip ncdu ];\n}",
+        );
     }
 
     const PYTHON_REPLIT_NIX: &str = r#"{ pkgs }: {
@@ -224,4 +482,447 @@ mod add_tests {
 }"#,
         );
     }
+
+    #[test]
+    fn test_sorted_add_middle() {
+        test_sorted_add(
+            DepType::Regular,
+            "pkgs.htop",
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.htop
+    pkgs.ncdu
+  ];
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_sorted_add_end() {
+        test_sorted_add(
+            DepType::Regular,
+            "pkgs.zip",
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu # disk usage
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu # disk usage
+    pkgs.zip
+  ];
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_sorted_add_keeps_comment_with_next_dep() {
+        test_sorted_add(
+            DepType::Python,
+            "pkgs.libuuid",
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.glib
+      # Needed for matplotlib
+      pkgs.xorg.libX11
+    ];
+  };
+}"#,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.glib
+      pkgs.libuuid
+      # Needed for matplotlib
+      pkgs.xorg.libX11
+    ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_sorted_add_unsorted_list_adds_to_front() {
+        test_sorted_add(
+            DepType::Regular,
+            "pkgs.htop",
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.ncdu
+    pkgs.cowsay
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.htop
+    pkgs.ncdu
+    pkgs.cowsay
+  ];
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_sorted_add_empty() {
+        test_sorted_add(
+            DepType::Regular,
+            "pkgs.test",
+            r#"{ pkgs }: { deps = []; }"#,
+            r#"{ pkgs }: { deps = [
+  pkgs.test
+]; }"#,
+        )
+    }
+
+    #[test]
+    fn test_with_pkgs_add_existing_dep() {
+        let contents = r#"{ pkgs }: {
+  deps = with pkgs; [
+    pkgs.ncdu
+    cowsay
+  ];
+}"#;
+        test_add(DepType::Regular, "ncdu", contents, contents);
+        test_add(DepType::Regular, "pkgs.cowsay", contents, contents);
+    }
+
+    #[test]
+    fn test_add_bare_name_without_with_pkgs() {
+        // without `with pkgs;` ncdu and pkgs.ncdu aren't the same thing
+        test_add(
+            DepType::Regular,
+            "ncdu",
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.ncdu
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    ncdu
+    pkgs.ncdu
+  ];
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_add_matches_existing_indent() {
+        test_add(
+            DepType::Regular,
+            "pkgs.ncdu",
+            r#"{ pkgs }: {
+    deps = [
+        pkgs.cowsay
+    ];
+}"#,
+            r#"{ pkgs }: {
+    deps = [
+        pkgs.ncdu
+        pkgs.cowsay
+    ];
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_add_tab_indented() {
+        test_add(
+            DepType::Regular,
+            "pkgs.ncdu",
+            "{ pkgs }: {\n\tdeps = [\n\t\tpkgs.cowsay\n\t];\n}",
+            "{ pkgs }: {\n\tdeps = [\n\t\tpkgs.ncdu\n\t\tpkgs.cowsay\n\t];\n}",
+        );
+        test_add(
+            DepType::Regular,
+            "pkgs.ncdu",
+            "{ pkgs }: {\n\tdeps = [];\n}",
+            "{ pkgs }: {\n\tdeps = [\n\t\tpkgs.ncdu\n\t];\n}",
+        );
+    }
+
+    #[test]
+    fn test_add_indent_for_empty_list() {
+        let tree = rnix::Root::parse("{ pkgs }: {\n    deps = [];\n}")
+            .syntax()
+            .clone_for_update();
+        let options = AddOptions {
+            indent: Some(4),
+            ..Default::default()
+        };
+
+        let res = add_dep_with(
+            verify_get(&tree, DepType::Regular).unwrap(),
+            Some("pkgs.ncdu".to_string()),
+            &options,
+        );
+        assert!(res.is_ok());
+
+        assert_eq!(
+            tree.to_string(),
+            "{ pkgs }: {\n    deps = [\n        pkgs.ncdu\n    ];\n}"
+        );
+    }
+
+    const GROUPED_REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+
+      # Needed for matplotlib
+      pkgs.xorg.libX11
+    ];
+  };
+}"#;
+
+    fn test_group_add(group: Option<&str>, expected_contents: &str) {
+        let tree = rnix::Root::parse(GROUPED_REPLIT_NIX)
+            .syntax()
+            .clone_for_update();
+        let options = AddOptions {
+            group: group.map(|group| group.to_string()),
+            ..Default::default()
+        };
+
+        let res = add_dep_with(
+            verify_get(&tree, DepType::Python).unwrap(),
+            Some("pkgs.glib".to_string()),
+            &options,
+        );
+        assert!(res.is_ok());
+
+        assert_eq!(tree.to_string(), expected_contents);
+    }
+
+    #[test]
+    fn test_add_below_leading_comment() {
+        test_group_add(
+            None,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.glib
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+
+      # Needed for matplotlib
+      pkgs.xorg.libX11
+    ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_add_to_group() {
+        test_group_add(
+            Some("Matplotlib"),
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+
+      # Needed for matplotlib
+      pkgs.glib
+      pkgs.xorg.libX11
+    ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_add_with_dep_on_bracket_line() {
+        let contents = r#"{ pkgs }: {
+  deps = [ pkgs.a # x
+    pkgs.b ];
+}"#;
+        test_add(
+            DepType::Regular,
+            "pkgs.c",
+            contents,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.c
+    pkgs.a # x
+    pkgs.b ];
+}"#,
+        );
+
+        // the comment doesn't hide pkgs.a
+        test_add(DepType::Regular, "pkgs.a", contents, contents);
+    }
+
+    fn test_position_add(position: AddPosition, initial_contents: &str, expected_contents: &str) {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+        let options = AddOptions {
+            position: Some(position),
+            ..Default::default()
+        };
+
+        let res = add_dep_with(
+            verify_get(&tree, DepType::Regular).unwrap(),
+            Some("pkgs.test".to_string()),
+            &options,
+        );
+        assert!(res.is_ok());
+
+        assert_eq!(tree.to_string(), expected_contents);
+    }
+
+    #[test]
+    fn test_add_to_back() {
+        test_position_add(
+            AddPosition::Back,
+            r#"{ pkgs }: {
+  deps = [
+      pkgs.cowsay
+    pkgs.ncdu # disk usage
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+      pkgs.cowsay
+    pkgs.ncdu # disk usage
+    pkgs.test
+  ];
+}"#,
+        );
+
+        // a comment closing the list stays next to the bracket
+        test_position_add(
+            AddPosition::Back,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    # TODO add more
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.test
+    # TODO add more
+  ];
+}"#,
+        );
+
+        // empty lists end up the same as adding to the front
+        for position in [AddPosition::Front, AddPosition::Back] {
+            test_position_add(
+                position,
+                "{ pkgs }: {\n  deps = [];\n}",
+                "{ pkgs }: {\n  deps = [\n    pkgs.test\n  ];\n}",
+            );
+            test_position_add(
+                position,
+                "{ pkgs }: {\n  deps = [\n  ];\n}",
+                "{ pkgs }: {\n  deps = [\n    pkgs.test\n  ];\n}",
+            );
+        }
+    }
+
+    #[test]
+    fn test_add_to_front_with_comment() {
+        let tree = rnix::Root::parse("{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}")
+            .syntax()
+            .clone_for_update();
+        let options = AddOptions {
+            position: Some(AddPosition::Front),
+            comment: Some("for testing".to_string()),
+            ..Default::default()
+        };
+
+        let res = add_dep_with(
+            verify_get(&tree, DepType::Regular).unwrap(),
+            Some("pkgs.test".to_string()),
+            &options,
+        );
+        assert!(res.is_ok());
+
+        assert_eq!(
+            tree.to_string(),
+            "{ pkgs }: {\n  deps = [\n    # for testing\n    pkgs.test\n    pkgs.cowsay\n  ];\n}"
+        );
+    }
+
+    #[test]
+    fn test_add_to_one_line_list() {
+        let contents = "{ pkgs }: {\n  deps = [ pkgs.cowsay pkgs.zlib ];\n}";
+        test_position_add(
+            AddPosition::Front,
+            contents,
+            "{ pkgs }: {\n  deps = [ pkgs.test pkgs.cowsay pkgs.zlib ];\n}",
+        );
+        test_position_add(
+            AddPosition::Back,
+            contents,
+            "{ pkgs }: {\n  deps = [ pkgs.cowsay pkgs.zlib pkgs.test ];\n}",
+        );
+        test_position_add(
+            AddPosition::Sorted,
+            contents,
+            "{ pkgs }: {\n  deps = [ pkgs.cowsay pkgs.test pkgs.zlib ];\n}",
+        );
+        test_position_add(
+            AddPosition::Front,
+            "{ pkgs }: {\n  deps = [ ];\n}",
+            "{ pkgs }: {\n  deps = [ pkgs.test ];\n}",
+        );
+    }
+
+    #[test]
+    fn test_add_sorted_below_leading_comment() {
+        test_position_add(
+            AddPosition::Sorted,
+            "{ pkgs }: {\n  deps = [\n    # tools\n    pkgs.xorg.libX11\n    pkgs.zlib\n  ];\n}",
+            "{ pkgs }: {\n  deps = [\n    # tools\n    pkgs.test\n    pkgs.xorg.libX11\n    pkgs.zlib\n  ];\n}",
+        );
+    }
+
+    #[test]
+    fn test_add_with_comment_keeps_front_default() {
+        let tree = rnix::Root::parse("{ pkgs }: {\n  deps = [\n    pkgs.b\n  ];\n}")
+            .syntax()
+            .clone_for_update();
+        let options = AddOptions {
+            comment: Some("why".to_string()),
+            ..Default::default()
+        };
+
+        let res = add_dep_with(
+            verify_get(&tree, DepType::Regular).unwrap(),
+            Some("pkgs.a".to_string()),
+            &options,
+        );
+        assert!(res.is_ok());
+
+        assert_eq!(
+            tree.to_string(),
+            "{ pkgs }: {\n  deps = [\n    # why\n    pkgs.a\n    pkgs.b\n  ];\n}"
+        );
+    }
 }
@@ -0,0 +1,184 @@
+use rnix::{SyntaxKind, SyntaxNode};
+
+use crate::error::{NixEditorError, Result};
+use crate::verify_getter::{
+    find_key_value_with_key, get_nth_child, insert_key_value, parse_key_value, remove_key_value,
+    same_dep, verify_get_attr_set_with, VerifyOptions,
+};
+
+// Deps can also be written as an attr set instead of a list, e.g.
+// `deps = { ncdu = pkgs.ncdu; };`. Each binding's value is a dep, and deps are
+// added and removed by the name they're bound to.
+
+// The attr set deps is bound to, if the file's deps are written that way.
+// Never edits the tree, anything unexpected is left for verify_get to report.
+pub fn find_deps_attr_set(root: &SyntaxNode, options: &VerifyOptions) -> Option<SyntaxNode> {
+    let options = VerifyOptions {
+        insert_missing: false,
+        ..options.clone()
+    };
+    let attr_set = verify_get_attr_set_with(root, &options).ok()?;
+    let deps = find_key_value_with_key(&attr_set, "deps")?;
+
+    get_nth_child(&deps.node, 1).filter(|value| value.kind() == SyntaxKind::NODE_ATTR_SET)
+}
+
+// The value of each binding, in file order
+pub fn get_attr_set_deps(deps_attr_set: &SyntaxNode) -> Vec<String> {
+    bindings(deps_attr_set)
+        .filter_map(|binding| get_nth_child(&binding, 1))
+        .map(|value| value.to_string())
+        .collect()
+}
+
+// The binding for dep, matched by either its name or its value
+pub fn find_attr_set_dep(deps_attr_set: &SyntaxNode, dep: &str) -> Option<SyntaxNode> {
+    bindings(deps_attr_set).find(|binding| {
+        let name = get_nth_child(binding, 0).map(|name| name.to_string());
+        let value = get_nth_child(binding, 1).map(|value| value.to_string());
+        name.as_deref() == Some(dep) || value.as_deref() == Some(dep)
+    })
+}
+
+// Binds dep to the last part of its path, so pkgs.ncdu is added as `ncdu = pkgs.ncdu;`.
+// Does nothing if dep is already bound, and fails if its name is bound to
+// something else.
+pub fn add_attr_set_dep(deps_attr_set: &SyntaxNode, dep_opt: Option<String>) -> Result<()> {
+    let dep = dep_opt.ok_or_else(|| NixEditorError::InvalidOp("no dependency".to_string()))?;
+
+    let invalid =
+        || NixEditorError::InvalidOp(format!("could not add {} to the deps attr set", dep));
+    let name = binding_name(&dep).ok_or_else(invalid)?;
+
+    let already_bound = bindings(deps_attr_set)
+        .filter_map(|binding| get_nth_child(&binding, 1))
+        .any(|value| same_dep(&value.to_string(), &dep));
+    if already_bound {
+        return Ok(());
+    }
+
+    if let Some(binding) = find_key_value_with_key(deps_attr_set, name) {
+        return Err(NixEditorError::InvalidOp(format!(
+            "could not add {} to the deps attr set, {} is already bound: {}",
+            dep,
+            name,
+            binding.node.to_string().trim()
+        )));
+    }
+
+    let binding = parse_key_value(name, &dep).ok_or_else(invalid)?;
+    insert_key_value(deps_attr_set, binding);
+
+    Ok(())
+}
+
+// Removes the binding for dep, returning whether there was one
+pub fn remove_attr_set_dep(deps_attr_set: &SyntaxNode, dep_opt: Option<String>) -> Result<bool> {
+    let dep =
+        dep_opt.ok_or_else(|| NixEditorError::InvalidOp("expected dep to remove".to_string()))?;
+
+    match find_attr_set_dep(deps_attr_set, &dep) {
+        Some(binding) => {
+            remove_key_value(deps_attr_set, &binding);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn bindings(deps_attr_set: &SyntaxNode) -> impl Iterator<Item = SyntaxNode> {
+    deps_attr_set
+        .children()
+        .filter(|child| child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+}
+
+// The last part of dep's path if it can be used as a name, e.g. pip for pkgs.python3Packages.pip
+fn binding_name(dep: &str) -> Option<&str> {
+    let name = dep.rsplit('.').next()?;
+    let starts_ok = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    let rest_ok = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'');
+
+    (starts_ok && rest_ok).then_some(name)
+}
+
+#[cfg(test)]
+mod attr_set_deps_tests {
+    use super::*;
+
+    const ATTR_SET_REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = {
+    cowsay = pkgs.cowsay;
+    pip = pkgs.python3Packages.pip;
+  };
+}"#;
+
+    fn deps_attr_set(contents: &str) -> (SyntaxNode, SyntaxNode) {
+        let tree = rnix::Root::parse(contents).syntax().clone_for_update();
+        let deps = find_deps_attr_set(&tree, &VerifyOptions::default()).unwrap();
+        (tree, deps)
+    }
+
+    #[test]
+    fn test_find_deps_attr_set() {
+        let tree = rnix::Root::parse("{ pkgs }: {\n  deps = [];\n}")
+            .syntax()
+            .clone_for_update();
+        assert!(find_deps_attr_set(&tree, &VerifyOptions::default()).is_none());
+
+        let (_, deps) = deps_attr_set(ATTR_SET_REPLIT_NIX);
+        assert_eq!(
+            get_attr_set_deps(&deps),
+            vec!["pkgs.cowsay", "pkgs.python3Packages.pip"]
+        );
+    }
+
+    #[test]
+    fn test_add_attr_set_dep() {
+        let (tree, deps) = deps_attr_set(ATTR_SET_REPLIT_NIX);
+        add_attr_set_dep(&deps, Some("pkgs.ncdu".to_string())).unwrap();
+        // already bound
+        add_attr_set_dep(&deps, Some("pkgs.cowsay".to_string())).unwrap();
+
+        assert_eq!(
+            tree.to_string(),
+            r#"{ pkgs }: {
+  deps = {
+    cowsay = pkgs.cowsay;
+    pip = pkgs.python3Packages.pip;
+    ncdu = pkgs.ncdu;
+  };
+}"#
+        );
+
+        let res = add_attr_set_dep(&deps, Some("\"${pkgs.hello}/bin\"".to_string()));
+        assert!(matches!(res, Err(NixEditorError::InvalidOp(_))));
+    }
+
+    #[test]
+    fn test_add_attr_set_dep_name_taken() {
+        let (tree, deps) = deps_attr_set(ATTR_SET_REPLIT_NIX);
+        let res = add_attr_set_dep(&deps, Some("pkgs.pip".to_string()));
+        match res {
+            Err(NixEditorError::InvalidOp(message)) => {
+                assert!(message.contains("pip = pkgs.python3Packages.pip;"))
+            }
+            res => panic!("expected the binding to conflict, got {:?}", res),
+        }
+        assert_eq!(tree.to_string(), ATTR_SET_REPLIT_NIX);
+    }
+
+    #[test]
+    fn test_remove_attr_set_dep() {
+        let (tree, deps) = deps_attr_set(ATTR_SET_REPLIT_NIX);
+        assert!(remove_attr_set_dep(&deps, Some("cowsay".to_string())).unwrap());
+        assert!(remove_attr_set_dep(&deps, Some("pkgs.python3Packages.pip".to_string())).unwrap());
+        assert!(!remove_attr_set_dep(&deps, Some("ncdu".to_string())).unwrap());
+
+        assert_eq!(tree.to_string(), "{ pkgs }: {\n  deps = {\n  };\n}");
+    }
+}
@@ -0,0 +1,120 @@
+use rnix::SyntaxNode;
+
+use crate::error::Result;
+use crate::verify_getter::SyntaxNodeAndWhitespace;
+
+pub fn clear_deps(deps_list: SyntaxNodeAndWhitespace) -> Result<SyntaxNode> {
+    let deps_list = deps_list.node;
+
+    if deps_list.children().count() == 0 {
+        // list is already empty, leave its formatting alone
+        return Ok(deps_list);
+    }
+
+    // drop everything between the brackets (deps, comments and whitespace)
+    // so the list collapses down to `[]`
+    let count = deps_list.children_with_tokens().count();
+    deps_list.splice_children(1..count - 1, vec![]);
+
+    Ok(deps_list)
+}
+
+#[cfg(test)]
+mod clear_tests {
+    use super::*;
+    use crate::verify_getter::verify_get;
+    use crate::DepType;
+
+    fn test_clear(dep_type: DepType, initial_contents: &str, expected_contents: &str) {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+
+        let deps_list_res = verify_get(&tree, dep_type);
+        assert!(deps_list_res.is_ok());
+
+        let deps_list = deps_list_res.unwrap();
+
+        let new_deps_list = clear_deps(deps_list);
+        assert!(new_deps_list.is_ok());
+
+        assert_eq!(tree.to_string(), expected_contents.to_string());
+    }
+
+    const PYTHON_REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+    pkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+    ];
+    LANG = "en_US.UTF-8";
+  };
+}"#;
+
+    #[test]
+    fn test_regular_clear() {
+        test_clear(
+            DepType::Regular,
+            PYTHON_REPLIT_NIX,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+    ];
+    LANG = "en_US.UTF-8";
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_python_clear() {
+        test_clear(
+            DepType::Python,
+            PYTHON_REPLIT_NIX,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+    pkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [];
+    LANG = "en_US.UTF-8";
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_with_pkgs_clear() {
+        test_clear(
+            DepType::Regular,
+            r#"{ pkgs }: {
+  deps = with pkgs; [
+    ncdu
+    cowsay
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = with pkgs; [];
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_empty_clear_is_noop() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+  ];
+}"#;
+        test_clear(DepType::Regular, contents, contents);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+
+use rnix::SyntaxNode;
+
+use crate::error::Result;
+use crate::remover::remove_entry;
+use crate::verify_getter::{dep_key, normalize_dep, SyntaxNodeAndWhitespace};
+
+// Removes every dep that already appeared earlier in the list, keeping the
+// first occurrence where it is. Returns how many deps were removed.
+pub fn dedup_deps(deps_list: SyntaxNodeAndWhitespace, arg_name: &str) -> Result<usize> {
+    let deps_list = deps_list.node;
+
+    let mut seen = HashSet::new();
+    let duplicates: Vec<SyntaxNode> = deps_list
+        .children()
+        .filter(|dep| {
+            !seen.insert(dep_key(&normalize_dep(
+                &deps_list,
+                &dep.to_string(),
+                arg_name,
+            )))
+        })
+        .collect();
+
+    // back to front so removing one doesn't shift the indices of the others
+    for dep in duplicates.iter().rev() {
+        remove_entry(&deps_list, dep);
+    }
+
+    Ok(duplicates.len())
+}
+
+#[cfg(test)]
+mod dedup_tests {
+    use super::*;
+    use crate::verify_getter::verify_get;
+    use crate::DepType;
+
+    fn test_dedup(
+        dep_type: DepType,
+        initial_contents: &str,
+        expected_contents: &str,
+        expected_removed: usize,
+    ) {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+
+        let deps_list_res = verify_get(&tree, dep_type);
+        assert!(deps_list_res.is_ok());
+
+        let removed = dedup_deps(deps_list_res.unwrap(), "pkgs");
+        assert_eq!(removed.unwrap(), expected_removed);
+
+        assert_eq!(tree.to_string(), expected_contents.to_string());
+    }
+
+    #[test]
+    fn test_regular_dedup() {
+        test_dedup(
+            DepType::Regular,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+    pkgs.cowsay # added again
+    pkgs.ncdu
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+  ];
+}"#,
+            2,
+        );
+    }
+
+    #[test]
+    fn test_python_dedup() {
+        test_dedup(
+            DepType::Python,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+      pkgs.glib
+      pkgs.zlib
+    ];
+  };
+}"#,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+      pkgs.glib
+    ];
+  };
+}"#,
+            1,
+        );
+    }
+
+    #[test]
+    fn test_with_pkgs_one_line_dedup() {
+        test_dedup(
+            DepType::Regular,
+            r#"{ pkgs }: { deps = with pkgs; [ ncdu cowsay pkgs.ncdu ]; }"#,
+            r#"{ pkgs }: { deps = with pkgs; [ ncdu cowsay ]; }"#,
+            1,
+        );
+    }
+
+    #[test]
+    fn test_dedup_without_duplicates_is_noop() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+  ];
+}"#;
+        test_dedup(DepType::Regular, contents, contents, 0);
+    }
+
+    #[test]
+    fn test_dedup_overridden_deps() {
+        test_dedup(
+            DepType::Regular,
+            r#"{ pkgs }: {
+  deps = [
+    (pkgs.hello.override { stdenv = pkgs.clangStdenv; })
+    (pkgs.hello.override {stdenv = pkgs.clangStdenv;})
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    (pkgs.hello.override { stdenv = pkgs.clangStdenv; })
+  ];
+}"#,
+            1,
+        );
+    }
+}
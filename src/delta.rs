@@ -0,0 +1,171 @@
+use serde::Serialize;
+
+// A single edit to the file's text: delete `delete` bytes starting at byte
+// `offset`, then insert `insert` there
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TextDelta {
+    pub offset: usize,
+    pub delete: usize,
+    pub insert: String,
+    // the first and last line of the new text that the edit touches, counted
+    // from 1, for callers that only re-render what changed
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+// The smallest single edit that turns old into new, or None if they're the same.
+// Everything before the first difference and after the last one is left out.
+pub fn text_delta(old: &str, new: &str) -> Option<TextDelta> {
+    if old == new {
+        return None;
+    }
+
+    let mut prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // don't split a multibyte character
+    while !old.is_char_boundary(prefix) || !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+
+    let mut suffix = old[prefix..]
+        .bytes()
+        .rev()
+        .zip(new[prefix..].bytes().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
+
+    let mut delta = TextDelta {
+        offset: prefix,
+        delete: old.len() - prefix - suffix,
+        insert: new[prefix..new.len() - suffix].to_string(),
+        start_line: 0,
+        end_line: 0,
+    };
+    if delta.delete == 0 || delta.insert.is_empty() {
+        align_left(old, &mut delta);
+    }
+
+    // everything before the offset is the same in old and new
+    delta.start_line = new[..delta.offset].matches('\n').count() + 1;
+    delta.end_line = delta.start_line + delta.insert.matches('\n').count();
+
+    Some(delta)
+}
+
+// A pure insert or delete can often be made at several offsets with the same
+// result (e.g. `ncdu\n    pkgs.` vs `pkgs.ncdu\n    `), so move it to the
+// earliest one, which tends to line up with whole lines
+fn align_left(old: &str, delta: &mut TextDelta) {
+    let mut changed = match delta.delete {
+        0 => delta.insert.clone(),
+        delete => old[delta.offset..delta.offset + delete].to_string(),
+    };
+
+    while let (Some(before), Some(last)) =
+        (old[..delta.offset].chars().last(), changed.chars().last())
+    {
+        if before != last {
+            break;
+        }
+        delta.offset -= before.len_utf8();
+        changed = format!("{}{}", before, &changed[..changed.len() - last.len_utf8()]);
+    }
+
+    if delta.delete == 0 {
+        delta.insert = changed;
+    }
+}
+
+#[cfg(test)]
+mod delta_tests {
+    use super::*;
+    use crate::{apply_op, DepType, OpKind};
+
+    fn apply_delta(contents: &str, delta: &TextDelta) -> String {
+        let mut contents = contents.to_string();
+        contents.replace_range(delta.offset..delta.offset + delta.delete, &delta.insert);
+        contents
+    }
+
+    const REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+}
+"#;
+
+    #[test]
+    fn test_delta_add() {
+        let new_contents = apply_op(
+            REPLIT_NIX,
+            OpKind::Add,
+            Some("pkgs.ncdu".to_string()),
+            DepType::Regular,
+        )
+        .unwrap();
+
+        // the new dep's line, indented like the rest, appended to the bracket's line
+        let delta = text_delta(REPLIT_NIX, &new_contents).unwrap();
+        assert_eq!(
+            delta,
+            TextDelta {
+                offset: 22,
+                delete: 0,
+                insert: "\n    pkgs.ncdu".to_string(),
+                start_line: 2,
+                end_line: 3,
+            }
+        );
+        assert_eq!(apply_delta(REPLIT_NIX, &delta), new_contents);
+    }
+
+    #[test]
+    fn test_delta_remove() {
+        let new_contents = apply_op(
+            REPLIT_NIX,
+            OpKind::Remove,
+            Some("pkgs.cowsay".to_string()),
+            DepType::Regular,
+        )
+        .unwrap();
+
+        let delta = text_delta(REPLIT_NIX, &new_contents).unwrap();
+        assert_eq!(
+            delta,
+            TextDelta {
+                offset: 22,
+                delete: 16,
+                insert: "".to_string(),
+                start_line: 2,
+                end_line: 2,
+            }
+        );
+        assert_eq!(apply_delta(REPLIT_NIX, &delta), new_contents);
+    }
+
+    #[test]
+    fn test_delta_unchanged() {
+        assert_eq!(text_delta(REPLIT_NIX, REPLIT_NIX), None);
+    }
+
+    #[test]
+    fn test_delta_multibyte() {
+        let delta = text_delta("# é\n", "# è\n").unwrap();
+        assert_eq!(
+            delta,
+            TextDelta {
+                offset: 2,
+                delete: 2,
+                insert: "è".to_string(),
+                start_line: 1,
+                end_line: 1,
+            }
+        );
+    }
+}
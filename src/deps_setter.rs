@@ -0,0 +1,112 @@
+use rnix::SyntaxNode;
+
+use crate::adder::{add_dep_with, existing_entry_indents, parse_elements, AddOptions};
+use crate::clearer::clear_deps;
+use crate::error::Result;
+use crate::verify_getter::{line_indent, SyntaxNodeAndWhitespace};
+use crate::AddPosition;
+
+// Replaces everything in the list with deps, in sorted order. Comments in the
+// list go too. The deps are indented like the ones they replace, and a list
+// written on one line stays on one line.
+pub fn set_deps(
+    deps_list: SyntaxNodeAndWhitespace,
+    deps: Vec<String>,
+    arg_name: &str,
+) -> Result<SyntaxNode> {
+    let node = deps_list.node.clone();
+    let one_line = !node.to_string().contains('\n') && node.children().count() > 0;
+
+    // how far past the list's line the old deps were indented, if it was with spaces
+    let base_indent = line_indent(&node);
+    let indent = existing_entry_indents(&node)
+        .next()
+        .and_then(|entry_indent| {
+            entry_indent
+                .strip_prefix(&base_indent)
+                .map(|indent| indent.to_string())
+        })
+        .filter(|indent| !indent.is_empty() && indent.chars().all(|c| c == ' '))
+        .map(|indent| indent.len());
+
+    clear_deps(deps_list.clone())?;
+    if one_line && !deps.is_empty() {
+        // adding to `[ ]` keeps the list on one line
+        node.splice_children(1..1, parse_elements(" "));
+    }
+
+    let options = AddOptions {
+        position: Some(AddPosition::Sorted),
+        indent,
+        arg_name: Some(arg_name.to_string()),
+        ..Default::default()
+    };
+    for dep in deps {
+        add_dep_with(deps_list.clone(), Some(dep), &options)?;
+    }
+
+    Ok(node)
+}
+
+#[cfg(test)]
+mod set_deps_tests {
+    use super::*;
+    use crate::verify_getter::verify_get;
+    use crate::DepType;
+
+    fn set_deps_in(deps: &[&str], initial_contents: &str) -> String {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let deps = deps.iter().map(|dep| dep.to_string()).collect();
+        assert!(set_deps(deps_list, deps, "pkgs").is_ok());
+
+        tree.to_string()
+    }
+
+    #[test]
+    fn test_set_deps() {
+        let contents = set_deps_in(
+            &["pkgs.ncdu", "pkgs.cowsay", "pkgs.ncdu"],
+            r#"{ pkgs }: {
+    deps = [
+        # tools
+        pkgs.htop
+        pkgs.cowsay
+    ];
+}"#,
+        );
+        assert_eq!(
+            contents,
+            r#"{ pkgs }: {
+    deps = [
+        pkgs.cowsay
+        pkgs.ncdu
+    ];
+}"#
+        );
+    }
+
+    #[test]
+    fn test_set_deps_one_line() {
+        let contents = set_deps_in(
+            &["pkgs.ncdu", "pkgs.cowsay"],
+            "{ pkgs }: {\n  deps = [ pkgs.htop ];\n}",
+        );
+        assert_eq!(
+            contents,
+            "{ pkgs }: {\n  deps = [ pkgs.cowsay pkgs.ncdu ];\n}"
+        );
+    }
+
+    #[test]
+    fn test_set_deps_empty() {
+        let contents = set_deps_in(&[], "{ pkgs }: {\n  deps = [ pkgs.htop ];\n}");
+        assert_eq!(contents, "{ pkgs }: {\n  deps = [];\n}");
+
+        let contents = set_deps_in(&["pkgs.ncdu"], "{ pkgs }: {\n  deps = [];\n}");
+        assert_eq!(contents, "{ pkgs }: {\n  deps = [\n    pkgs.ncdu\n  ];\n}");
+    }
+}
@@ -0,0 +1,270 @@
+// How many unchanged lines to show around each change
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineChange {
+    Same,
+    Removed,
+    Added,
+}
+
+// A unified diff (like `diff -u`) between old and new, with both sides labeled
+// name. Empty if they're the same.
+pub fn unified_diff(old: &str, new: &str, name: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let changes = diff_lines(&old_lines, &new_lines);
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", name, name);
+    for hunk in hunks(&changes) {
+        // where the hunk starts in each file, counted in lines
+        let old_start = changes[..hunk.start]
+            .iter()
+            .filter(|change| **change != LineChange::Added)
+            .count();
+        let new_start = changes[..hunk.start]
+            .iter()
+            .filter(|change| **change != LineChange::Removed)
+            .count();
+        let hunk_changes = &changes[hunk.clone()];
+        let old_count = hunk_changes
+            .iter()
+            .filter(|change| **change != LineChange::Added)
+            .count();
+        let new_count = hunk_changes
+            .iter()
+            .filter(|change| **change != LineChange::Removed)
+            .count();
+
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        ));
+
+        let (mut old_index, mut new_index) = (old_start, new_start);
+        for change in hunk_changes {
+            let (prefix, line) = match change {
+                LineChange::Same => {
+                    old_index += 1;
+                    new_index += 1;
+                    (' ', old_lines[old_index - 1])
+                }
+                LineChange::Removed => {
+                    old_index += 1;
+                    ('-', old_lines[old_index - 1])
+                }
+                LineChange::Added => {
+                    new_index += 1;
+                    ('+', new_lines[new_index - 1])
+                }
+            };
+
+            out.push(prefix);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    out
+}
+
+// The shortest list of line changes that turns old into new. Edits usually
+// touch a few lines of a long file, so the lines they start and end with are
+// left out before comparing the rest
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<LineChange> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old_line, new_line)| old_line == new_line)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old_line, new_line)| old_line == new_line)
+        .count();
+
+    let mut changes = vec![LineChange::Same; prefix];
+    changes.extend(changed_lines(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    ));
+    changes.extend(vec![LineChange::Same; suffix]);
+    changes
+}
+
+// The line changes between old and new, from the longest common subsequence
+// of their lines
+fn changed_lines(old: &[&str], new: &[&str]) -> Vec<LineChange> {
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let mut changes = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.push(LineChange::Same);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            changes.push(LineChange::Added);
+            j += 1;
+        } else {
+            changes.push(LineChange::Removed);
+            i += 1;
+        }
+    }
+
+    // show removed lines before the lines that replace them, like diff does
+    for k in 1..changes.len() {
+        let mut k = k;
+        while k > 0 && changes[k] == LineChange::Removed && changes[k - 1] == LineChange::Added {
+            changes.swap(k, k - 1);
+            k -= 1;
+        }
+    }
+
+    changes
+}
+
+// Groups the changes into ranges of changes, each with some unchanged lines
+// around it. Changes close enough to share context end up in the same range.
+fn hunks(changes: &[LineChange]) -> Vec<std::ops::Range<usize>> {
+    let mut hunks: Vec<std::ops::Range<usize>> = vec![];
+    for (index, change) in changes.iter().enumerate() {
+        if *change == LineChange::Same {
+            continue;
+        }
+
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + 1 + CONTEXT_LINES).min(changes.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+
+    hunks
+}
+
+// A hunk header's range: the first line and how many lines, with the count
+// left out when it's 1 and the line before the hunk used when it's empty
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+
+    const REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+}
+"#;
+
+    #[test]
+    fn test_diff_added_line() {
+        let new = "{ pkgs }: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n";
+        assert_eq!(
+            unified_diff(REPLIT_NIX, new, "replit.nix"),
+            r#"--- a/replit.nix
++++ b/replit.nix
+@@ -1,5 +1,6 @@
+ { pkgs }: {
+   deps = [
++    pkgs.ncdu
+     pkgs.cowsay
+   ];
+ }
+"#
+        );
+    }
+
+    #[test]
+    fn test_diff_changed_line() {
+        let new = "{ pkgs }: {\n  deps = [\n    pkgs.ncdu\n  ];\n}\n";
+        assert_eq!(
+            unified_diff(REPLIT_NIX, new, "replit.nix"),
+            r#"--- a/replit.nix
++++ b/replit.nix
+@@ -1,5 +1,5 @@
+ { pkgs }: {
+   deps = [
+-    pkgs.cowsay
++    pkgs.ncdu
+   ];
+ }
+"#
+        );
+    }
+
+    #[test]
+    fn test_diff_separate_hunks() {
+        let old: String = (1..=12).map(|n| format!("{}\n", n)).collect();
+        let new = old.replacen("2\n", "two\n", 1).replace("\n11\n", "\n");
+        assert_eq!(
+            unified_diff(&old, &new, "numbers"),
+            r#"--- a/numbers
++++ b/numbers
+@@ -1,5 +1,5 @@
+ 1
+-2
++two
+ 3
+ 4
+ 5
+@@ -8,5 +8,4 @@
+ 8
+ 9
+ 10
+-11
+ 12
+"#
+        );
+    }
+
+    #[test]
+    fn test_diff_no_newline_at_end() {
+        assert_eq!(
+            unified_diff("a\n", "a\nb", "file"),
+            "--- a/file\n+++ b/file\n@@ -1 +1,2 @@\n a\n+b\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        assert_eq!(unified_diff(REPLIT_NIX, REPLIT_NIX, "replit.nix"), "");
+    }
+
+    #[test]
+    fn test_diff_long_file() {
+        // too long to compare every line against every other one
+        let old: String = (0..100_000).map(|n| format!("{}\n", n)).collect();
+        let new = old.replacen("\n50000\n", "\nfifty thousand\n", 1);
+        assert_eq!(
+            unified_diff(&old, &new, "file"),
+            "--- a/file\n+++ b/file\n@@ -49998,7 +49998,7 @@\n 49997\n 49998\n 49999\n-50000\n+fifty thousand\n 50001\n 50002\n 50003\n"
+        );
+    }
+}
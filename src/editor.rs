@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use rnix::SyntaxNode;
+
+use crate::error::{NixEditorError, Result};
+use crate::remover::{find_dep, is_dep_pattern, remove_entry};
+use crate::verify_getter::{
+    is_concatenated, verify_get_with, SyntaxNodeAndWhitespace, VerifyOptions,
+};
+use crate::{
+    add_dep_with, add_options, check_policy, get_deps, list_deps_of, package_names, parse,
+    rename_dep, uses_crlf, verify_options, AddOptions, DepType, Op, OpKind, OpOutput,
+};
+
+// Holds a parsed replit.nix so many ops can be applied to the same tree
+// without reparsing the file for each one
+pub struct NixEditor {
+    // None until an op needs the tree, or after an op that had to go through
+    // the contents instead of the tree
+    root: Option<SyntaxNode>,
+    contents: String,
+    options: VerifyOptions,
+    crlf: bool,
+
+    // every op edits inside the list, so a list stays valid once it's been found
+    deps_lists: HashMap<DepType, SyntaxNodeAndWhitespace>,
+}
+
+impl NixEditor {
+    pub fn new(contents: &str) -> Result<Self> {
+        Self::with_options(contents, VerifyOptions::default())
+    }
+
+    pub fn with_options(contents: &str, options: VerifyOptions) -> Result<Self> {
+        let mut editor = Self::unparsed(contents, options);
+        editor.tree()?;
+        Ok(editor)
+    }
+
+    // Like with_options, but contents are only parsed once an op needs them, so
+    // ops that work on contents that don't parse (e.g. info) still can
+    pub fn unparsed(contents: &str, options: VerifyOptions) -> Self {
+        NixEditor {
+            root: None,
+            contents: contents.to_string(),
+            options,
+            crlf: uses_crlf(contents),
+            deps_lists: HashMap::new(),
+        }
+    }
+
+    // Replaces what's being edited, e.g. with the contents after a formatter ran
+    // on them. They're parsed once an op needs them
+    pub fn set_contents(&mut self, contents: &str) {
+        *self = Self::unparsed(contents, self.options.clone());
+    }
+
+    // Runs op the way run_op would on the contents. add, remove, rename and get
+    // are done on the tree when they can be. Anything else (or one of those with
+    // options only run_op handles) runs on the contents, which are then parsed
+    // again only once an op needs the tree.
+    pub fn run_op(&mut self, op: Op, default_dep_type: DepType) -> Result<OpOutput> {
+        if let Some(output) = self.run_op_on_tree(&op, default_dep_type)? {
+            return Ok(output);
+        }
+
+        let contents = self.to_string();
+        let output = crate::run_op(&contents, op, default_dep_type)?;
+        if let OpOutput::Contents(new_contents) | OpOutput::ContentsWithData(new_contents, _) =
+            &output
+        {
+            if *new_contents != contents {
+                self.set_contents(new_contents);
+            }
+        }
+        Ok(output)
+    }
+
+    // What op outputs when it can be done on the tree as it is, None when it
+    // has to go through run_op
+    fn run_op_on_tree(&mut self, op: &Op, default_dep_type: DepType) -> Result<Option<OpOutput>> {
+        let on_tree = matches!(
+            op.op,
+            OpKind::Add | OpKind::Remove | OpKind::Rename | OpKind::Get
+        ) && op.always_sort != Some(true)
+            && op.target_path.is_none()
+            && op.env_key.is_none();
+        if !on_tree {
+            return Ok(None);
+        }
+
+        // contents that don't parse fail the same way through run_op
+        let root = match self.tree() {
+            Ok(root) => root,
+            Err(_) => return Ok(None),
+        };
+
+        // a missing list has to be filled in and deps written as an attr set
+        // aren't a list, run_op takes care of both
+        let options = VerifyOptions {
+            insert_missing: false,
+            ..verify_options(op)
+        };
+        let dep_type = op.dep_type.unwrap_or(default_dep_type);
+        let deps_list = match verify_get_with(&root, dep_type, &options) {
+            Ok(deps_list) => deps_list,
+            Err(_) => return Ok(None),
+        };
+
+        check_policy(op, &options)?;
+        let arg_name = &options.arg_name;
+        match op.op {
+            OpKind::Add => {
+                add_dep_with(deps_list, op.dep.clone(), &add_options(op, &options))?;
+            }
+            OpKind::Remove => {
+                let by_name = op.index.is_none()
+                    && op.all != Some(true)
+                    && op.prune_empty != Some(true)
+                    && !op.dep.as_deref().is_some_and(is_dep_pattern);
+                // short names and deps that aren't there are left to run_op too
+                let dep = op.dep.as_deref().filter(|_| by_name);
+                match dep.and_then(|dep| find_dep(&deps_list.node, dep, arg_name)) {
+                    Some(dep) => remove_entry(&deps_list.node, &dep),
+                    None => return Ok(None),
+                }
+            }
+            OpKind::Rename => {
+                rename_dep(deps_list.node, op.from.clone(), op.to.clone(), arg_name)?;
+            }
+            _ => {
+                if op.with_range == Some(true) || is_concatenated(&deps_list.node) {
+                    return Ok(None);
+                }
+                let sorted = op.sorted.unwrap_or(false);
+                let unquote = op.unquote.unwrap_or(false);
+                let deps = list_deps_of(deps_list.node, arg_name, unquote, sorted)?;
+                let deps = match op.names_only.unwrap_or(false) {
+                    true => package_names(deps, arg_name, sorted),
+                    false => deps,
+                };
+                return Ok(Some(OpOutput::Deps(deps)));
+            }
+        }
+
+        Ok(Some(OpOutput::Contents(self.to_string())))
+    }
+
+    pub fn add(&mut self, dep: &str, dep_type: DepType) -> Result<()> {
+        let deps_list = self.deps_list(dep_type)?;
+        let options = AddOptions {
+            arg_name: Some(self.options.arg_name.clone()),
+            ..Default::default()
+        };
+        add_dep_with(deps_list, Some(dep.to_string()), &options)?;
+        Ok(())
+    }
+
+    // fails with DepNotFound when the list doesn't have dep, like the remove op
+    pub fn remove(&mut self, dep: &str, dep_type: DepType) -> Result<()> {
+        let deps_list = self.deps_list(dep_type)?.node;
+        let dep_node = find_dep(&deps_list, dep, &self.options.arg_name)
+            .ok_or_else(|| NixEditorError::DepNotFound(dep.to_string()))?;
+        remove_entry(&deps_list, &dep_node);
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str, dep_type: DepType) -> Result<()> {
+        let deps_list = self.deps_list(dep_type)?;
+        rename_dep(
+            deps_list.node,
+            Some(from.to_string()),
+            Some(to.to_string()),
+            &self.options.arg_name,
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&mut self, dep_type: DepType) -> Result<Vec<String>> {
+        let deps_list = self.deps_list(dep_type)?;
+        get_deps(deps_list.node, &self.options.arg_name)
+    }
+
+    pub fn into_contents(self) -> String {
+        self.to_string()
+    }
+
+    fn tree(&mut self) -> Result<SyntaxNode> {
+        if let Some(root) = &self.root {
+            return Ok(root.clone());
+        }
+
+        let root = match self.crlf {
+            true => parse(&self.contents.replace("\r\n", "\n"))?,
+            false => parse(&self.contents)?,
+        };
+        self.root = Some(root.clone());
+        Ok(root)
+    }
+
+    fn deps_list(&mut self, dep_type: DepType) -> Result<SyntaxNodeAndWhitespace> {
+        if let Some(deps_list) = self.deps_lists.get(&dep_type) {
+            return Ok(deps_list.clone());
+        }
+
+        let deps_list = verify_get_with(&self.tree()?, dep_type, &self.options)?;
+        self.deps_lists.insert(dep_type, deps_list.clone());
+        Ok(deps_list)
+    }
+}
+
+impl fmt::Display for NixEditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let root = match &self.root {
+            Some(root) => root,
+            None => return write!(f, "{}", self.contents),
+        };
+        match self.crlf {
+            true => write!(f, "{}", root.to_string().replace('\n', "\r\n")),
+            false => write!(f, "{}", root),
+        }
+    }
+}
+
+#[cfg(test)]
+mod editor_tests {
+    use super::*;
+
+    const PYTHON_REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib # Needed for pandas / numpy
+    ];
+  };
+}
+"#;
+
+    #[test]
+    fn test_editor_many_ops() {
+        let mut editor = NixEditor::new(PYTHON_REPLIT_NIX).unwrap();
+
+        editor.add("pkgs.cowsay", DepType::Regular).unwrap();
+        editor.add("pkgs.ncdu", DepType::Regular).unwrap();
+        editor
+            .remove("pkgs.python38Full", DepType::Regular)
+            .unwrap();
+        editor
+            .rename("pkgs.ncdu", "pkgs.htop", DepType::Regular)
+            .unwrap();
+        editor.add("pkgs.glib", DepType::Python).unwrap();
+        editor.remove("pkgs.zlib", DepType::Python).unwrap();
+
+        assert_eq!(
+            editor.get(DepType::Regular).unwrap(),
+            vec!["pkgs.htop", "pkgs.cowsay"]
+        );
+        assert_eq!(editor.get(DepType::Python).unwrap(), vec!["pkgs.glib"]);
+
+        assert_eq!(
+            editor.into_contents(),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.htop
+    pkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.glib
+    ];
+  };
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_editor_matches_run_op() {
+        let mut editor = NixEditor::new(PYTHON_REPLIT_NIX).unwrap();
+        editor.remove("pkgs.zlib", DepType::Python).unwrap();
+
+        let new_contents = crate::apply_op(
+            PYTHON_REPLIT_NIX,
+            crate::OpKind::Remove,
+            Some("pkgs.zlib".to_string()),
+            DepType::Python,
+        )
+        .unwrap();
+
+        assert_eq!(editor.to_string(), new_contents);
+    }
+
+    #[test]
+    fn test_editor_remove_missing() {
+        let mut editor = NixEditor::new(PYTHON_REPLIT_NIX).unwrap();
+        let res = editor.remove("pkgs.ncdu", DepType::Regular);
+        assert!(matches!(res, Err(NixEditorError::DepNotFound(dep)) if dep == "pkgs.ncdu"));
+        assert_eq!(editor.to_string(), PYTHON_REPLIT_NIX);
+    }
+
+    #[test]
+    fn test_editor_crlf() {
+        let contents = PYTHON_REPLIT_NIX.replace('\n', "\r\n");
+
+        let mut editor = NixEditor::new(&contents).unwrap();
+        editor.add("pkgs.cowsay", DepType::Regular).unwrap();
+
+        assert_eq!(
+            editor.into_contents(),
+            "{ pkgs }: {\r\n  deps = [\r\n    pkgs.cowsay\r\n    pkgs.python38Full\r\n  ];\r\n  env = {\r\n    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [\r\n      pkgs.zlib # Needed for pandas / numpy\r\n    ];\r\n  };\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn test_editor_run_op_matches_run_op() {
+        let ops = [
+            (r#"{"op": "add", "dep": "pkgs.cowsay"}"#, DepType::Regular),
+            (
+                r#"{"op": "add", "dep": "pkgs.ncdu", "comment": "disk usage"}"#,
+                DepType::Regular,
+            ),
+            // only the reparsing path finds deps by their short name
+            (
+                r#"{"op": "remove", "dep": "python38Full"}"#,
+                DepType::Regular,
+            ),
+            (
+                r#"{"op": "rename", "from": "pkgs.cowsay", "to": "pkgs.htop"}"#,
+                DepType::Regular,
+            ),
+            (r#"{"op": "get", "names_only": true}"#, DepType::Regular),
+            (r#"{"op": "sort"}"#, DepType::Regular),
+            (r#"{"op": "remove", "dep": "pkgs.zlib"}"#, DepType::Python),
+            (
+                r#"{"op": "remove", "dep": "pkgs.missing"}"#,
+                DepType::Regular,
+            ),
+        ];
+
+        let mut editor = NixEditor::unparsed(PYTHON_REPLIT_NIX, VerifyOptions::default());
+        let mut contents = PYTHON_REPLIT_NIX.to_string();
+        for (op, dep_type) in ops {
+            let expected = crate::run_op(&contents, serde_json::from_str(op).unwrap(), dep_type);
+            let output = editor.run_op(serde_json::from_str(op).unwrap(), dep_type);
+            match (expected, output) {
+                (Ok(expected), Ok(output)) => {
+                    if let OpOutput::Contents(new_contents) = &expected {
+                        contents = new_contents.clone();
+                    }
+                    assert_eq!(output, expected);
+                }
+                (expected, output) => assert_eq!(
+                    output.map_err(|err| err.to_string()),
+                    expected.map_err(|err| err.to_string())
+                ),
+            }
+            assert_eq!(editor.to_string(), contents);
+        }
+    }
+
+    #[test]
+    fn test_editor_run_op_keeps_the_tree() {
+        let mut editor = NixEditor::unparsed(PYTHON_REPLIT_NIX, VerifyOptions::default());
+        assert!(editor.root.is_none());
+
+        let add = Op::new(OpKind::Add, Some("pkgs.cowsay".to_string()));
+        editor.run_op(add, DepType::Regular).unwrap();
+        let remove = Op::new(OpKind::Remove, Some("pkgs.zlib".to_string()));
+        editor.run_op(remove, DepType::Python).unwrap();
+        editor
+            .run_op(Op::new(OpKind::Get, None), DepType::Regular)
+            .unwrap();
+        assert!(editor.root.is_some());
+
+        // removing by short name goes through the contents, which are parsed
+        // again when needed
+        let remove = Op::new(OpKind::Remove, Some("cowsay".to_string()));
+        editor.run_op(remove, DepType::Regular).unwrap();
+        assert!(editor.root.is_none());
+    }
+}
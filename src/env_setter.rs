@@ -0,0 +1,554 @@
+use anyhow::{bail, Context};
+use rnix::{SyntaxKind, SyntaxNode};
+
+use crate::error::{NixEditorError, Result};
+use crate::verify_getter::{
+    find_key_value_with_key, find_or_insert_key_value_with_key, get_nth_child, insert_key_value,
+    parse_binding_template, parse_key_value, remove_key_value, string_dep_value,
+};
+
+pub fn set_env(
+    attr_set: &SyntaxNode,
+    key_opt: Option<String>,
+    value_opt: Option<String>,
+) -> Result<()> {
+    set_env_with(attr_set, key_opt, value_opt, None, "pkgs")
+}
+
+// Like set_env, but raw says whether value is a nix expression to write as is
+// or a string to quote. Without it, values that already look like a string
+// literal or start with arg_name. (e.g. pkgs.) are written as is and anything
+// else is quoted.
+pub fn set_env_with(
+    attr_set: &SyntaxNode,
+    key_opt: Option<String>,
+    value_opt: Option<String>,
+    raw: Option<bool>,
+    arg_name: &str,
+) -> Result<()> {
+    let key = key_opt.ok_or_else(|| NixEditorError::InvalidOp("expected env key".to_string()))?;
+    let value =
+        value_opt.ok_or_else(|| NixEditorError::InvalidOp("expected env value".to_string()))?;
+
+    let env_attr_set = find_or_insert_env(attr_set)
+        .map_err(|err| NixEditorError::VerifyFailed(err.to_string()))?;
+
+    let value_expr = match raw {
+        Some(true) => value.clone(),
+        Some(false) => quote_string(&value),
+        None => env_value(&value, arg_name),
+    };
+    let key_value = parse_key_value(&key, &value_expr).ok_or_else(|| {
+        NixEditorError::InvalidOp(format!("could not set env {} to {}", key, value))
+    })?;
+
+    match find_key_value_with_key(&env_attr_set, &key) {
+        Some(existing) => {
+            // only swap out the value so the key keeps its position and formatting
+            let old_value = get_nth_child(&existing.node, 1).ok_or_else(|| {
+                NixEditorError::VerifyFailed(format!("expected {} to have a value", key))
+            })?;
+            let new_value = get_nth_child(&key_value, 1).unwrap();
+            new_value.detach();
+
+            let index = old_value.index();
+            existing
+                .node
+                .splice_children(index..index + 1, vec![rnix::NodeOrToken::Node(new_value)]);
+        }
+        None => insert_key_value(&env_attr_set, key_value),
+    }
+
+    Ok(())
+}
+
+pub fn unset_env(attr_set: &SyntaxNode, key_opt: Option<String>) -> Result<()> {
+    let key = key_opt.ok_or_else(|| NixEditorError::InvalidOp("expected env key".to_string()))?;
+
+    let env_attr_set = match find_env(attr_set)? {
+        Some(env_attr_set) => env_attr_set,
+        // no env block means there's nothing to unset
+        None => return Ok(()),
+    };
+
+    let key_value = match find_key_value_with_key(&env_attr_set, &key) {
+        Some(key_value) => key_value.node,
+        None => return Ok(()),
+    };
+
+    remove_key_value(&env_attr_set, &key_value);
+
+    Ok(())
+}
+
+// The value key is set to in the env block, with a string's quotes and escapes
+// dropped. Anything else, like a makeLibraryPath call, comes back as written.
+// Fails with EnvNotFound if the key isn't set, an empty string is still set.
+pub fn get_env(attr_set: &SyntaxNode, key_opt: Option<String>) -> Result<String> {
+    let key = key_opt.ok_or_else(|| NixEditorError::InvalidOp("expected env key".to_string()))?;
+
+    let value = find_env(attr_set)?
+        .and_then(|env_attr_set| find_key_value_with_key(&env_attr_set, &key))
+        .and_then(|key_value| get_nth_child(&key_value.node, 1))
+        .ok_or(NixEditorError::EnvNotFound(key))?;
+
+    Ok(string_dep_value(&value).unwrap_or_else(|| value.to_string()))
+}
+
+// The keys set in the env block, in file order. Empty if there's no env block.
+pub fn get_env_keys(attr_set: &SyntaxNode) -> Result<Vec<String>> {
+    let env_attr_set = match find_env(attr_set)? {
+        Some(env_attr_set) => env_attr_set,
+        None => return Ok(vec![]),
+    };
+
+    let keys = env_attr_set
+        .children()
+        .filter(|child| child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+        .filter_map(|key_value| get_nth_child(&key_value, 0))
+        .map(|key| key.to_string())
+        .collect();
+
+    Ok(keys)
+}
+
+// Removes the key a python or node library path list is bound to once the
+// list is empty, and the env block too if that was its last key. Returns
+// whether anything was removed. A list with comments left in it is kept.
+pub fn prune_empty_lib_path(deps_list: &SyntaxNode) -> bool {
+    if !is_empty(deps_list) {
+        return false;
+    }
+
+    // the list is the argument in `KEY = pkgs.lib.makeLibraryPath [ ];`
+    let lib_path = match deps_list
+        .parent()
+        .filter(|apply| apply.kind() == SyntaxKind::NODE_APPLY)
+        .and_then(|apply| apply.parent())
+        .filter(|lib_path| lib_path.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+    {
+        Some(lib_path) => lib_path,
+        None => return false,
+    };
+    let env_attr_set = match lib_path.parent() {
+        Some(env_attr_set) => env_attr_set,
+        None => return false,
+    };
+    remove_key_value(&env_attr_set, &lib_path);
+
+    let env = env_attr_set
+        .parent()
+        .filter(|env| env.kind() == SyntaxKind::NODE_ATTRPATH_VALUE);
+    if let Some(env) = env.filter(|_| is_empty(&env_attr_set)) {
+        if let Some(attr_set) = env.parent() {
+            remove_key_value(&attr_set, &env);
+        }
+    }
+
+    true
+}
+
+// Whether a list or attr set has nothing in it, not even comments
+fn is_empty(node: &SyntaxNode) -> bool {
+    node.children().next().is_none()
+        && !node
+            .children_with_tokens()
+            .any(|child| child.kind() == SyntaxKind::TOKEN_COMMENT)
+}
+
+// The env block's attr set, if there is one
+fn find_env(attr_set: &SyntaxNode) -> Result<Option<SyntaxNode>> {
+    let env = match find_key_value_with_key(attr_set, "env") {
+        Some(env) => env,
+        None => return Ok(None),
+    };
+
+    get_nth_child(&env.node, 1)
+        .filter(|env_attr_set| env_attr_set.kind() == SyntaxKind::NODE_ATTR_SET)
+        .map(Some)
+        .ok_or_else(|| {
+            NixEditorError::VerifyFailed("expected env to be an attribute set".to_string())
+        })
+}
+
+fn find_or_insert_env(attr_set: &SyntaxNode) -> anyhow::Result<SyntaxNode> {
+    let env = find_or_insert_key_value_with_key(attr_set, "env", template_empty_env()?)
+        .context("expected to have env key")?
+        .node;
+
+    let env_attr_set = get_nth_child(&env, 1).context("expected to have two children")?;
+    if env_attr_set.kind() != SyntaxKind::NODE_ATTR_SET {
+        bail!("error: expected env to be an attribute set");
+    }
+
+    Ok(env_attr_set)
+}
+
+// Values that already look like nix (a string literal or something pulled out
+// of the package set) are passed through as is, anything else is treated as a
+// plain string
+fn env_value(value: &str, arg_name: &str) -> String {
+    let from_pkgs = value.starts_with(&format!("{}.", arg_name));
+    if value.starts_with('"') || value.starts_with("''") || from_pkgs {
+        return value.to_string();
+    }
+
+    quote_string(value)
+}
+
+fn quote_string(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+fn template_empty_env() -> anyhow::Result<SyntaxNode> {
+    parse_binding_template("{\n  env = {};\n}")
+}
+
+#[cfg(test)]
+mod env_tests {
+    use super::*;
+    use crate::verify_getter::{
+        verify_get, verify_get_attr_set, verify_get_attr_set_with, VerifyOptions,
+    };
+    use crate::DepType;
+
+    const PYTHON_REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+    PYTHONBIN = "${pkgs.python38Full}/bin/python3.8";
+    LANG = "en_US.UTF-8";
+  };
+}"#;
+
+    fn check_set_env(key: &str, value: &str, initial_contents: &str, expected_contents: &str) {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+
+        let attr_set = verify_get_attr_set(&tree);
+        assert!(attr_set.is_ok());
+
+        let res = set_env(
+            &attr_set.unwrap(),
+            Some(key.to_string()),
+            Some(value.to_string()),
+        );
+        assert!(res.is_ok());
+
+        assert_eq!(tree.to_string(), expected_contents.to_string());
+    }
+
+    fn check_unset_env(key: &str, initial_contents: &str, expected_contents: &str) {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+
+        let attr_set = verify_get_attr_set(&tree);
+        assert!(attr_set.is_ok());
+
+        let res = unset_env(&attr_set.unwrap(), Some(key.to_string()));
+        assert!(res.is_ok());
+
+        assert_eq!(tree.to_string(), expected_contents.to_string());
+    }
+
+    #[test]
+    fn test_set_env_new_key() {
+        check_set_env(
+            "LD_LIBRARY_PATH",
+            "pkgs.lib.makeLibraryPath [ pkgs.libuuid ]",
+            PYTHON_REPLIT_NIX,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+    PYTHONBIN = "${pkgs.python38Full}/bin/python3.8";
+    LANG = "en_US.UTF-8";
+    LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [ pkgs.libuuid ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_set_env_existing_key() {
+        check_set_env(
+            "LANG",
+            "C.UTF-8",
+            PYTHON_REPLIT_NIX,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+    PYTHONBIN = "${pkgs.python38Full}/bin/python3.8";
+    LANG = "C.UTF-8";
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_set_env_missing_env() {
+        check_set_env(
+            "LANG",
+            "en_US.UTF-8",
+            r#"{ pkgs }: {
+  deps = [];
+}"#,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    LANG = "en_US.UTF-8";
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_set_env_string_literal() {
+        check_set_env(
+            "PYTHONBIN",
+            r#""${pkgs.python310Full}/bin/python3.10""#,
+            PYTHON_REPLIT_NIX,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+    PYTHONBIN = "${pkgs.python310Full}/bin/python3.10";
+    LANG = "en_US.UTF-8";
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_unset_env() {
+        check_unset_env(
+            "PYTHONBIN",
+            PYTHON_REPLIT_NIX,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+    LANG = "en_US.UTF-8";
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_unset_missing_env_is_noop() {
+        check_unset_env(
+            "LANG",
+            "{ pkgs }: {\n  deps = [];\n}",
+            "{ pkgs }: {\n  deps = [];\n}",
+        );
+        check_unset_env("FOO", PYTHON_REPLIT_NIX, PYTHON_REPLIT_NIX);
+    }
+
+    #[test]
+    fn test_get_env_keys() {
+        let tree = rnix::Root::parse(PYTHON_REPLIT_NIX)
+            .syntax()
+            .clone_for_update();
+        let attr_set = verify_get_attr_set(&tree).unwrap();
+
+        assert_eq!(
+            get_env_keys(&attr_set).unwrap(),
+            vec!["PYTHON_LD_LIBRARY_PATH", "PYTHONBIN", "LANG"]
+        );
+        assert_eq!(tree.to_string(), PYTHON_REPLIT_NIX);
+    }
+
+    #[test]
+    fn test_get_env_keys_missing_env() {
+        let tree = rnix::Root::parse("{ pkgs }: {\n  deps = [];\n}")
+            .syntax()
+            .clone_for_update();
+        let attr_set = verify_get_attr_set(&tree).unwrap();
+
+        assert!(get_env_keys(&attr_set).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_prune_empty_lib_path() {
+        let tree = rnix::Root::parse(PYTHON_REPLIT_NIX)
+            .syntax()
+            .clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Python).unwrap();
+
+        // the list still has pkgs.zlib in it
+        assert!(!prune_empty_lib_path(&deps_list.node));
+        assert_eq!(tree.to_string(), PYTHON_REPLIT_NIX);
+
+        let contents = PYTHON_REPLIT_NIX.replace("      pkgs.zlib\n", "      # pkgs.zlib\n");
+        let tree = rnix::Root::parse(&contents).syntax().clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Python).unwrap();
+
+        // comments are worth keeping
+        assert!(!prune_empty_lib_path(&deps_list.node));
+
+        let contents = PYTHON_REPLIT_NIX.replace("      pkgs.zlib\n", "");
+        let tree = rnix::Root::parse(&contents).syntax().clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Python).unwrap();
+
+        assert!(prune_empty_lib_path(&deps_list.node));
+        assert_eq!(
+            tree.to_string(),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHONBIN = "${pkgs.python38Full}/bin/python3.8";
+    LANG = "en_US.UTF-8";
+  };
+}"#
+        );
+    }
+
+    #[test]
+    fn test_get_env() {
+        let contents = PYTHON_REPLIT_NIX.replace("\"en_US.UTF-8\"", "\"\"");
+        let tree = rnix::Root::parse(&contents).syntax().clone_for_update();
+        let attr_set = verify_get_attr_set(&tree).unwrap();
+        let get = |key: &str| get_env(&attr_set, Some(key.to_string()));
+
+        assert_eq!(
+            get("PYTHONBIN").unwrap(),
+            "${pkgs.python38Full}/bin/python3.8"
+        );
+        assert_eq!(
+            get("PYTHON_LD_LIBRARY_PATH").unwrap(),
+            "pkgs.lib.makeLibraryPath [\n      pkgs.zlib\n    ]"
+        );
+        // set to an empty string isn't the same as not set
+        assert_eq!(get("LANG").unwrap(), "");
+        assert!(matches!(
+            get("FOO"),
+            Err(NixEditorError::EnvNotFound(key)) if key == "FOO"
+        ));
+        assert_eq!(tree.to_string(), contents);
+
+        let tree = rnix::Root::parse("{ pkgs }: {\n  deps = [];\n}")
+            .syntax()
+            .clone_for_update();
+        let attr_set = verify_get_attr_set(&tree).unwrap();
+        assert!(matches!(
+            get_env(&attr_set, Some("LANG".to_string())),
+            Err(NixEditorError::EnvNotFound(_))
+        ));
+    }
+
+    // the attr set after setting VALUE to value in an empty replit.nix
+    fn set_env_raw(value: &str, raw: Option<bool>) -> Result<SyntaxNode> {
+        let tree = rnix::Root::parse("{ pkgs }: {\n  deps = [];\n}")
+            .syntax()
+            .clone_for_update();
+        let attr_set = verify_get_attr_set(&tree).unwrap();
+        let key = Some("VALUE".to_string());
+        set_env_with(&attr_set, key, Some(value.to_string()), raw, "pkgs")?;
+        Ok(attr_set)
+    }
+
+    // what VALUE ends up written as
+    fn env_written(value: &str, raw: Option<bool>) -> String {
+        let attr_set = set_env_raw(value, raw).unwrap();
+        let env_attr_set = find_env(&attr_set).unwrap().unwrap();
+        let key_value = find_key_value_with_key(&env_attr_set, "VALUE").unwrap();
+        get_nth_child(&key_value.node, 1).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_set_env_raw_expression() {
+        assert_eq!(
+            env_written(r#""${pkgs.foo}/bin""#, Some(true)),
+            r#""${pkgs.foo}/bin""#
+        );
+        assert_eq!(
+            env_written("builtins.toString 3", Some(true)),
+            "builtins.toString 3"
+        );
+
+        // an expression that doesn't parse isn't written
+        assert!(matches!(
+            set_env_raw("\"unterminated", Some(true)),
+            Err(NixEditorError::InvalidOp(_))
+        ));
+    }
+
+    #[test]
+    fn test_set_env_string_literal_escapes() {
+        assert_eq!(env_written("en_US.UTF-8", Some(false)), r#""en_US.UTF-8""#);
+        // quoted even though it looks like an expression
+        assert_eq!(env_written("pkgs.foo", Some(false)), r#""pkgs.foo""#);
+        assert_eq!(
+            env_written(r#"say "hi" \ ${HOME}"#, Some(false)),
+            r#""say \"hi\" \\ \${HOME}""#
+        );
+
+        // and comes back as it was given
+        for value in [r#"say "hi" \ ${HOME}"#, r#""${pkgs.foo}/bin""#] {
+            let attr_set = set_env_raw(value, Some(false)).unwrap();
+            assert_eq!(
+                get_env(&attr_set, Some("VALUE".to_string())).unwrap(),
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_env_arg_name() {
+        let tree = rnix::Root::parse("{ p }: {\n  deps = [];\n}")
+            .syntax()
+            .clone_for_update();
+        let options = VerifyOptions {
+            arg_name: "p".to_string(),
+            ..Default::default()
+        };
+        let attr_set = verify_get_attr_set_with(&tree, &options).unwrap();
+        let value = "p.lib.makeLibraryPath [ p.zlib ]";
+        set_env_with(
+            &attr_set,
+            Some("LIBS".to_string()),
+            Some(value.to_string()),
+            None,
+            "p",
+        )
+        .unwrap();
+
+        let env_attr_set = find_env(&attr_set).unwrap().unwrap();
+        let key_value = find_key_value_with_key(&env_attr_set, "LIBS").unwrap();
+        assert_eq!(
+            get_nth_child(&key_value.node, 1).unwrap().to_string(),
+            value
+        );
+    }
+}
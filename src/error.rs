@@ -0,0 +1,100 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NixEditorError {
+    #[error("error: reading file - {path:?}: {source}")]
+    FileRead {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Could not find {path}")]
+    FileNotFound { path: String },
+
+    #[error("Refusing to overwrite {path}, it already exists")]
+    FileExists { path: String },
+
+    #[error("Refusing to read {path}, it is larger than {max_size} bytes")]
+    FileTooLarge { path: String, max_size: u64 },
+
+    #[error("Could not parse replit.nix: {0}")]
+    ParseFailed(String),
+
+    #[error("Could not verify and get: {0}")]
+    VerifyFailed(String),
+
+    #[error("Could not edit {key}: it is {kind} rather than a literal list")]
+    NotAList { key: String, kind: String },
+
+    #[error("Could not find dep {0}")]
+    DepNotFound(String),
+
+    #[error("{dep} could be any of {}, pass the full name", candidates.join(", "))]
+    AmbiguousDep {
+        dep: String,
+        candidates: Vec<String>,
+    },
+
+    #[error("Could not find env key {0}")]
+    EnvNotFound(String),
+
+    #[error("Could not perform op: {0}")]
+    InvalidOp(String),
+
+    #[error("Refusing to add {dep}: {reason}")]
+    Rejected { dep: String, reason: String },
+
+    #[error("Could not format: {0}")]
+    FormatFailed(String),
+
+    #[error("Refusing to write {path}, it would no longer parse: {message}")]
+    UnparseableOutput { path: String, message: String },
+
+    #[error("Gave up parsing after {timeout_ms}ms")]
+    ParseTimeout { timeout_ms: u64 },
+
+    #[error("Refusing to start another op, {workers} timed out ones are still running")]
+    Busy { workers: usize },
+
+    #[error("Timed out waiting for another edit of {path} to finish")]
+    LockTimeout { path: String },
+
+    #[error("Could not write to file {path}: {source}")]
+    WriteFailed {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl NixEditorError {
+    // Stable identifier for the kind of error, so programmatic callers can
+    // branch on it without matching on the human readable message
+    pub fn code(&self) -> &'static str {
+        match self {
+            NixEditorError::FileRead { .. } => "file_read",
+            NixEditorError::FileNotFound { .. } => "file_not_found",
+            NixEditorError::FileExists { .. } => "file_exists",
+            NixEditorError::FileTooLarge { .. } => "file_too_large",
+            NixEditorError::ParseFailed(_) => "parse_failed",
+            NixEditorError::VerifyFailed(_) => "verify_failed",
+            NixEditorError::NotAList { .. } => "not_a_list",
+            NixEditorError::DepNotFound(_) => "dep_not_found",
+            NixEditorError::AmbiguousDep { .. } => "ambiguous_dep",
+            NixEditorError::EnvNotFound(_) => "env_not_found",
+            NixEditorError::InvalidOp(_) => "invalid_op",
+            NixEditorError::Rejected { .. } => "rejected",
+            NixEditorError::FormatFailed(_) => "format_failed",
+            NixEditorError::UnparseableOutput { .. } => "unparseable_output",
+            NixEditorError::ParseTimeout { .. } => "parse_timeout",
+            NixEditorError::Busy { .. } => "busy",
+            NixEditorError::LockTimeout { .. } => "lock_timeout",
+            NixEditorError::WriteFailed { .. } => "write_failed",
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, NixEditorError>;
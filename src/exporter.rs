@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::ExportFormat;
+
+// Every list of deps in a file, for handing to tools that don't read nix: the
+// regular deps, and each library path list in the env block keyed by its env
+// key. Lists missing from the file are empty.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct DepsExport {
+    pub deps: Vec<String>,
+    pub env: BTreeMap<String, Vec<String>>,
+}
+
+impl DepsExport {
+    pub fn render(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Json => serde_json::to_string(self).unwrap(),
+            ExportFormat::Toml => self.to_toml(),
+        }
+    }
+
+    // e.g.
+    // deps = ["pkgs.cowsay"]
+    //
+    // [env]
+    // PYTHON_LD_LIBRARY_PATH = ["pkgs.zlib"]
+    pub fn to_toml(&self) -> String {
+        let mut toml = format!("deps = {}\n\n[env]\n", toml_array(&self.deps));
+        for (key, deps) in &self.env {
+            toml.push_str(&format!("{} = {}\n", key, toml_array(deps)));
+        }
+        toml
+    }
+}
+
+// A JSON array of strings is also a valid TOML array, TOML's basic strings
+// take the same escapes
+fn toml_array(values: &[String]) -> String {
+    serde_json::to_string(values).unwrap()
+}
+
+#[cfg(test)]
+mod exporter_tests {
+    use super::*;
+
+    fn export() -> DepsExport {
+        DepsExport {
+            deps: vec![
+                "pkgs.cowsay".to_string(),
+                r#""${pkgs.hello}/bin""#.to_string(),
+            ],
+            env: BTreeMap::from([
+                ("LD_LIBRARY_PATH".to_string(), vec![]),
+                (
+                    "PYTHON_LD_LIBRARY_PATH".to_string(),
+                    vec!["pkgs.zlib".to_string()],
+                ),
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_export_json() {
+        assert_eq!(
+            export().render(ExportFormat::Json),
+            r#"{"deps":["pkgs.cowsay","\"${pkgs.hello}/bin\""],"env":{"LD_LIBRARY_PATH":[],"PYTHON_LD_LIBRARY_PATH":["pkgs.zlib"]}}"#
+        );
+    }
+
+    #[test]
+    fn test_export_toml() {
+        assert_eq!(
+            export().render(ExportFormat::Toml),
+            r#"deps = ["pkgs.cowsay","\"${pkgs.hello}/bin\""]
+
+[env]
+LD_LIBRARY_PATH = []
+PYTHON_LD_LIBRARY_PATH = ["pkgs.zlib"]
+"#
+        );
+
+        assert_eq!(DepsExport::default().to_toml(), "deps = []\n\n[env]\n");
+    }
+}
@@ -0,0 +1,116 @@
+use rnix::SyntaxNode;
+use serde::Serialize;
+
+use crate::attr_set_deps::{find_deps_attr_set, get_attr_set_deps};
+use crate::verify_getter::{
+    find_key_value_with_key, verify_get_attr_set_with, verify_get_with, VerifyOptions,
+};
+use crate::{parse_read_only, DepType, FileKind};
+
+// A quick health check of a file: whether it parses and which of the parts
+// the ops look for are there
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct FileInfo {
+    pub parses: bool,
+    // where the first syntax error is, if it doesn't parse
+    pub parse_error: Option<String>,
+    pub has_deps: bool,
+    pub has_env: bool,
+    pub has_python_path: bool,
+    pub has_node_path: bool,
+    // how many regular deps there are
+    pub dep_count: usize,
+}
+
+// Only reads contents, nothing missing is filled in. Anything that doesn't
+// verify counts as missing.
+pub fn file_info(contents: &str, options: &VerifyOptions) -> FileInfo {
+    let root = match parse_read_only(contents) {
+        Ok(root) => root,
+        Err(err) => {
+            return FileInfo {
+                parse_error: Some(err.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+
+    let options = VerifyOptions {
+        insert_missing: false,
+        target_path: None,
+        env_key: None,
+        ..options.clone()
+    };
+    let has_list = |dep_type| verify_get_with(&root, dep_type, &options).is_ok();
+
+    let dep_count = match find_deps_attr_set(&root, &options) {
+        Some(deps_attr_set) => Some(get_attr_set_deps(&deps_attr_set).len()),
+        None => verify_get_with(&root, DepType::Regular, &options)
+            .ok()
+            .map(|deps_list| deps_list.node.children().count()),
+    };
+
+    let replit = options.file_kind == FileKind::Replit;
+    FileInfo {
+        parses: true,
+        parse_error: None,
+        has_deps: dep_count.is_some(),
+        has_env: replit && has_env(&root, &options),
+        has_python_path: replit && has_list(DepType::Python),
+        has_node_path: replit && has_list(DepType::Node),
+        dep_count: dep_count.unwrap_or(0),
+    }
+}
+
+fn has_env(root: &SyntaxNode, options: &VerifyOptions) -> bool {
+    verify_get_attr_set_with(root, options)
+        .ok()
+        .and_then(|attr_set| find_key_value_with_key(&attr_set, "env"))
+        .is_some()
+}
+
+#[cfg(test)]
+mod info_tests {
+    use super::*;
+
+    #[test]
+    fn test_file_info() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.python310
+    pkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [];
+  };
+}"#;
+        assert_eq!(
+            file_info(contents, &VerifyOptions::default()),
+            FileInfo {
+                parses: true,
+                parse_error: None,
+                has_deps: true,
+                has_env: true,
+                has_python_path: true,
+                has_node_path: false,
+                dep_count: 2,
+            }
+        );
+
+        let info = file_info("{ pkgs }: {\n  env = {};\n}", &VerifyOptions::default());
+        assert!(info.parses && info.has_env && !info.has_deps);
+        assert_eq!(info.dep_count, 0);
+    }
+
+    #[test]
+    fn test_file_info_broken_file() {
+        let info = file_info("{ pkgs }: {\n  deps = [\n}", &VerifyOptions::default());
+        assert!(!info.parses);
+        assert!(info.parse_error.unwrap().contains("line 3"));
+        assert!(!info.has_deps);
+
+        // parses, but isn't shaped like a replit.nix
+        let info = file_info("[ 1 2 ]", &VerifyOptions::default());
+        assert!(info.parses && !info.has_deps && !info.has_env);
+    }
+}
@@ -0,0 +1,2529 @@
+mod adder;
+mod attr_set_deps;
+mod clearer;
+mod deduper;
+mod delta;
+mod deps_setter;
+mod diff;
+mod editor;
+mod env_setter;
+mod error;
+mod exporter;
+mod info;
+mod merger;
+mod mover;
+mod op_json;
+mod pin;
+mod policy;
+mod remover;
+mod renamer;
+mod sorter;
+mod verify_getter;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+use rnix::parser::ParseError;
+use rnix::SyntaxNode;
+
+use serde::{Deserialize, Serialize};
+
+use clap::ArgEnum;
+
+pub use crate::adder::{add_dep, add_dep_with, AddOptions};
+pub use crate::attr_set_deps::{
+    add_attr_set_dep, find_attr_set_dep, find_deps_attr_set, get_attr_set_deps, remove_attr_set_dep,
+};
+pub use crate::clearer::clear_deps;
+pub use crate::deduper::dedup_deps;
+pub use crate::delta::{text_delta, TextDelta};
+pub use crate::deps_setter::set_deps;
+pub use crate::diff::unified_diff;
+pub use crate::editor::NixEditor;
+pub use crate::env_setter::{
+    get_env, get_env_keys, prune_empty_lib_path, set_env, set_env_with, unset_env,
+};
+pub use crate::error::{NixEditorError, Result};
+pub use crate::exporter::DepsExport;
+pub use crate::info::{file_info, FileInfo};
+pub use crate::merger::missing_deps;
+pub use crate::mover::move_dep;
+pub use crate::op_json::{dep_type_name, partial_deps_json, run_op_json};
+pub use crate::pin::{find_pin, Pin};
+pub use crate::policy::Policy;
+pub use crate::remover::{
+    is_dep_pattern, remove_dep, remove_dep_all, remove_dep_at, remove_dep_in_place,
+    remove_deps_matching,
+};
+pub use crate::renamer::rename_dep;
+pub use crate::sorter::sort_deps;
+pub use crate::verify_getter::{
+    verify_get, verify_get_attr_set, verify_get_attr_set_with, verify_get_existing,
+    verify_get_with, SyntaxNodeAndWhitespace, VerifyOptions,
+};
+
+use crate::remover::find_dep;
+use crate::sorter::dep_sort_key;
+use crate::verify_getter::{is_concatenated, lib_path_key, normalize_dep, string_dep_value};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    #[serde(rename = "add")]
+    Add,
+
+    #[serde(rename = "remove")]
+    Remove,
+
+    #[serde(rename = "get")]
+    Get,
+
+    #[serde(rename = "rename")]
+    Rename,
+
+    #[serde(rename = "has")]
+    Has,
+
+    #[serde(rename = "count")]
+    Count,
+
+    #[serde(rename = "clear")]
+    Clear,
+
+    // replace the whole list with the given deps
+    #[serde(rename = "set_deps")]
+    SetDeps,
+
+    #[serde(rename = "set_env")]
+    SetEnv,
+
+    #[serde(rename = "unset_env")]
+    UnsetEnv,
+
+    #[serde(rename = "sort")]
+    Sort,
+
+    #[serde(rename = "check")]
+    Check,
+
+    #[serde(rename = "dedup")]
+    Dedup,
+
+    #[serde(rename = "move")]
+    Move,
+
+    // get for every dep type at once
+    #[serde(rename = "get_all")]
+    GetAll,
+
+    #[serde(rename = "get_env_keys")]
+    GetEnvKeys,
+
+    // the value of one env key
+    #[serde(rename = "get_env")]
+    GetEnv,
+
+    #[serde(rename = "get_pin")]
+    GetPin,
+
+    // every list of deps at once, as JSON or TOML
+    #[serde(rename = "export")]
+    Export,
+
+    // whether the file parses and which parts of it are there
+    #[serde(rename = "info")]
+    Info,
+
+    // replace the contents with a fresh template, with the dep type's list in it
+    #[serde(rename = "init")]
+    Init,
+
+    // the deps a reference file has that this one doesn't, which apply adds
+    #[serde(rename = "merge")]
+    Merge,
+}
+
+impl OpKind {
+    // Ops that only look at the file and never change it
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            OpKind::Get
+                | OpKind::GetAll
+                | OpKind::GetEnvKeys
+                | OpKind::GetEnv
+                | OpKind::GetPin
+                | OpKind::Has
+                | OpKind::Count
+                | OpKind::Check
+                | OpKind::Export
+                | OpKind::Info
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DepType {
+    #[serde(rename = "regular")]
+    #[default]
+    Regular,
+
+    #[serde(rename = "python")]
+    Python,
+
+    // LD_LIBRARY_PATH in the env block, for native libraries node packages load
+    #[serde(rename = "node")]
+    Node,
+
+    // the buildInputs of a flake.nix's default devShell
+    #[serde(rename = "flake")]
+    Flake,
+}
+
+// Where add puts the new dep in a list spanning multiple lines
+#[derive(Serialize, Deserialize, ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AddPosition {
+    // the top of the list, below any comments heading it
+    #[serde(rename = "front")]
+    #[default]
+    Front,
+
+    // the end of the list
+    #[serde(rename = "back")]
+    Back,
+
+    // in alphabetical order if the list is already sorted, the front otherwise
+    #[serde(rename = "sorted")]
+    Sorted,
+}
+
+// What kind of nix file the deps are in, which decides where verify_get looks for them
+#[derive(Serialize, Deserialize, ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    // `{ pkgs }: { deps = [ ... ]; }`
+    #[serde(rename = "replit")]
+    #[default]
+    Replit,
+
+    // the buildInputs of a `mkShell { ... }` call
+    #[serde(rename = "shell")]
+    Shell,
+
+    // like shell, but the buildInputs can also be a `mkDerivation { ... }` call's
+    #[serde(rename = "default")]
+    Default,
+}
+
+// What export writes the deps out as
+#[derive(Serialize, Deserialize, ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    #[serde(rename = "json")]
+    #[default]
+    Json,
+
+    #[serde(rename = "toml")]
+    Toml,
+}
+
+impl FileKind {
+    // The name files of this kind usually go by
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            FileKind::Replit => "replit.nix",
+            FileKind::Shell => "shell.nix",
+            FileKind::Default => "default.nix",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Op {
+    // echoed back in the response so callers can match it up with the op
+    pub id: Option<serde_json::Value>,
+
+    pub op: OpKind,
+    pub dep_type: Option<DepType>,
+    pub dep: Option<String>,
+
+    // only used by set_deps, everything the list should hold
+    pub deps: Option<Vec<String>>,
+
+    // only used by rename
+    pub from: Option<String>,
+    pub to: Option<String>,
+
+    // only used by set_env, unset_env and get_env
+    pub key: Option<String>,
+    pub value: Option<String>,
+
+    // only used by set_env, write value as a nix expression instead of as a
+    // string. If not set, it's guessed from what value looks like
+    pub raw: Option<bool>,
+
+    // for add, insert the dep in alphabetical order if the list is sorted.
+    // for get, return the deps in alphabetical order instead of file order
+    pub sorted: Option<bool>,
+
+    // the name of the function's argument that nixpkgs is passed in as, pkgs if not set
+    pub arg_name: Option<String>,
+
+    // what kind of file the deps are in, replit if not set
+    pub file_kind: Option<FileKind>,
+
+    // a dotted path of keys to the list to edit instead of dep_type's list, e.g.
+    // nativeBuildInputs or env.PYTHON_LD_LIBRARY_PATH. Not used by get_all or move
+    pub target_path: Option<String>,
+
+    // an env key whose `pkgs.lib.makeLibraryPath [ ... ]` list to edit instead of
+    // dep_type's list, e.g. GI_TYPELIB_PATH. Not used by get_all or move
+    pub env_key: Option<String>,
+
+    // only used by remove, remove every occurrence of the dep instead of just the first
+    pub all: Option<bool>,
+
+    // only used by remove, remove the dep at this index in the list (counting
+    // from 0) instead of looking it up by dep
+    pub index: Option<usize>,
+
+    // only used by remove, succeed without changing anything when the dep isn't
+    // there instead of failing with dep_not_found
+    pub ignore_missing: Option<bool>,
+
+    // only used by remove, when removing a python or node dep empties its
+    // library path list, also remove its env key (and the env block if that
+    // leaves it empty)
+    pub prune_empty: Option<bool>,
+
+    // only used by init, replace a file that's already there. It's up to
+    // whatever reads the file to check, contents passed in are always replaced
+    pub force: Option<bool>,
+
+    // only used by merge, the contents of the file to take deps from
+    pub reference: Option<String>,
+
+    // only used by merge, add the missing deps instead of only returning them
+    pub apply: Option<bool>,
+
+    // only used by get, return string deps without their quotes
+    pub unquote: Option<bool>,
+
+    // only used by get, return deps without the leading pkgs. (or arg_name.), so
+    // pkgs.xorg.libX11 comes back as xorg.libX11
+    pub names_only: Option<bool>,
+
+    // only used by get, also return where the deps list is in the file
+    pub with_range: Option<bool>,
+
+    // only used by move, the dep type of the list the dep is moved into.
+    // dep_type is the list it's moved out of
+    pub to_dep_type: Option<DepType>,
+
+    // only used by add, how far to indent the dep past an empty list, 2 if not set
+    pub indent: Option<usize>,
+
+    // only used by add, put the dep under the first comment in the list containing this
+    pub group: Option<String>,
+
+    // only used by add, a comment to put on the line above the dep
+    pub comment: Option<String>,
+
+    // only used by add, where the dep goes. Front if not set, or sorted if sorted is set
+    pub position: Option<AddPosition>,
+
+    // only used by export, json if not set
+    pub export_format: Option<ExportFormat>,
+
+    // for ops that edit the file, sort every deps list afterwards
+    pub always_sort: Option<bool>,
+
+    // for ops that add deps (add, set_deps and rename's to), patterns of deps
+    // to reject, and if set, patterns one of which every dep has to match
+    pub deny: Option<Vec<String>>,
+    pub allow_only: Option<Vec<String>>,
+}
+
+impl Op {
+    pub fn new(op: OpKind, dep: Option<String>) -> Self {
+        Op {
+            id: None,
+            op,
+            dep_type: None,
+            dep,
+            deps: None,
+            from: None,
+            to: None,
+            key: None,
+            value: None,
+            raw: None,
+            sorted: None,
+            arg_name: None,
+            file_kind: None,
+            target_path: None,
+            env_key: None,
+            all: None,
+            index: None,
+            ignore_missing: None,
+            prune_empty: None,
+            force: None,
+            reference: None,
+            apply: None,
+            unquote: None,
+            names_only: None,
+            with_range: None,
+            to_dep_type: None,
+            indent: None,
+            group: None,
+            comment: None,
+            position: None,
+            export_format: None,
+            always_sort: None,
+            deny: None,
+            allow_only: None,
+        }
+    }
+}
+
+// What running an op produced: either the new file contents for ops that edit
+// the file, or some data about the file for read only ops
+#[derive(Debug, PartialEq, Eq)]
+pub enum OpOutput {
+    Contents(String),
+    Data(String),
+    // the deps in the list, produced by get
+    Deps(Vec<String>),
+    // the deps and where their list is, produced by get with with_range. There's
+    // no range when the list isn't in the file as written, e.g. it's missing
+    DepsWithRange(Vec<String>, Option<DepsRange>),
+    // the deps in the literal list of a `++` concatenation, produced by get. The
+    // lists it's concatenated with aren't included. The range is only there with with_range
+    PartialDeps(Vec<String>, Option<DepsRange>),
+    // the deps in each list, produced by get_all
+    DepsByType(Vec<(DepType, Vec<String>)>),
+    // the keys in the env block, produced by get_env_keys
+    EnvKeys(Vec<String>),
+    // where pkgs is pinned to, produced by get_pin
+    Pin(Option<Pin>),
+    // ops that edit the file but also have something to report
+    ContentsWithData(String, String),
+    // every list of deps and the format to write them out in, produced by export
+    Export(DepsExport, ExportFormat),
+    // produced by info
+    Info(FileInfo),
+}
+
+// The byte offsets of the start and end of a deps list
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepsRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl DepsRange {
+    fn of(node: &SyntaxNode) -> Self {
+        let range = node.text_range();
+        DepsRange {
+            start: range.start().into(),
+            end: range.end().into(),
+        }
+    }
+}
+
+pub const EMPTY_TEMPLATE: &str = r#"{pkgs}: {
+  deps = [];
+}
+"#;
+
+// Applies op to the given replit.nix contents and returns the new contents.
+// Read only ops (get, get_all, get_env_keys, get_env, get_pin, has, count, check, export, info) leave the contents untouched.
+// Nothing here touches the filesystem, so it's safe to embed.
+pub fn apply_op(
+    contents: &str,
+    op: OpKind,
+    dep: Option<String>,
+    dep_type: DepType,
+) -> Result<String> {
+    match run_op(contents, Op::new(op, dep), dep_type)? {
+        OpOutput::Contents(new_contents) | OpOutput::ContentsWithData(new_contents, _) => {
+            Ok(new_contents)
+        }
+        OpOutput::Data(_)
+        | OpOutput::Deps(_)
+        | OpOutput::DepsWithRange(..)
+        | OpOutput::PartialDeps(..)
+        | OpOutput::DepsByType(_)
+        | OpOutput::EnvKeys(_)
+        | OpOutput::Pin(_)
+        | OpOutput::Export(..)
+        | OpOutput::Info(_) => Ok(contents.to_string()),
+    }
+}
+
+// Like apply_op, but takes a full Op and also hands back the data produced by
+// read only ops. dep_type is used when the op doesn't specify its own.
+pub fn run_op(contents: &str, op: Op, default_dep_type: DepType) -> Result<OpOutput> {
+    // the edits only deal with \n, so files using \r\n get converted there and back
+    if !uses_crlf(contents) {
+        return run_op_lf(contents, op, default_dep_type);
+    }
+
+    let lf_contents = contents.replace("\r\n", "\n");
+    let to_crlf = |new_contents: String| new_contents.replace('\n', "\r\n");
+    // every line before an offset loses its \r
+    let to_crlf_offset = |offset: usize| offset + lf_contents[..offset].matches('\n').count();
+    let output = match run_op_lf(&lf_contents, op, default_dep_type)? {
+        OpOutput::Contents(new_contents) => OpOutput::Contents(to_crlf(new_contents)),
+        OpOutput::ContentsWithData(new_contents, data) => {
+            OpOutput::ContentsWithData(to_crlf(new_contents), data)
+        }
+        OpOutput::DepsWithRange(deps, range) => OpOutput::DepsWithRange(
+            deps,
+            range.map(|range| DepsRange {
+                start: to_crlf_offset(range.start),
+                end: to_crlf_offset(range.end),
+            }),
+        ),
+        OpOutput::PartialDeps(deps, range) => OpOutput::PartialDeps(
+            deps,
+            range.map(|range| DepsRange {
+                start: to_crlf_offset(range.start),
+                end: to_crlf_offset(range.end),
+            }),
+        ),
+        output => output,
+    };
+
+    Ok(output)
+}
+
+// The deps in a list the way get returns them
+pub(crate) fn list_deps_of(
+    deps_list: SyntaxNode,
+    arg_name: &str,
+    unquote: bool,
+    sorted: bool,
+) -> Result<Vec<String>> {
+    let mut deps = match unquote {
+        true => get_deps_unquoted(deps_list, arg_name)?,
+        false => get_deps(deps_list, arg_name)?,
+    };
+    if sorted {
+        deps.sort_by_key(|dep| dep_sort_key(dep));
+    }
+    Ok(deps)
+}
+
+// The deps with their leading `arg_name.` dropped. Anything else (e.g. a string
+// dep) is left as is.
+pub(crate) fn package_names(deps: Vec<String>, arg_name: &str, sorted: bool) -> Vec<String> {
+    let prefix = format!("{}.", arg_name);
+    let mut names: Vec<String> = deps
+        .into_iter()
+        .map(|dep| match dep.strip_prefix(&prefix) {
+            Some(name) => name.to_string(),
+            None => dep,
+        })
+        .collect();
+    // deps that didn't have the prefix may sort differently against the rest now
+    if sorted {
+        names.sort_by_key(|name| dep_sort_key(name));
+    }
+    names
+}
+
+// Whether most of the lines in contents end in \r\n
+pub(crate) fn uses_crlf(contents: &str) -> bool {
+    let crlf = contents.matches("\r\n").count();
+    let lf = contents.matches('\n').count() - crlf;
+    crlf > lf
+}
+
+// With always_sort, an edit also sorts every deps list in the file
+fn run_op_lf(contents: &str, op: Op, default_dep_type: DepType) -> Result<OpOutput> {
+    let sort_options = op.always_sort.unwrap_or(false).then(|| verify_options(&op));
+    let output = run_op_unsorted(contents, op, default_dep_type)?;
+
+    let options = match sort_options {
+        Some(options) => options,
+        None => return Ok(output),
+    };
+    // an edit that didn't change anything leaves the file alone
+    let output = match output {
+        OpOutput::Contents(new_contents) if new_contents != contents => {
+            OpOutput::Contents(sort_all_deps(&new_contents, &options)?)
+        }
+        OpOutput::ContentsWithData(new_contents, data) if new_contents != contents => {
+            OpOutput::ContentsWithData(sort_all_deps(&new_contents, &options)?, data)
+        }
+        output => output,
+    };
+    Ok(output)
+}
+
+// Sorts each deps list that's in contents, see sort_deps. Missing lists aren't filled in.
+pub fn sort_all_deps(contents: &str, options: &VerifyOptions) -> Result<String> {
+    let root = parse(contents)?;
+    let options = VerifyOptions {
+        insert_missing: false,
+        target_path: None,
+        env_key: None,
+        ..options.clone()
+    };
+
+    let dep_types = match options.file_kind {
+        FileKind::Replit => vec![DepType::Regular, DepType::Python, DepType::Node],
+        _ => vec![DepType::Regular],
+    };
+    for dep_type in dep_types {
+        if let Ok(deps_list) = verify_get_with(&root, dep_type, &options) {
+            sort_deps(deps_list)?;
+        }
+    }
+
+    Ok(root.to_string())
+}
+
+// A new replit.nix with an empty list for dep_type, so python and node deps
+// get their env block too
+pub fn init_template(dep_type: DepType, options: &VerifyOptions) -> Result<String> {
+    if options.file_kind != FileKind::Replit {
+        return Err(NixEditorError::InvalidOp(format!(
+            "init only writes replit.nix, not {}",
+            options.file_kind.file_name()
+        )));
+    }
+
+    let root = parse(&EMPTY_TEMPLATE.replacen("pkgs", &options.arg_name, 1))?;
+    let options = VerifyOptions {
+        insert_missing: true,
+        ..options.clone()
+    };
+    verify_get_with(&root, dep_type, &options)?;
+    Ok(root.to_string())
+}
+
+pub(crate) fn verify_options(op: &Op) -> VerifyOptions {
+    let mut options = VerifyOptions::default();
+    if let Some(arg_name) = &op.arg_name {
+        options.arg_name = arg_name.clone();
+    }
+    options.file_kind = op.file_kind.unwrap_or_default();
+    options.target_path = op.target_path.clone();
+    options.env_key = op.env_key.clone();
+    options
+}
+
+fn run_op_unsorted(contents: &str, op: Op, default_dep_type: DepType) -> Result<OpOutput> {
+    let dep_type = op.dep_type.unwrap_or(default_dep_type);
+
+    let options = verify_options(&op);
+    if options.target_path.is_some() && matches!(op.op, OpKind::GetAll | OpKind::Move) {
+        return Err(NixEditorError::InvalidOp(
+            "target_path can't be used with get_all or move".to_string(),
+        ));
+    }
+    if options.env_key.is_some() && matches!(op.op, OpKind::GetAll | OpKind::Move) {
+        return Err(NixEditorError::InvalidOp(
+            "env_key can't be used with get_all or move".to_string(),
+        ));
+    }
+    if options.env_key.is_some() && options.target_path.is_some() {
+        return Err(NixEditorError::InvalidOp(
+            "env_key and target_path can't be used together".to_string(),
+        ));
+    }
+
+    check_policy(&op, &options)?;
+
+    // info reports a file that doesn't parse instead of failing on it
+    if op.op == OpKind::Info {
+        return Ok(OpOutput::Info(file_info(contents, &options)));
+    }
+
+    // init doesn't care what was there before
+    if op.op == OpKind::Init {
+        return Ok(OpOutput::Contents(init_template(dep_type, &options)?));
+    }
+
+    let unquote = op.unquote.unwrap_or(false);
+    let sorted = op.sorted.unwrap_or(false);
+    let with_range = op.with_range.unwrap_or(false);
+    let names_only = op.names_only.unwrap_or(false);
+    let arg_name = options.arg_name.clone();
+    // partial is for a list that's only part of the deps, see PartialDeps
+    let deps_output = move |deps: Vec<String>, range: Option<DepsRange>, partial| {
+        let deps = match names_only {
+            true => package_names(deps, &arg_name, sorted),
+            false => deps,
+        };
+        match (partial, with_range) {
+            (true, _) => OpOutput::PartialDeps(deps, range.filter(|_| with_range)),
+            (false, true) => OpOutput::DepsWithRange(deps, range),
+            (false, false) => OpOutput::Deps(deps),
+        }
+    };
+
+    let tree = parse_read_only(contents)?;
+
+    // get is the most common op and rarely needs to edit the tree, so try it on
+    // the tree as parsed first. Anything that doesn't verify without filling in
+    // missing keys (e.g. a missing list) falls through to the editable copy.
+    if op.op == OpKind::Get {
+        let existing_options = VerifyOptions {
+            insert_missing: false,
+            ..options.clone()
+        };
+        if let Ok(deps_list) = verify_get_with(&tree, dep_type, &existing_options) {
+            let range = DepsRange::of(&deps_list.node);
+            let partial = is_concatenated(&deps_list.node);
+            let deps = list_deps_of(deps_list.node, &options.arg_name, unquote, sorted)?;
+            return Ok(deps_output(deps, Some(range), partial));
+        }
+    }
+
+    let root = tree.clone_for_update();
+
+    // deps written as an attr set instead of a list only support the basic ops
+    let attr_set_op = matches!(
+        op.op,
+        OpKind::Add | OpKind::Remove | OpKind::Get | OpKind::Has | OpKind::Count
+    );
+    let own_list = options.target_path.is_none() && options.env_key.is_none();
+    if attr_set_op && dep_type == DepType::Regular && own_list {
+        if let Some(deps_attr_set) = find_deps_attr_set(&root, &options) {
+            let output = run_attr_set_op(contents, &root, &deps_attr_set, op)?;
+            return Ok(match output {
+                OpOutput::Deps(deps) => {
+                    deps_output(deps, Some(DepsRange::of(&deps_attr_set)), false)
+                }
+                output => output,
+            });
+        }
+    }
+
+    // env ops don't touch any deps list, so only look one up when the op needs it
+    let deps_list = || verify_get_with(&root, dep_type, &options);
+
+    let list_deps = |dep_type: DepType| -> Result<Vec<String>> {
+        list_deps_of(
+            verify_get_with(&root, dep_type, &options)?.node,
+            &options.arg_name,
+            unquote,
+            sorted,
+        )
+    };
+
+    let new_contents = match op.op {
+        OpKind::Add => {
+            add_dep_with(deps_list()?, op.dep.clone(), &add_options(&op, &options))?;
+            root.to_string()
+        }
+        OpKind::Remove => {
+            let deps_list = deps_list()?.node;
+
+            // looking up the list can change the tree (e.g. merging duplicate lists),
+            // so the text ranges have to come from what the tree looks like now
+            let current_contents = root.to_string();
+
+            let prune = op.prune_empty.unwrap_or(false)
+                && (matches!(dep_type, DepType::Python | DepType::Node)
+                    || options.env_key.is_some())
+                && options.target_path.is_none();
+            let prune_empty = |new_contents: String| match prune {
+                true => prune_empty_contents(new_contents, dep_type, &options),
+                false => Ok(new_contents),
+            };
+
+            if let Some(index) = op.index {
+                return Ok(OpOutput::Contents(prune_empty(remove_dep_at(
+                    &current_contents,
+                    deps_list,
+                    index,
+                )?)?));
+            }
+
+            if op.dep.as_deref().is_some_and(is_dep_pattern) {
+                let pattern = op.dep.clone().unwrap_or_default();
+                let (new_contents, removed) =
+                    remove_deps_matching(&current_contents, deps_list, op.dep, &options.arg_name)?;
+                let new_contents = match removed {
+                    0 if op.ignore_missing.unwrap_or(false) => contents.to_string(),
+                    0 => return Err(NixEditorError::DepNotFound(pattern)),
+                    _ => prune_empty(new_contents)?,
+                };
+                return Ok(OpOutput::ContentsWithData(
+                    new_contents,
+                    removed.to_string(),
+                ));
+            }
+
+            let dep = op.dep.clone().unwrap_or_default();
+            let new_contents = match op.all.unwrap_or(false) {
+                true => remove_dep_all(&current_contents, deps_list, op.dep, &options.arg_name)?,
+                false => remove_dep(&current_contents, deps_list, op.dep, &options.arg_name)?,
+            };
+
+            // nothing was removed, so leave the file as it was
+            match new_contents == current_contents {
+                true if op.ignore_missing.unwrap_or(false) => contents.to_string(),
+                true => return Err(NixEditorError::DepNotFound(dep)),
+                false => prune_empty(new_contents)?,
+            }
+        }
+        OpKind::Rename => {
+            rename_dep(deps_list()?.node, op.from, op.to, &options.arg_name)?;
+            root.to_string()
+        }
+        OpKind::Clear => {
+            clear_deps(deps_list()?)?;
+            root.to_string()
+        }
+        OpKind::SetDeps => {
+            let deps = op
+                .deps
+                .ok_or_else(|| NixEditorError::InvalidOp("expected deps to set".to_string()))?;
+            set_deps(deps_list()?, deps, &options.arg_name)?;
+            root.to_string()
+        }
+        OpKind::Sort => {
+            sort_deps(deps_list()?)?;
+            root.to_string()
+        }
+        OpKind::Merge => {
+            let reference = op.reference.as_deref().ok_or_else(|| {
+                NixEditorError::InvalidOp(
+                    "expected the contents of a file to merge from".to_string(),
+                )
+            })?;
+            let reference_options = VerifyOptions {
+                insert_missing: false,
+                ..options.clone()
+            };
+            let reference_list =
+                verify_get_with(&parse_read_only(reference)?, dep_type, &reference_options)?;
+
+            let deps_list = deps_list()?;
+            let missing = missing_deps(&deps_list.node, &reference_list.node, &options.arg_name);
+            if !op.apply.unwrap_or(false) {
+                return Ok(OpOutput::Deps(missing));
+            }
+
+            let policy = policy(&op, &options);
+            let sorted = op.sorted.unwrap_or(false).then_some(AddPosition::Sorted);
+            let add_options = AddOptions {
+                position: op.position.or(sorted),
+                indent: op.indent,
+                arg_name: Some(options.arg_name.clone()),
+                ..Default::default()
+            };
+            missing.iter().try_for_each(|dep| policy.check(dep))?;
+            // each dep goes in front of the one added before it unless they go at
+            // the back, so they'd come out reversed
+            let mut to_add: Vec<&String> = missing.iter().collect();
+            if add_options.position != Some(AddPosition::Back) {
+                to_add.reverse();
+            }
+            for dep in to_add {
+                add_dep_with(deps_list.clone(), Some(dep.clone()), &add_options)?;
+            }
+            return Ok(OpOutput::ContentsWithData(
+                root.to_string(),
+                missing.join(","),
+            ));
+        }
+        OpKind::Dedup => {
+            let removed = dedup_deps(deps_list()?, &options.arg_name)?;
+            return Ok(OpOutput::ContentsWithData(
+                root.to_string(),
+                removed.to_string(),
+            ));
+        }
+        OpKind::Move => {
+            let to_dep_type = op.to_dep_type.ok_or_else(|| {
+                NixEditorError::InvalidOp("expected dep type to move to".to_string())
+            })?;
+            // moving within the same list wouldn't change anything
+            if to_dep_type != dep_type {
+                move_dep(
+                    deps_list()?,
+                    verify_get_with(&root, to_dep_type, &options)?,
+                    op.dep,
+                    &options.arg_name,
+                )?;
+            }
+            root.to_string()
+        }
+        OpKind::SetEnv => {
+            set_env_with(
+                &verify_get_attr_set_with(&root, &options)?,
+                op.key,
+                op.value,
+                op.raw,
+                &options.arg_name,
+            )?;
+            root.to_string()
+        }
+        OpKind::UnsetEnv => {
+            unset_env(&verify_get_attr_set_with(&root, &options)?, op.key)?;
+            root.to_string()
+        }
+        OpKind::Get => {
+            // getting here means the list had to be filled in or merged, so
+            // offsets into the edited tree wouldn't match the file
+            let deps_list = deps_list()?.node;
+            let partial = is_concatenated(&deps_list);
+            let deps = list_deps_of(deps_list, &options.arg_name, unquote, sorted)?;
+            return Ok(deps_output(deps, None, partial));
+        }
+        OpKind::GetAll => {
+            // replit.nix has a list for each dep type, shell.nix and default.nix only the one.
+            // missing lists are filled in from the templates, so they come back empty
+            let dep_types = match options.file_kind {
+                FileKind::Replit => vec![DepType::Regular, DepType::Python, DepType::Node],
+                _ => vec![DepType::Regular],
+            };
+            let deps_by_type = dep_types
+                .into_iter()
+                .map(|dep_type| Ok((dep_type, list_deps(dep_type)?)))
+                .collect::<Result<_>>()?;
+            return Ok(OpOutput::DepsByType(deps_by_type));
+        }
+        OpKind::GetPin => {
+            return Ok(OpOutput::Pin(find_pin(&root)));
+        }
+        OpKind::Info | OpKind::Init => unreachable!(),
+        OpKind::Export => {
+            let mut export = DepsExport {
+                deps: list_deps(DepType::Regular)?,
+                ..Default::default()
+            };
+            // only replit.nix has an env block. Missing lists are filled in from
+            // the templates, so they come back empty
+            if options.file_kind == FileKind::Replit {
+                for dep_type in [DepType::Python, DepType::Node] {
+                    let key = lib_path_key(dep_type).unwrap().to_string();
+                    export.env.insert(key, list_deps(dep_type)?);
+                }
+            }
+            let format = op.export_format.unwrap_or_default();
+            return Ok(OpOutput::Export(export, format));
+        }
+        OpKind::GetEnvKeys => {
+            let keys = get_env_keys(&verify_get_attr_set_with(&root, &options)?)?;
+            return Ok(OpOutput::EnvKeys(keys));
+        }
+        OpKind::GetEnv => {
+            let value = get_env(&verify_get_attr_set_with(&root, &options)?, op.key)?;
+            return Ok(OpOutput::Data(value));
+        }
+        OpKind::Has => {
+            let has = has_dep(deps_list()?.node, op.dep, &options.arg_name)?;
+            return Ok(OpOutput::Data(has.to_string()));
+        }
+        OpKind::Check => {
+            // parsing already failed above if the file has syntax errors
+            let options = VerifyOptions {
+                insert_missing: false,
+                ..options
+            };
+            verify_get_with(&root, dep_type, &options)?;
+            return Ok(OpOutput::Data("ok".to_string()));
+        }
+        OpKind::Count => {
+            let deps = get_deps(deps_list()?.node, &options.arg_name)?;
+            return Ok(OpOutput::Data(deps.len().to_string()));
+        }
+    };
+
+    Ok(OpOutput::Contents(new_contents))
+}
+
+// Drops dep_type's library path list from contents if removing from it left it
+// empty, see prune_empty_lib_path
+fn prune_empty_contents(
+    contents: String,
+    dep_type: DepType,
+    options: &VerifyOptions,
+) -> Result<String> {
+    let root = parse(&contents)?;
+    let options = VerifyOptions {
+        insert_missing: false,
+        ..options.clone()
+    };
+    let deps_list = verify_get_with(&root, dep_type, &options)?;
+
+    match prune_empty_lib_path(&deps_list.node) {
+        true => Ok(root.to_string()),
+        false => Ok(contents),
+    }
+}
+
+// How add places op's dep
+pub(crate) fn add_options(op: &Op, options: &VerifyOptions) -> AddOptions {
+    let sorted = op.sorted.unwrap_or(false).then_some(AddPosition::Sorted);
+    AddOptions {
+        position: op.position.or(sorted),
+        indent: op.indent,
+        group: op.group.clone(),
+        comment: op.comment.clone(),
+        arg_name: Some(options.arg_name.clone()),
+    }
+}
+
+// Fails with Rejected if op would add a dep its deny or allow_only patterns don't allow
+pub(crate) fn check_policy(op: &Op, options: &VerifyOptions) -> Result<()> {
+    let policy = policy(op, options);
+    let new_deps: Vec<&String> = match op.op {
+        OpKind::Add => op.dep.iter().collect(),
+        OpKind::SetDeps => op.deps.iter().flatten().collect(),
+        OpKind::Rename => op.to.iter().collect(),
+        _ => vec![],
+    };
+    new_deps.into_iter().try_for_each(|dep| policy.check(dep))
+}
+
+fn policy<'a>(op: &'a Op, options: &'a VerifyOptions) -> Policy<'a> {
+    Policy {
+        deny: op.deny.as_deref().unwrap_or_default(),
+        allow_only: op.allow_only.as_deref().unwrap_or_default(),
+        arg_name: &options.arg_name,
+    }
+}
+
+// Runs one of the ops that make sense for deps written as an attr set
+fn run_attr_set_op(
+    contents: &str,
+    root: &SyntaxNode,
+    deps_attr_set: &SyntaxNode,
+    op: Op,
+) -> Result<OpOutput> {
+    let output = match op.op {
+        OpKind::Add => {
+            add_attr_set_dep(deps_attr_set, op.dep)?;
+            OpOutput::Contents(root.to_string())
+        }
+        OpKind::Remove => {
+            if op.index.is_some() {
+                return Err(NixEditorError::InvalidOp(
+                    "removing by index needs deps to be a list".to_string(),
+                ));
+            }
+            let dep = op.dep.clone().unwrap_or_default();
+            match remove_attr_set_dep(deps_attr_set, op.dep)? {
+                true => OpOutput::Contents(root.to_string()),
+                false if op.ignore_missing.unwrap_or(false) => {
+                    OpOutput::Contents(contents.to_string())
+                }
+                false => return Err(NixEditorError::DepNotFound(dep)),
+            }
+        }
+        OpKind::Get => {
+            let mut deps = get_attr_set_deps(deps_attr_set);
+            if op.sorted.unwrap_or(false) {
+                deps.sort_by_key(|dep| dep_sort_key(dep));
+            }
+            OpOutput::Deps(deps)
+        }
+        OpKind::Has => {
+            let dep = op
+                .dep
+                .ok_or_else(|| NixEditorError::InvalidOp("no dependency".to_string()))?;
+            let has = find_attr_set_dep(deps_attr_set, &dep).is_some();
+            OpOutput::Data(has.to_string())
+        }
+        OpKind::Count => OpOutput::Data(get_attr_set_deps(deps_attr_set).len().to_string()),
+        _ => unreachable!(),
+    };
+
+    Ok(output)
+}
+
+// Parses contents into a tree that can be edited. A file with syntax errors is
+// rejected up front, editing the broken tree would only produce garbage.
+pub(crate) fn parse(contents: &str) -> Result<SyntaxNode> {
+    Ok(parse_read_only(contents)?.clone_for_update())
+}
+
+// Errors with where the first syntax error is if contents aren't valid nix
+pub fn check_parses(contents: &str) -> Result<()> {
+    parse_read_only(contents).map(|_| ())
+}
+
+// Like parse, but the tree can't be edited, which saves making a copy of it
+pub(crate) fn parse_read_only(contents: &str) -> Result<SyntaxNode> {
+    let ast = rnix::Root::parse(contents);
+
+    // an empty file fails to parse, but it's fine since verify_get fills it in with the template
+    let is_empty = ast.syntax().children().count() == 0;
+    if let Some(err) = ast.errors().first().filter(|_| !is_empty) {
+        return Err(NixEditorError::ParseFailed(describe_parse_error(
+            contents, err,
+        )));
+    }
+
+    Ok(ast.syntax())
+}
+
+fn describe_parse_error(contents: &str, err: &ParseError) -> String {
+    let offset = match err {
+        ParseError::Unexpected(range)
+        | ParseError::UnexpectedExtra(range)
+        | ParseError::UnexpectedWanted(_, range, _)
+        | ParseError::UnexpectedDoubleBind(range)
+        | ParseError::DuplicatedArgs(range, _) => range.start().into(),
+        _ => contents.len(),
+    };
+
+    let (line, column) = line_column(contents, offset);
+    format!("{} (line {}, column {})", err, line, column)
+}
+
+// The 1-based line and column a byte offset falls on
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+// Under `with pkgs;` (`with` the arg_name) deps are returned without the pkgs.
+// prefix, so a list mixing `pkgs.ncdu` and `cowsay` comes back as `ncdu` and `cowsay`
+pub fn get_deps(deps_list: SyntaxNode, arg_name: &str) -> Result<Vec<String>> {
+    Ok(deps_list
+        .children()
+        .map(|child| normalize_dep(&deps_list, &child.to_string(), arg_name))
+        .collect())
+}
+
+// Like get_deps, but string deps are returned without their quotes
+pub fn get_deps_unquoted(deps_list: SyntaxNode, arg_name: &str) -> Result<Vec<String>> {
+    Ok(deps_list
+        .children()
+        .map(|child| {
+            string_dep_value(&child)
+                .unwrap_or_else(|| normalize_dep(&deps_list, &child.to_string(), arg_name))
+        })
+        .collect())
+}
+
+pub fn has_dep(deps_list: SyntaxNode, dep_opt: Option<String>, arg_name: &str) -> Result<bool> {
+    let dep = dep_opt.ok_or_else(|| NixEditorError::InvalidOp("no dependency".to_string()))?;
+    Ok(find_dep(&deps_list, &dep, arg_name).is_some())
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"{pkgs}: {
+  deps = [
+    pkgs.cowsay
+  ];
+}
+"#;
+
+    #[test]
+    fn test_apply_op_add() {
+        let new_contents = apply_op(
+            TEMPLATE,
+            OpKind::Add,
+            Some("pkgs.ncdu".to_string()),
+            DepType::Regular,
+        );
+        assert!(new_contents.is_ok());
+
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{pkgs}: {
+  deps = [
+    pkgs.ncdu
+    pkgs.cowsay
+  ];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_apply_op_remove() {
+        let new_contents = apply_op(
+            TEMPLATE,
+            OpKind::Remove,
+            Some("pkgs.cowsay".to_string()),
+            DepType::Regular,
+        );
+        assert!(new_contents.is_ok());
+
+        assert_eq!(new_contents.unwrap(), "{pkgs}: {\n  deps = [\n  ];\n}\n");
+    }
+
+    #[test]
+    fn test_apply_op_get_leaves_contents() {
+        let new_contents = apply_op(TEMPLATE, OpKind::Get, None, DepType::Regular);
+        assert!(new_contents.is_ok());
+
+        assert_eq!(new_contents.unwrap(), TEMPLATE);
+    }
+
+    #[test]
+    fn test_run_op_get() {
+        let output = run_op(TEMPLATE, Op::new(OpKind::Get, None), DepType::Regular);
+        assert!(output.is_ok());
+
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec!["pkgs.cowsay".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_run_op_get_needing_edits() {
+        // these only verify once the tree is edited, so they can't take the read only path
+        let output = run_op("", Op::new(OpKind::Get, None), DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Deps(vec![]));
+
+        let output = run_op(TEMPLATE, Op::new(OpKind::Get, None), DepType::Python);
+        assert_eq!(output.unwrap(), OpOutput::Deps(vec![]));
+
+        // the later list's deps are merged into the back of the first one
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.a
+  ];
+  deps = [
+    pkgs.b
+  ];
+}"#;
+        let output = run_op(contents, Op::new(OpKind::Get, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec!["pkgs.a".to_string(), "pkgs.b".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_run_op_get_with_pkgs() {
+        let contents = r#"{ pkgs }: {
+  deps = with pkgs; [
+    pkgs.ncdu
+    cowsay
+  ];
+}
+"#;
+        let output = run_op(contents, Op::new(OpKind::Get, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec!["ncdu".to_string(), "cowsay".to_string()])
+        );
+
+        let output = run_op(
+            contents,
+            Op::new(OpKind::Has, Some("pkgs.cowsay".to_string())),
+            DepType::Regular,
+        );
+        assert_eq!(output.unwrap(), OpOutput::Data("true".to_string()));
+    }
+
+    #[test]
+    fn test_apply_op_add_flake() {
+        let contents = r#"{
+  outputs = { self, nixpkgs }: {
+    devShells.x86_64-linux.default = pkgs.mkShell {
+      buildInputs = [
+        pkgs.cowsay
+      ];
+    };
+  };
+}
+"#;
+        let new_contents = apply_op(
+            contents,
+            OpKind::Add,
+            Some("pkgs.ncdu".to_string()),
+            DepType::Flake,
+        );
+
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{
+  outputs = { self, nixpkgs }: {
+    devShells.x86_64-linux.default = pkgs.mkShell {
+      buildInputs = [
+        pkgs.ncdu
+        pkgs.cowsay
+      ];
+    };
+  };
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_run_op_parse_error() {
+        let contents = r#"{pkgs}: {
+  deps = [
+    pkgs.cowsay
+  ;
+}
+"#;
+        let output = run_op(contents, Op::new(OpKind::Get, None), DepType::Regular);
+
+        match output {
+            Err(NixEditorError::ParseFailed(message)) => {
+                assert!(message.contains("line 4, column 3"), "{}", message)
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_op_empty_contents() {
+        let output = run_op("", Op::new(OpKind::Count, None), DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Data("0".to_string()));
+    }
+
+    #[test]
+    fn test_run_op_check() {
+        let check =
+            |contents: &str, dep_type| run_op(contents, Op::new(OpKind::Check, None), dep_type);
+
+        assert_eq!(
+            check(TEMPLATE, DepType::Regular).unwrap(),
+            OpOutput::Data("ok".to_string())
+        );
+        assert_eq!(
+            check("{ pkgs }: {\n  deps = [];\n}", DepType::Regular).unwrap(),
+            OpOutput::Data("ok".to_string())
+        );
+
+        // missing keys aren't filled in
+        assert!(matches!(
+            check(TEMPLATE, DepType::Python),
+            Err(NixEditorError::VerifyFailed(_))
+        ));
+        assert!(matches!(
+            check("{ pkgs }: {}", DepType::Regular),
+            Err(NixEditorError::VerifyFailed(_))
+        ));
+        assert!(matches!(
+            check("", DepType::Regular),
+            Err(NixEditorError::VerifyFailed(_))
+        ));
+
+        assert!(matches!(
+            check("{ pkgs }: { deps = [ }", DepType::Regular),
+            Err(NixEditorError::ParseFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_op_get_names_only() {
+        let contents = r#"{ nixpkgs }: {
+  deps = [
+    nixpkgs.xorg.libX11
+    nixpkgs.cowsay
+    pkgs.ncdu
+  ];
+}"#;
+        let op = Op {
+            names_only: Some(true),
+            sorted: Some(true),
+            arg_name: Some("nixpkgs".to_string()),
+            ..Op::new(OpKind::Get, None)
+        };
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec![
+                "cowsay".to_string(),
+                "pkgs.ncdu".to_string(),
+                "xorg.libX11".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_run_op_arg_name_with_list() {
+        let contents = "{ p }: {\n  deps = with p; [ ncdu ];\n}\n";
+        let op_with_arg = |kind: OpKind, dep: &str| {
+            let mut op = Op::new(kind, Some(dep.to_string()));
+            op.arg_name = Some("p".to_string());
+            op
+        };
+
+        // the list has bare names, so p.cowsay goes in as cowsay
+        let output = run_op(
+            contents,
+            op_with_arg(OpKind::Add, "p.cowsay"),
+            DepType::Regular,
+        );
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents("{ p }: {\n  deps = with p; [ cowsay ncdu ];\n}\n".to_string())
+        );
+
+        let output = run_op(
+            contents,
+            op_with_arg(OpKind::Remove, "p.ncdu"),
+            DepType::Regular,
+        );
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents("{ p }: {\n  deps = with p; [ ];\n}\n".to_string())
+        );
+
+        let contents = "{ p }: {\n  deps = with p; [ ncdu p.cowsay ];\n}\n";
+        let mut op = Op::new(OpKind::Get, None);
+        op.arg_name = Some("p".to_string());
+        assert_eq!(
+            run_op(contents, op, DepType::Regular).unwrap(),
+            OpOutput::Deps(vec!["ncdu".to_string(), "cowsay".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_run_op_arg_name() {
+        let contents = r#"{ nixpkgs, ... }: {
+  deps = [
+    nixpkgs.cowsay
+  ];
+}
+"#;
+        let mut op = Op::new(OpKind::Add, Some("nixpkgs.zlib".to_string()));
+        op.arg_name = Some("nixpkgs".to_string());
+        let output = run_op(contents, op, DepType::Python);
+
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents(
+                r#"{ nixpkgs, ... }: {
+  deps = [
+    nixpkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = nixpkgs.lib.makeLibraryPath [
+      nixpkgs.zlib
+    ];
+  };
+}
+"#
+                .to_string()
+            )
+        );
+
+        // without the arg name the file doesn't verify
+        let output = run_op(contents, Op::new(OpKind::Get, None), DepType::Regular);
+        assert!(matches!(output, Err(NixEditorError::VerifyFailed(_))));
+    }
+
+    #[test]
+    fn test_apply_op_crlf() {
+        let contents = TEMPLATE.replace('\n', "\r\n");
+
+        let new_contents = apply_op(
+            &contents,
+            OpKind::Add,
+            Some("pkgs.ncdu".to_string()),
+            DepType::Regular,
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            "{pkgs}: {\r\n  deps = [\r\n    pkgs.ncdu\r\n    pkgs.cowsay\r\n  ];\r\n}\r\n"
+        );
+
+        let new_contents = apply_op(
+            &contents,
+            OpKind::Remove,
+            Some("pkgs.cowsay".to_string()),
+            DepType::Regular,
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            "{pkgs}: {\r\n  deps = [\r\n  ];\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn test_run_op_string_deps() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    "${pkgs.hello}/bin"
+    "foo \"bar\""
+  ];
+}
+"#;
+        let output = run_op(contents, Op::new(OpKind::Get, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec![
+                "pkgs.cowsay".to_string(),
+                r#""${pkgs.hello}/bin""#.to_string(),
+                r#""foo \"bar\"""#.to_string(),
+            ])
+        );
+
+        let mut op = Op::new(OpKind::Get, None);
+        op.unquote = Some(true);
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec![
+                "pkgs.cowsay".to_string(),
+                "${pkgs.hello}/bin".to_string(),
+                r#"foo "bar""#.to_string(),
+            ])
+        );
+
+        let op = Op {
+            names_only: Some(true),
+            ..Op::new(OpKind::Get, None)
+        };
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec![
+                "cowsay".to_string(),
+                r#""${pkgs.hello}/bin""#.to_string(),
+                r#""foo \"bar\"""#.to_string(),
+            ])
+        );
+
+        // string deps can be removed by either their quoted or unquoted value
+        let new_contents = apply_op(
+            contents,
+            OpKind::Remove,
+            Some(r#"foo "bar""#.to_string()),
+            DepType::Regular,
+        )
+        .unwrap();
+        let new_contents = apply_op(
+            &new_contents,
+            OpKind::Remove,
+            Some(r#""${pkgs.hello}/bin""#.to_string()),
+            DepType::Regular,
+        )
+        .unwrap();
+        assert_eq!(new_contents, TEMPLATE.replace("{pkgs}", "{ pkgs }"));
+
+        // and adding one that's already there is a no-op
+        let new_contents = apply_op(
+            contents,
+            OpKind::Add,
+            Some("${pkgs.hello}/bin".to_string()),
+            DepType::Regular,
+        );
+        assert_eq!(new_contents.unwrap(), contents);
+    }
+
+    #[test]
+    fn test_run_op_sorted_get() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.ncdu
+    pkgs.python310Packages.pip
+    pkgs.cowsay
+    pkgs.python310
+  ];
+}
+"#;
+        let mut op = Op::new(OpKind::Get, None);
+        op.sorted = Some(true);
+        let output = run_op(contents, op, DepType::Regular);
+
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec![
+                "pkgs.cowsay".to_string(),
+                "pkgs.ncdu".to_string(),
+                "pkgs.python310".to_string(),
+                "pkgs.python310Packages.pip".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apply_op_remove_from_duplicate_deps() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+  deps = [
+    pkgs.ncdu
+  ];
+}
+"#;
+        let new_contents = apply_op(
+            contents,
+            OpKind::Remove,
+            Some("pkgs.cowsay".to_string()),
+            DepType::Regular,
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            "{ pkgs }: {\n  deps = [\n    pkgs.ncdu\n  ];\n}\n"
+        );
+
+        // a dep that isn't there leaves the duplicate lists as they were
+        let op = Op {
+            ignore_missing: Some(true),
+            ..Op::new(OpKind::Remove, Some("pkgs.htop".to_string()))
+        };
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Contents(contents.to_string()));
+    }
+
+    #[test]
+    fn test_run_op_remove_missing() {
+        let op = Op::new(OpKind::Remove, Some("pkgs.htop".to_string()));
+        assert!(matches!(
+            run_op(TEMPLATE, op, DepType::Regular),
+            Err(NixEditorError::DepNotFound(dep)) if dep == "pkgs.htop"
+        ));
+
+        let op = Op {
+            ignore_missing: Some(true),
+            ..Op::new(OpKind::Remove, Some("pkgs.htop".to_string()))
+        };
+        let output = run_op(TEMPLATE, op, DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Contents(TEMPLATE.to_string()));
+    }
+
+    #[test]
+    fn test_run_op_remove_prune_empty() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.python310
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}"#;
+        let remove_zlib = |prune_empty| Op {
+            prune_empty,
+            ..Op::new(OpKind::Remove, Some("pkgs.zlib".to_string()))
+        };
+
+        // the empty list is kept by default
+        let output = run_op(contents, remove_zlib(None), DepType::Python);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents(
+                "{ pkgs }: {\n  deps = [\n    pkgs.python310\n  ];\n  env = {\n    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [\n    ];\n  };\n}"
+                    .to_string()
+            )
+        );
+
+        let output = run_op(contents, remove_zlib(Some(true)), DepType::Python);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents("{ pkgs }: {\n  deps = [\n    pkgs.python310\n  ];\n}".to_string())
+        );
+
+        // other keys keep the env block around
+        let contents = contents.replace("  };\n}", "    LANG = \"en_US.UTF-8\";\n  };\n}");
+        let output = run_op(&contents, remove_zlib(Some(true)), DepType::Python);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents(
+                "{ pkgs }: {\n  deps = [\n    pkgs.python310\n  ];\n  env = {\n    LANG = \"en_US.UTF-8\";\n  };\n}"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_op_move() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.zlib
+  ];
+}
+"#;
+        let op: Op = serde_json::from_str(
+            r#"{"op": "move", "dep": "pkgs.zlib", "dep_type": "regular", "to_dep_type": "python"}"#,
+        )
+        .unwrap();
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents(
+                r#"{ pkgs }: {
+  deps = [
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}
+"#
+                .to_string()
+            )
+        );
+
+        // moving into the list it's already in leaves the file alone
+        let mut op = Op::new(OpKind::Move, Some("pkgs.zlib".to_string()));
+        op.to_dep_type = Some(DepType::Regular);
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Contents(contents.to_string()));
+    }
+
+    #[test]
+    fn test_run_op_remove_pattern() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.python310
+    pkgs.cowsay
+    pkgs.python310Packages.pip
+  ];
+}
+"#;
+        let output = run_op(
+            contents,
+            Op::new(OpKind::Remove, Some("pkgs.python*".to_string())),
+            DepType::Regular,
+        );
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::ContentsWithData(
+                "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}\n".to_string(),
+                "2".to_string()
+            )
+        );
+
+        let output = run_op(
+            contents,
+            Op::new(OpKind::Remove, Some("pkgs.node*".to_string())),
+            DepType::Regular,
+        );
+        assert!(matches!(output, Err(NixEditorError::DepNotFound(_))));
+
+        let mut op = Op::new(OpKind::Remove, Some("pkgs.node*".to_string()));
+        op.ignore_missing = Some(true);
+        assert_eq!(
+            run_op(contents, op, DepType::Regular).unwrap(),
+            OpOutput::ContentsWithData(contents.to_string(), "0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_op_add_with_comment_round_trip() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+}
+"#;
+        let mut op = Op::new(OpKind::Add, Some("pkgs.SDL2".to_string()));
+        op.comment = Some("Needed for pygame".to_string());
+        // at the end the comment can only be about the dep
+        op.position = Some(AddPosition::Back);
+        let added = match run_op(contents, op, DepType::Regular).unwrap() {
+            OpOutput::Contents(added) => added,
+            output => panic!("expected contents, got {:?}", output),
+        };
+        assert_eq!(
+            added,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    # Needed for pygame
+    pkgs.SDL2
+  ];
+}
+"#
+        );
+
+        let output = run_op(&added, Op::new(OpKind::Get, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec!["pkgs.cowsay".to_string(), "pkgs.SDL2".to_string()])
+        );
+
+        // the comment is only about the dep, so it goes with it
+        let removed = apply_op(
+            &added,
+            OpKind::Remove,
+            Some("pkgs.SDL2".to_string()),
+            DepType::Regular,
+        );
+        assert_eq!(removed.unwrap(), contents);
+    }
+
+    #[test]
+    fn test_run_op_shell_nix() {
+        let contents = r#"{ pkgs ? import <nixpkgs> {} }:
+pkgs.mkShell {
+  buildInputs = [
+    pkgs.hello
+  ];
+}
+"#;
+        let mut op = Op::new(OpKind::Add, Some("pkgs.cowsay".to_string()));
+        op.file_kind = Some(FileKind::Shell);
+        assert_eq!(
+            run_op(contents, op, DepType::Regular).unwrap(),
+            OpOutput::Contents(
+                r#"{ pkgs ? import <nixpkgs> {} }:
+pkgs.mkShell {
+  buildInputs = [
+    pkgs.cowsay
+    pkgs.hello
+  ];
+}
+"#
+                .to_string()
+            )
+        );
+
+        // without the file kind it's read as a replit.nix, which it isn't
+        let op = Op::new(OpKind::Get, None);
+        assert!(matches!(
+            run_op(contents, op, DepType::Regular),
+            Err(NixEditorError::VerifyFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_op_get_all() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+    pkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}"#;
+        let output = run_op(contents, Op::new(OpKind::GetAll, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::DepsByType(vec![
+                (
+                    DepType::Regular,
+                    vec!["pkgs.python38Full".to_string(), "pkgs.cowsay".to_string()]
+                ),
+                (DepType::Python, vec!["pkgs.zlib".to_string()]),
+                (DepType::Node, vec![]),
+            ])
+        );
+
+        // getting doesn't fill in the missing lists
+        let new_contents = apply_op(contents, OpKind::GetAll, None, DepType::Regular);
+        assert_eq!(new_contents.unwrap(), contents);
+    }
+
+    #[test]
+    fn test_run_op_always_sort() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.ncdu
+    # for pygame
+    pkgs.SDL2
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+      pkgs.glib
+    ];
+  };
+}"#;
+        let always_sort = |op: Op| Op {
+            always_sort: Some(true),
+            ..op
+        };
+
+        let op = always_sort(Op::new(OpKind::Add, Some("pkgs.cowsay".to_string())));
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents(
+                r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+    # for pygame
+    pkgs.SDL2
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.glib
+      pkgs.zlib
+    ];
+  };
+}"#
+                .to_string()
+            )
+        );
+
+        // an edit that does nothing doesn't sort either
+        let op = Op {
+            ignore_missing: Some(true),
+            ..always_sort(Op::new(OpKind::Remove, Some("pkgs.htop".to_string())))
+        };
+        let output = run_op(contents, op, DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Contents(contents.to_string()));
+
+        // missing lists aren't filled in
+        let op = always_sort(Op::new(OpKind::Add, Some("pkgs.cowsay".to_string())));
+        let output = run_op(EMPTY_TEMPLATE, op, DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents("{pkgs}: {\n  deps = [\n    pkgs.cowsay\n  ];\n}\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_op_export() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}"#;
+        let output = run_op(contents, Op::new(OpKind::Export, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Export(
+                DepsExport {
+                    deps: vec!["pkgs.cowsay".to_string()],
+                    env: std::collections::BTreeMap::from([
+                        ("LD_LIBRARY_PATH".to_string(), vec![]),
+                        (
+                            "PYTHON_LD_LIBRARY_PATH".to_string(),
+                            vec!["pkgs.zlib".to_string()]
+                        ),
+                    ]),
+                },
+                ExportFormat::Json
+            )
+        );
+
+        // shell.nix has no env block to export
+        let op = Op {
+            file_kind: Some(FileKind::Shell),
+            export_format: Some(ExportFormat::Toml),
+            ..Op::new(OpKind::Export, None)
+        };
+        let output = run_op(
+            "{ pkgs }: pkgs.mkShell {\n  buildInputs = [ pkgs.cowsay ];\n}",
+            op,
+            DepType::Regular,
+        );
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Export(
+                DepsExport {
+                    deps: vec!["pkgs.cowsay".to_string()],
+                    ..Default::default()
+                },
+                ExportFormat::Toml
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_op_get_skips_comments() {
+        let contents = r#"{ pkgs }: {
+  deps = [ pkgs.a # x
+    # y
+    pkgs.b ];
+}"#;
+        let output = run_op(contents, Op::new(OpKind::Get, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec!["pkgs.a".to_string(), "pkgs.b".to_string()])
+        );
+
+        let output = run_op(contents, Op::new(OpKind::Count, None), DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Data("2".to_string()));
+    }
+
+    #[test]
+    fn test_run_op_add_position() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    pkgs.ncdu\n  ];\n}\n";
+        let add = |position, sorted| {
+            let mut op = Op::new(OpKind::Add, Some("pkgs.htop".to_string()));
+            op.position = position;
+            op.sorted = Some(sorted);
+            match run_op(contents, op, DepType::Regular).unwrap() {
+                OpOutput::Contents(new_contents) => new_contents,
+                output => panic!("expected contents, got {:?}", output),
+            }
+        };
+
+        let front =
+            "{ pkgs }: {\n  deps = [\n    pkgs.htop\n    pkgs.cowsay\n    pkgs.ncdu\n  ];\n}\n";
+        let sorted =
+            "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    pkgs.htop\n    pkgs.ncdu\n  ];\n}\n";
+        let back =
+            "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    pkgs.ncdu\n    pkgs.htop\n  ];\n}\n";
+        assert_eq!(add(None, false), front);
+        assert_eq!(add(None, true), sorted);
+        assert_eq!(add(Some(AddPosition::Sorted), false), sorted);
+        assert_eq!(add(Some(AddPosition::Back), false), back);
+        // an explicit position wins over sorted
+        assert_eq!(add(Some(AddPosition::Back), true), back);
+    }
+
+    #[test]
+    fn test_run_op_let_bound_deps() {
+        let contents = r#"{ pkgs }: let
+  deps = [
+    pkgs.cowsay
+  ];
+in {
+  inherit deps;
+  env = {};
+  deps = deps;
+}
+"#;
+        let new_contents = apply_op(
+            contents,
+            OpKind::Add,
+            Some("pkgs.ncdu".to_string()),
+            DepType::Regular,
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{ pkgs }: let
+  deps = [
+    pkgs.ncdu
+    pkgs.cowsay
+  ];
+in {
+  inherit deps;
+  env = {};
+  deps = deps;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_run_op_get_env_keys() {
+        let contents = r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHONBIN = "${pkgs.python38Full}/bin/python3.8";
+    LANG = "en_US.UTF-8";
+  };
+}"#;
+        let output = run_op(
+            contents,
+            Op::new(OpKind::GetEnvKeys, None),
+            DepType::Regular,
+        );
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::EnvKeys(vec!["PYTHONBIN".to_string(), "LANG".to_string()])
+        );
+
+        let output = run_op(
+            EMPTY_TEMPLATE,
+            Op::new(OpKind::GetEnvKeys, None),
+            DepType::Regular,
+        );
+        assert_eq!(output.unwrap(), OpOutput::EnvKeys(vec![]));
+    }
+
+    #[test]
+    fn test_run_op_get_env() {
+        let contents = r#"{ pkgs }: {
+  deps = [];
+  env = {
+    LANG = "en_US.UTF-8";
+  };
+}"#;
+        let get_env = |key: &str| Op {
+            key: Some(key.to_string()),
+            ..Op::new(OpKind::GetEnv, None)
+        };
+
+        let output = run_op(contents, get_env("LANG"), DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Data("en_US.UTF-8".to_string()));
+
+        let output = run_op(contents, get_env("PYTHONBIN"), DepType::Regular);
+        assert_eq!(output.unwrap_err().code(), "env_not_found");
+    }
+
+    #[test]
+    fn test_run_op_attr_set_deps() {
+        let contents = r#"{ pkgs }: {
+  deps = {
+    cowsay = pkgs.cowsay;
+  };
+}"#;
+        let output = run_op(contents, Op::new(OpKind::Get, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Deps(vec!["pkgs.cowsay".to_string()])
+        );
+
+        let new_contents = apply_op(
+            contents,
+            OpKind::Add,
+            Some("pkgs.ncdu".to_string()),
+            DepType::Regular,
+        )
+        .unwrap();
+        assert_eq!(
+            new_contents,
+            "{ pkgs }: {\n  deps = {\n    cowsay = pkgs.cowsay;\n    ncdu = pkgs.ncdu;\n  };\n}"
+        );
+
+        let output = run_op(
+            &new_contents,
+            Op::new(OpKind::Remove, Some("cowsay".to_string())),
+            DepType::Regular,
+        );
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Contents(
+                "{ pkgs }: {\n  deps = {\n    ncdu = pkgs.ncdu;\n  };\n}".to_string()
+            )
+        );
+
+        // ops that only make sense for a list still fail
+        let output = run_op(contents, Op::new(OpKind::Sort, None), DepType::Regular);
+        assert!(matches!(output, Err(NixEditorError::NotAList { .. })));
+    }
+
+    #[test]
+    fn test_run_op_get_pin() {
+        let contents = r#"{ pkgs ? import (fetchTarball "https://example.com/nixpkgs.tar.gz") {} }: {
+  deps = [];
+}"#;
+        let output = run_op(contents, Op::new(OpKind::GetPin, None), DepType::Regular);
+        assert_eq!(
+            output.unwrap(),
+            OpOutput::Pin(Some(Pin {
+                fetcher: "fetchTarball".to_string(),
+                url: Some("https://example.com/nixpkgs.tar.gz".to_string()),
+                rev: None,
+            }))
+        );
+
+        let output = run_op(TEMPLATE, Op::new(OpKind::GetPin, None), DepType::Regular);
+        assert_eq!(output.unwrap(), OpOutput::Pin(None));
+    }
+
+    // replit.nix files in the shapes people write them, for the round trip tests
+    const ROUND_TRIP_CORPUS: &[&str] = &[
+        "{ pkgs }: {\n  deps = [ ];\n}",
+        "{ pkgs }: {\n  deps = [\n  ];\n}\n",
+        "{ pkgs }: {\n  deps = [ pkgs.cowsay ];\n}\n",
+        "{ pkgs }: {\n  deps = [ pkgs.cowsay pkgs.htop ];\n}\n",
+        "{ pkgs }: { deps = with pkgs; [ cowsay htop ]; }",
+        "{pkgs}: {\n  deps = [\n    pkgs.cowsay\n  ];\n}\n",
+        "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    pkgs.htop\n  ];\n}\n",
+        "{ pkgs }: {\n\tdeps = [\n\t\tpkgs.cowsay\n\t];\n}\n",
+        "{ pkgs }: {\r\n  deps = [\r\n    pkgs.cowsay\r\n  ];\r\n}\r\n",
+        "{ pkgs }: {\n  deps = [\n    pkgs.cowsay # says things\n    pkgs.htop\n  ];\n}\n",
+        "{ pkgs }: {\n  deps = [\n    # tools\n    pkgs.cowsay\n\n    # more tools\n    pkgs.htop\n  ];\n}\n",
+        "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    # pkgs.htop\n  ];\n}\n",
+        "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n\n  ];\n}\n",
+        "{ pkgs }: {\n  deps = [\n    pkgs.cowsay ];\n}\n",
+        "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    \"${pkgs.hello}/bin\"\n  ];\n}\n",
+        "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n  env = {\n    LANG = \"en_US.UTF-8\";\n  };\n}\n",
+        "let\n  extra = 1;\nin { pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}\n",
+    ];
+
+    // files that adding a dep reformats, so removing it again can't give back
+    // exactly the same text. From then on they round trip like the rest.
+    const ROUND_TRIP_REFORMATTED: &[&str] = &[
+        // expanded onto multiple lines
+        "{ pkgs }: {\n  deps = [];\n}\n",
+        // the dep sharing the bracket's line gets a line of its own
+        "{ pkgs }: {\n  deps = [ pkgs.cowsay\n  ];\n}\n",
+    ];
+
+    const ROUND_TRIP_DEPS: &[&str] = &["pkgs.ncdu", "pkgs.python3Packages.pip", "pkgs.zzz"];
+
+    fn add_with(contents: &str, dep: &str, position: Option<AddPosition>) -> String {
+        let op = Op {
+            position,
+            ..Op::new(OpKind::Add, Some(dep.to_string()))
+        };
+        match run_op(contents, op, DepType::Regular).unwrap() {
+            OpOutput::Contents(new_contents) => new_contents,
+            output => panic!("expected contents but got {:?}", output),
+        }
+    }
+
+    #[test]
+    fn test_add_then_remove_round_trips() {
+        let positions = [
+            None,
+            Some(AddPosition::Front),
+            Some(AddPosition::Back),
+            Some(AddPosition::Sorted),
+        ];
+        for contents in ROUND_TRIP_CORPUS {
+            for dep in ROUND_TRIP_DEPS {
+                for position in positions {
+                    let added = add_with(contents, dep, position);
+                    assert_ne!(&added, contents, "adding {} to {:?}", dep, contents);
+
+                    let removed = apply_op(
+                        &added,
+                        OpKind::Remove,
+                        Some(dep.to_string()),
+                        DepType::Regular,
+                    )
+                    .unwrap();
+                    assert_eq!(
+                        &removed, contents,
+                        "removing {} ({:?}) from {:?}",
+                        dep, position, added
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_then_remove_round_trips_after_reformatting() {
+        for contents in ROUND_TRIP_REFORMATTED {
+            for dep in ROUND_TRIP_DEPS {
+                let round_trip = |contents: &str| {
+                    let added = add_with(contents, dep, None);
+                    apply_op(
+                        &added,
+                        OpKind::Remove,
+                        Some(dep.to_string()),
+                        DepType::Regular,
+                    )
+                    .unwrap()
+                };
+
+                let reformatted = round_trip(contents);
+                assert_eq!(
+                    round_trip(&reformatted),
+                    reformatted,
+                    "adding and removing {} in {:?}",
+                    dep,
+                    contents
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_is_idempotent() {
+        for contents in ROUND_TRIP_CORPUS.iter().chain(ROUND_TRIP_REFORMATTED) {
+            for dep in ROUND_TRIP_DEPS {
+                let added = add_with(contents, dep, None);
+                assert_eq!(
+                    add_with(&added, dep, None),
+                    added,
+                    "adding {} twice to {:?}",
+                    dep,
+                    contents
+                );
+
+                // getting sees the dep exactly once
+                let output = run_op(&added, Op::new(OpKind::Get, None), DepType::Regular);
+                let deps = match output.unwrap() {
+                    OpOutput::Deps(deps) => deps,
+                    output => panic!("expected deps but got {:?}", output),
+                };
+                let name = dep.trim_start_matches("pkgs.");
+                let count = deps
+                    .iter()
+                    .filter(|got| *got == dep || *got == name)
+                    .count();
+                assert_eq!(count, 1, "getting {} from {:?}", dep, added);
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_op_remove_by_index() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.a\n    pkgs.b\n  ];\n}\n";
+        let op: Op = serde_json::from_str(r#"{"op": "remove", "index": 1}"#).unwrap();
+        assert_eq!(
+            run_op(contents, op, DepType::Regular).unwrap(),
+            OpOutput::Contents("{ pkgs }: {\n  deps = [\n    pkgs.a\n  ];\n}\n".to_string())
+        );
+
+        let op: Op = serde_json::from_str(r#"{"op": "remove", "index": 2}"#).unwrap();
+        assert!(matches!(
+            run_op(contents, op, DepType::Regular),
+            Err(NixEditorError::InvalidOp(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_op_target_path() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}";
+        let mut op = Op::new(OpKind::Add, Some("pkgs.zlib".to_string()));
+        op.target_path = Some("env.LD_PATHS".to_string());
+        let output = run_op(contents, op, DepType::Regular).unwrap();
+        assert_eq!(
+            output,
+            OpOutput::Contents(
+                "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n  env = {\n    LD_PATHS = [\n      pkgs.zlib\n    ];\n  };\n}".to_string()
+            )
+        );
+
+        let mut op = Op::new(OpKind::GetAll, None);
+        op.target_path = Some("env.LD_PATHS".to_string());
+        let res = run_op(contents, op, DepType::Regular);
+        assert!(matches!(res, Err(NixEditorError::InvalidOp(_))));
+    }
+
+    #[test]
+    fn test_run_op_get_with_range() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}";
+        let mut op = Op::new(OpKind::Get, None);
+        op.with_range = Some(true);
+        let range = DepsRange { start: 21, end: 42 };
+        assert_eq!(&contents[range.start..range.end], "[\n    pkgs.cowsay\n  ]");
+        assert_eq!(
+            run_op(contents, op, DepType::Regular).unwrap(),
+            OpOutput::DepsWithRange(vec!["pkgs.cowsay".to_string()], Some(range))
+        );
+
+        // offsets count the \r on each line
+        let mut op = Op::new(OpKind::Get, None);
+        op.with_range = Some(true);
+        assert_eq!(
+            run_op(&contents.replace('\n', "\r\n"), op, DepType::Regular).unwrap(),
+            OpOutput::DepsWithRange(
+                vec!["pkgs.cowsay".to_string()],
+                Some(DepsRange { start: 22, end: 45 })
+            )
+        );
+
+        let mut op = Op::new(OpKind::Get, None);
+        op.with_range = Some(true);
+        assert_eq!(
+            run_op(contents, op, DepType::Python).unwrap(),
+            OpOutput::DepsWithRange(vec![], None)
+        );
+    }
+
+    #[test]
+    fn test_run_op_set_deps() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.htop\n  ];\n}";
+        let mut op = Op::new(OpKind::SetDeps, None);
+        op.deps = Some(vec!["pkgs.ncdu".to_string(), "pkgs.cowsay".to_string()]);
+        let new_contents = match run_op(contents, op, DepType::Regular).unwrap() {
+            OpOutput::Contents(new_contents) => new_contents,
+            output => panic!("unexpected output {:?}", output),
+        };
+        assert_eq!(
+            new_contents,
+            "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    pkgs.ncdu\n  ];\n}"
+        );
+
+        // setting the same deps again changes nothing
+        let mut op = Op::new(OpKind::SetDeps, None);
+        op.deps = Some(vec!["pkgs.cowsay".to_string(), "pkgs.ncdu".to_string()]);
+        assert_eq!(
+            run_op(&new_contents, op, DepType::Regular).unwrap(),
+            OpOutput::Contents(new_contents.clone())
+        );
+
+        let res = run_op(contents, Op::new(OpKind::SetDeps, None), DepType::Regular);
+        assert!(matches!(res, Err(NixEditorError::InvalidOp(_))));
+    }
+
+    #[test]
+    fn test_run_op_env_key() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}";
+        let mut op = Op::new(OpKind::Add, Some("pkgs.gtk3".to_string()));
+        op.env_key = Some("GI_TYPELIB_PATH".to_string());
+        let new_contents = match run_op(contents, op, DepType::Regular).unwrap() {
+            OpOutput::Contents(new_contents) => new_contents,
+            output => panic!("unexpected output {:?}", output),
+        };
+        assert_eq!(
+            new_contents,
+            "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n  env = {\n    GI_TYPELIB_PATH = pkgs.lib.makeLibraryPath [\n      pkgs.gtk3\n    ];\n  };\n}"
+        );
+
+        let mut op = Op::new(OpKind::Get, None);
+        op.env_key = Some("GI_TYPELIB_PATH".to_string());
+        assert_eq!(
+            run_op(&new_contents, op, DepType::Regular).unwrap(),
+            OpOutput::Deps(vec!["pkgs.gtk3".to_string()])
+        );
+
+        let mut op = Op::new(OpKind::Remove, Some("pkgs.gtk3".to_string()));
+        op.env_key = Some("GI_TYPELIB_PATH".to_string());
+        op.prune_empty = Some(true);
+        assert_eq!(
+            run_op(&new_contents, op, DepType::Regular).unwrap(),
+            OpOutput::Contents(contents.to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_op_env_key_invalid() {
+        let contents = "{ pkgs }: {\n  deps = [];\n}";
+        let mut op = Op::new(OpKind::Add, Some("pkgs.gtk3".to_string()));
+        op.env_key = Some("GI_TYPELIB_PATH = null; X".to_string());
+        assert!(matches!(
+            run_op(contents, op, DepType::Regular),
+            Err(NixEditorError::VerifyFailed(_))
+        ));
+
+        let mut op = Op::new(OpKind::GetAll, None);
+        op.env_key = Some("GI_TYPELIB_PATH".to_string());
+        assert!(matches!(
+            run_op(contents, op, DepType::Regular),
+            Err(NixEditorError::InvalidOp(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_op_init() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}";
+        assert_eq!(
+            run_op(contents, Op::new(OpKind::Init, None), DepType::Regular).unwrap(),
+            OpOutput::Contents(EMPTY_TEMPLATE.to_string())
+        );
+
+        assert_eq!(
+            run_op("", Op::new(OpKind::Init, None), DepType::Python).unwrap(),
+            OpOutput::Contents(
+                "{pkgs}: {\n  deps = [];\n  env = {\n    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [];\n  };\n}\n".to_string()
+            )
+        );
+
+        let op = Op {
+            arg_name: Some("nixpkgs".to_string()),
+            ..Op::new(OpKind::Init, None)
+        };
+        assert_eq!(
+            run_op(contents, op, DepType::Regular).unwrap(),
+            OpOutput::Contents("{nixpkgs}: {\n  deps = [];\n}\n".to_string())
+        );
+
+        let op = Op {
+            file_kind: Some(FileKind::Shell),
+            ..Op::new(OpKind::Init, None)
+        };
+        assert!(matches!(
+            run_op(contents, op, DepType::Regular),
+            Err(NixEditorError::InvalidOp(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_op_merge() {
+        let contents = "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}";
+        let reference =
+            "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n    pkgs.ncdu\n    pkgs.htop\n  ];\n}";
+        let merge = |apply| Op {
+            reference: Some(reference.to_string()),
+            apply: Some(apply),
+            ..Op::new(OpKind::Merge, None)
+        };
+
+        assert_eq!(
+            run_op(contents, merge(false), DepType::Regular).unwrap(),
+            OpOutput::Deps(vec!["pkgs.ncdu".to_string(), "pkgs.htop".to_string()])
+        );
+        assert_eq!(
+            run_op(contents, merge(true), DepType::Regular).unwrap(),
+            OpOutput::ContentsWithData(
+                "{ pkgs }: {\n  deps = [\n    pkgs.ncdu\n    pkgs.htop\n    pkgs.cowsay\n  ];\n}"
+                    .to_string(),
+                "pkgs.ncdu,pkgs.htop".to_string()
+            )
+        );
+
+        // nothing to add from a reference that's behind
+        assert_eq!(
+            run_op(
+                reference,
+                Op {
+                    reference: Some(contents.to_string()),
+                    ..merge(true)
+                },
+                DepType::Regular
+            )
+            .unwrap(),
+            OpOutput::ContentsWithData(reference.to_string(), "".to_string())
+        );
+
+        let deny = Op {
+            deny: Some(vec!["htop".to_string()]),
+            ..merge(true)
+        };
+        assert_eq!(
+            run_op(contents, deny, DepType::Regular).unwrap_err().code(),
+            "rejected"
+        );
+
+        let res = run_op(contents, Op::new(OpKind::Merge, None), DepType::Regular);
+        assert!(matches!(res, Err(NixEditorError::InvalidOp(_))));
+    }
+
+    #[test]
+    fn test_run_op_concatenated_deps() {
+        let contents = "{ pkgs }: let\n  baseDeps = [ pkgs.cowsay ];\nin {\n  deps = baseDeps ++ [\n    pkgs.extra\n  ];\n}";
+        let add = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+        let new_contents = match run_op(contents, add, DepType::Regular).unwrap() {
+            OpOutput::Contents(new_contents) => new_contents,
+            output => panic!("unexpected output {:?}", output),
+        };
+        assert_eq!(
+            new_contents,
+            "{ pkgs }: let\n  baseDeps = [ pkgs.cowsay ];\nin {\n  deps = baseDeps ++ [\n    pkgs.ncdu\n    pkgs.extra\n  ];\n}"
+        );
+
+        // get only sees the literal list, and says so
+        assert_eq!(
+            run_op(&new_contents, Op::new(OpKind::Get, None), DepType::Regular).unwrap(),
+            OpOutput::PartialDeps(
+                vec!["pkgs.ncdu".to_string(), "pkgs.extra".to_string()],
+                None
+            )
+        );
+        let mut op = Op::new(OpKind::Get, None);
+        op.with_range = Some(true);
+        assert!(matches!(
+            run_op(&new_contents, op, DepType::Regular).unwrap(),
+            OpOutput::PartialDeps(_, Some(_))
+        ));
+
+        let remove = Op::new(OpKind::Remove, Some("pkgs.ncdu".to_string()));
+        assert_eq!(
+            run_op(&new_contents, remove, DepType::Regular).unwrap(),
+            OpOutput::Contents(contents.to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_op_concatenated_with_pkgs() {
+        let contents = "{ pkgs }: {\n  deps = with pkgs; [ cowsay ] ++ [ htop ];\n}";
+        let add = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+        let new_contents = match run_op(contents, add, DepType::Regular).unwrap() {
+            OpOutput::Contents(new_contents) => new_contents,
+            output => panic!("unexpected output {:?}", output),
+        };
+        assert_eq!(
+            new_contents,
+            "{ pkgs }: {\n  deps = with pkgs; [ cowsay ] ++ [ ncdu htop ];\n}"
+        );
+
+        let remove = Op::new(OpKind::Remove, Some("pkgs.htop".to_string()));
+        assert_eq!(
+            run_op(&new_contents, remove, DepType::Regular).unwrap(),
+            OpOutput::Contents(
+                "{ pkgs }: {\n  deps = with pkgs; [ cowsay ] ++ [ ncdu ];\n}".to_string()
+            )
+        );
+    }
+}
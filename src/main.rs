@@ -1,492 +1,3479 @@
-mod adder;
-mod remover;
-mod verify_getter;
-
-use anyhow::Result;
-use rnix::SyntaxNode;
-
+#[cfg(not(feature = "no-fs"))]
 use std::fs;
-use std::{env, io, io::prelude::*, path::Path};
+#[cfg(all(unix, not(feature = "no-fs")))]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(all(unix, not(feature = "no-fs")))]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+#[cfg(all(unix, not(feature = "no-fs")))]
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+#[cfg(not(feature = "no-fs"))]
+use std::time::Instant;
+use std::{env, io, io::prelude::*, path::Path, process};
 
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
+use serde_json::{from_str, json, to_string, Value};
 
 use clap::{ArgEnum, Parser};
 
-use crate::adder::add_dep;
-use crate::remover::remove_dep;
-use crate::verify_getter::verify_get;
+#[cfg(not(feature = "no-fs"))]
+use nix_editor::{check_parses, EMPTY_TEMPLATE};
+use nix_editor::{
+    dep_type_name, partial_deps_json, text_delta, unified_diff, AddPosition, DepType, ExportFormat,
+    FileKind, NixEditor, NixEditorError, Op, OpKind, OpOutput, Result, VerifyOptions,
+};
 
 #[derive(Parser, Debug, Default, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    // dep to add
-    #[clap(short, long, value_parser)]
+    // dep to add. Like --remove, --get and --dep-type, it can also be given in
+    // the environment, e.g. NIX_EDITOR_ADD, for when passing args is awkward.
+    // The flag wins when both are set
+    #[clap(short, long, value_parser, env = "NIX_EDITOR_ADD")]
     add: Option<String>,
 
     // dep to remove
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "NIX_EDITOR_REMOVE")]
     remove: Option<String>,
 
-    // print current deps
-    #[clap(short, long, value_parser, default_value = "false")]
+    // print current deps. NIX_EDITOR_GET also takes 1 or 0, yes or no
+    #[clap(
+        short,
+        long,
+        value_parser = clap::builder::BoolishValueParser::new(),
+        default_value = "false",
+        env = "NIX_EDITOR_GET"
+    )]
     get: bool,
 
-    // filepath for replit.nix file
-    #[clap(short, long, value_parser)]
-    path: Option<String>,
+    // print the deps of every dep type, keyed by dep type
+    #[clap(long, value_parser, default_value = "false")]
+    get_all: bool,
+
+    // print whether the file parses and which parts of it are there
+    #[clap(long, value_parser, default_value = "false")]
+    info: bool,
+
+    // print every list of deps as one json or toml document
+    #[clap(long, arg_enum)]
+    export: Option<ExportFormat>,
+
+    // write a fresh replit.nix, with the env block for --dep-type python or node
+    #[clap(long, value_parser, default_value = "false")]
+    init: bool,
+
+    // with --init, replace a file that's already there
+    #[clap(long, value_parser, default_value = "false")]
+    force: bool,
+
+    // print the deps this reference file has that the file doesn't
+    #[clap(long, value_parser)]
+    merge_from: Option<String>,
+
+    // with --merge-from, add the missing deps to the file
+    #[clap(long, value_parser, default_value = "false")]
+    apply: bool,
+
+    // filepath for replit.nix file. --get and --get-all can be given several
+    // (e.g. a shell glob), and respond with what each file gave
+    #[clap(short, long, value_parser, multiple_values = true)]
+    path: Vec<String>,
+
+    // how responses are printed
+    #[clap(long, arg_enum, default_value = "json")]
+    format: OutputFormat,
 
-    // human readable output
-    #[clap(short, long, value_parser, default_value = "false")]
+    // old spelling of --format human
+    #[clap(short, long, value_parser, default_value = "false", hide = true)]
     human: bool,
 
     // dep type - used for setting special dep types in the replit.nix file
-    #[clap(short, long, arg_enum, default_value = "regular")]
+    #[clap(
+        short,
+        long,
+        arg_enum,
+        default_value = "regular",
+        env = "NIX_EDITOR_DEP_TYPE"
+    )]
     dep_type: DepType,
 
-    // verbose output
-    #[clap(short, long, value_parser, default_value = "false")]
-    verbose: bool,
+    // verbose output on stderr, -vv also prints the syntax tree each op runs on
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
 
     // Whether or not to write this value directly to the file,
     // or just print it as part of the return message
     #[clap(long, value_parser, default_value = "false")]
     return_output: bool,
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-enum OpKind {
-    #[serde(rename = "add")]
-    Add,
+    // never write to the file, print what the new contents would be instead
+    #[clap(long, value_parser, default_value = "false")]
+    dry_run: bool,
 
-    #[serde(rename = "remove")]
-    Remove,
+    // return edits as the change to the file's text (offset, deleted length and
+    // inserted text) instead of the full new contents
+    #[clap(long, value_parser, default_value = "false")]
+    delta: bool,
 
-    #[serde(rename = "get")]
-    Get,
-}
+    // return edits as a unified diff of the file instead of the full new contents
+    #[clap(long, value_parser, default_value = "false")]
+    diff: bool,
+
+    // save the previous contents to replit.nix.bak before writing changes
+    #[clap(long, value_parser, default_value = "false")]
+    backup: bool,
+
+    // write the new contents here instead of back to --path, which is then only read
+    #[clap(long, value_parser)]
+    output: Option<String>,
+
+    // fail with file_not_found when the file doesn't exist instead of starting
+    // from an empty one
+    #[clap(long, value_parser, default_value = "false")]
+    no_create: bool,
+
+    // refuse to read a file larger than this many bytes, 4 MiB if not set
+    #[clap(long, value_parser)]
+    max_file_size: Option<u64>,
+
+    // give up on an op that takes longer than this many milliseconds to parse
+    // the contents and edit them. Off by default, except with --serve where it's 5000. 0 turns it off
+    #[clap(long, value_parser)]
+    parse_timeout: Option<u64>,
+
+    // how get returns the deps
+    #[clap(long, arg_enum, default_value = "joined")]
+    get_format: GetFormat,
+
+    // return the deps from get in alphabetical order
+    #[clap(long, value_parser, default_value = "false")]
+    sort_get: bool,
+
+    // return string deps from get without their quotes
+    #[clap(long, value_parser, default_value = "false")]
+    unquote: bool,
+
+    // return deps from get without their pkgs. prefix, e.g. xorg.libX11 for pkgs.xorg.libX11
+    #[clap(long, value_parser, default_value = "false")]
+    names_only: bool,
+
+    // respond to get with an object holding the deps and the byte offsets of their list
+    #[clap(long, value_parser, default_value = "false")]
+    with_range: bool,
+
+    // insert added deps in alphabetical order when the list is already sorted
+    #[clap(long, value_parser, default_value = "false")]
+    sorted: bool,
+
+    // refuse to add deps matching this pattern, where * matches anything. Can be
+    // given more than once
+    #[clap(long, value_parser)]
+    deny: Vec<String>,
+
+    // refuse to add deps that don't match one of these patterns
+    #[clap(long, value_parser)]
+    allow_only: Vec<String>,
+
+    // sort every deps list after each edit, so the order never depends on how deps were added
+    #[clap(long, value_parser, default_value = "false")]
+    always_sort: bool,
+
+    // where added deps go in the list, front if not set (or sorted with --sorted)
+    #[clap(long, arg_enum)]
+    position: Option<AddPosition>,
+
+    // remove every occurrence of the dep instead of just the first
+    #[clap(long, value_parser, default_value = "false")]
+    all: bool,
+
+    // removing a dep that isn't there succeeds instead of failing with dep_not_found
+    #[clap(long, value_parser, default_value = "false")]
+    ignore_missing: bool,
+
+    // when removing a python or node dep empties its library path list, remove
+    // the list's env key too, and the env block if nothing is left in it
+    #[clap(long, value_parser, default_value = "false")]
+    prune_empty: bool,
+
+    // how many spaces to indent a dep added to an empty list, deps added to a list
+    // with entries already in it line up with those
+    #[clap(long, value_parser)]
+    indent: Option<usize>,
+
+    // add the dep under the first comment in the list containing this, e.g. "matplotlib"
+    #[clap(long, value_parser)]
+    group: Option<String>,
+
+    // comment to put on the line above an added dep, e.g. "Needed for pygame"
+    #[clap(long, value_parser)]
+    comment: Option<String>,
+
+    // name of the function argument nixpkgs is passed in as, for files that don't call it pkgs
+    #[clap(long, value_parser)]
+    arg_name: Option<String>,
+
+    // what kind of file the deps are in, which also picks the default path
+    #[clap(long, arg_enum, default_value = "replit")]
+    file_kind: FileKind,
+
+    // dotted path of keys to the list to edit instead of the dep type's list,
+    // e.g. nativeBuildInputs or env.PYTHON_LD_LIBRARY_PATH
+    #[clap(long, value_parser)]
+    target_path: Option<String>,
+
+    // env key whose makeLibraryPath list to edit instead of the dep type's list,
+    // e.g. GI_TYPELIB_PATH
+    #[clap(long, value_parser)]
+    env_key: Option<String>,
+
+    // read the replit.nix contents from stdin and respond with the result instead of
+    // touching any file
+    #[clap(long, value_parser, default_value = "false")]
+    content_stdin: bool,
+
+    // read all of stdin as one JSON array of ops, which may span several lines,
+    // and run it as a single batch instead of reading an op per line
+    #[clap(long, value_parser, default_value = "false")]
+    json_input_array: bool,
+
+    // formatter command to run the new contents through before writing them,
+    // e.g. "nixpkgs-fmt" or "alejandra -q -"
+    #[clap(long, value_parser)]
+    format_output: Option<String>,
+
+    // skip parsing the new contents again before writing them. By default a
+    // file that would no longer parse is never written
+    #[clap(long, value_parser, default_value = "false")]
+    no_reparse: bool,
 
-#[derive(Serialize, Deserialize, ArgEnum, Clone, Copy, Debug)]
-pub enum DepType {
-    #[serde(rename = "regular")]
-    Regular,
+    // a single op as a JSON object, like one line of the stdin protocol. It's run,
+    // its response printed and the process exits
+    #[clap(long, value_parser)]
+    op_json: Option<String>,
 
-    #[serde(rename = "python")]
-    Python,
+    // listen on this unix socket and handle ops sent over it instead of reading stdin
+    #[clap(long, value_parser)]
+    serve: Option<String>,
 }
 
-impl Default for DepType {
-    fn default() -> Self {
-        DepType::Regular
+impl Args {
+    fn output_format(&self) -> OutputFormat {
+        match self.human {
+            true => OutputFormat::Human,
+            false => self.format,
+        }
+    }
+
+    fn file_size_limit(&self) -> u64 {
+        self.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE)
+    }
+
+    fn parse_time_limit(&self) -> Option<Duration> {
+        let default = self.serve.as_ref().map(|_| DEFAULT_SERVE_PARSE_TIMEOUT);
+        self.parse_timeout
+            .or(default)
+            .filter(|&timeout| timeout > 0)
+            .map(Duration::from_millis)
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct Op {
-    op: OpKind,
-    dep_type: Option<DepType>,
-    dep: Option<String>,
+// Plenty for any replit.nix, while keeping a huge file from being read into memory and parsed
+const DEFAULT_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+// A socket can be sent anything, so one request shouldn't be able to hold up
+// the rest for long
+const DEFAULT_SERVE_PARSE_TIMEOUT: u64 = 5000;
+
+#[derive(ArgEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    // a JSON Res per line
+    #[default]
+    Json,
+
+    // the status followed by the data
+    Human,
+
+    // just the data, with lists one item per line. errors go to stderr
+    Plain,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, Default)]
+enum GetFormat {
+    // a single comma separated string
+    #[default]
+    Joined,
+
+    // a JSON array of strings
+    JsonArray,
 }
 
 #[derive(Serialize, Deserialize)]
 struct Res {
+    // the id of the op this is a response to, if it had one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Value>,
+
     status: String,
-    data: Option<String>,
+    data: Option<Value>,
+
+    // machine readable error kind, only set on errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+
+    // in a batch, whether an op that edits the file changed it. False when it
+    // had nothing to do, e.g. adding a dep that's already there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed: Option<bool>,
+}
+
+// What one of the ops run together against a file responds with
+#[derive(Debug, PartialEq)]
+struct OpResponse {
+    data: Option<Value>,
+    // only set for ops that edit the file, whether this op changed the contents
+    changed: Option<bool>,
+}
+
+// A single op read from stdin or a socket, optionally naming the file it's for
+#[derive(Deserialize)]
+struct Request {
+    // edit this file instead of the one from --path
+    path: Option<String>,
+
+    #[serde(flatten)]
+    op: Op,
 }
 
 fn main() {
     // handle command line args
     let args = Args::parse();
-    real_main(&mut io::stdout(), args)
+    let exit_code = real_main(&mut io::stdout(), &mut io::stderr(), args);
+    process::exit(exit_code);
 }
 
-fn real_main<W: io::Write>(stdout: &mut W, args: Args) {
-    let replit_nix_file = "./replit.nix";
+// Returns the exit code for the process
+fn real_main<W: io::Write, E: io::Write>(stdout: &mut W, stderr: &mut E, args: Args) -> i32 {
+    let replit_nix_file = format!("./{}", args.file_kind.file_name());
     let default_replit_nix_filepath: String = match env::var("REPL_HOME") {
         Ok(repl_home) => Path::new(repl_home.as_str())
-            .join(replit_nix_file)
+            .join(&replit_nix_file)
             .to_str()
             .unwrap()
             .to_string(),
-        Err(_) => replit_nix_file.to_string(),
+        Err(_) => replit_nix_file,
     };
 
-    let replit_nix_filepath = args.path.unwrap_or_else(|| default_replit_nix_filepath);
+    let replit_nix_filepath = args
+        .path
+        .first()
+        .cloned()
+        .unwrap_or(default_replit_nix_filepath);
+
+    let format = args.output_format();
+    let verbose = args.verbose > 0;
 
-    let human_readable = args.human;
-    let verbose = args.verbose;
+    if args.path.len() > 1 {
+        if verbose {
+            writeln!(stderr, "reading {} files", args.path.len()).unwrap();
+        }
+
+        return perform_on_paths(stdout, stderr, &args);
+    }
 
     if args.get {
         if verbose {
-            writeln!(stdout, "get_dep").unwrap();
+            writeln!(stderr, "get_dep").unwrap();
         }
 
-        let (status, data) = perform_op(
-            stdout,
-            OpKind::Get,
-            None,
-            args.dep_type,
+        let res = perform_flag_op(
+            stderr,
+            with_arg_defaults(Op::new(OpKind::Get, None), &args),
             &replit_nix_filepath,
-            verbose,
-            args.return_output,
+            &args,
         );
-        send_res(stdout, &status, data, human_readable);
-        return;
+        return send_op_res(stdout, stderr, res, format);
     }
 
-    // if user explicitly passes in a add or remove dep, then we only handle that specific op
-    if let Some(add_dep) = args.add {
+    if args.get_all {
         if verbose {
-            writeln!(stdout, "add_dep").unwrap();
+            writeln!(stderr, "get_all").unwrap();
         }
 
-        let (status, data) = perform_op(
-            stdout,
-            OpKind::Add,
-            Some(add_dep),
-            args.dep_type,
+        let res = perform_flag_op(
+            stderr,
+            with_arg_defaults(Op::new(OpKind::GetAll, None), &args),
             &replit_nix_filepath,
-            verbose,
-            args.return_output,
+            &args,
         );
-        send_res(stdout, &status, data, human_readable);
-        return;
+        return send_op_res(stdout, stderr, res, format);
     }
 
-    if let Some(remove_dep) = args.remove {
+    if args.info {
         if verbose {
-            writeln!(stdout, "remove_dep").unwrap();
+            writeln!(stderr, "info").unwrap();
         }
 
-        let (status, data) = perform_op(
-            stdout,
-            OpKind::Remove,
-            Some(remove_dep),
-            args.dep_type,
+        let res = perform_flag_op(
+            stderr,
+            with_arg_defaults(Op::new(OpKind::Info, None), &args),
             &replit_nix_filepath,
-            verbose,
-            args.return_output,
+            &args,
         );
-        send_res(stdout, &status, data, human_readable);
-        return;
+        return send_op_res(stdout, stderr, res, format);
     }
 
-    if verbose {
-        writeln!(stdout, "reading from stdin").unwrap();
+    if let Some(export_format) = args.export {
+        if verbose {
+            writeln!(stderr, "export").unwrap();
+        }
+
+        let op = Op {
+            export_format: Some(export_format),
+            ..Op::new(OpKind::Export, None)
+        };
+        let res = perform_flag_op(
+            stderr,
+            with_arg_defaults(op, &args),
+            &replit_nix_filepath,
+            &args,
+        );
+        return send_op_res(stdout, stderr, res, format);
     }
 
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        match line {
-            Ok(line) => {
-                let json: Op = match from_str(&line) {
-                    Ok(json_val) => json_val,
-                    Err(_) => {
-                        send_res(
-                            stdout,
-                            "error",
-                            Some("Invalid JSON".to_string()),
-                            human_readable,
-                        );
-                        continue;
-                    }
-                };
-
-                let (status, data) = perform_op(
-                    stdout,
-                    json.op,
-                    json.dep,
-                    json.dep_type.unwrap_or(args.dep_type),
-                    &replit_nix_filepath,
-                    verbose,
-                    args.return_output,
-                );
-                send_res(stdout, &status, data, human_readable);
-            }
-            Err(_) => {
-                send_res(
-                    stdout,
-                    "error",
-                    Some("Could not read stdin".to_string()),
-                    human_readable,
-                );
-            }
+    if args.init {
+        if verbose {
+            writeln!(stderr, "init").unwrap();
         }
+
+        let res = perform_flag_op(
+            stderr,
+            with_arg_defaults(Op::new(OpKind::Init, None), &args),
+            &replit_nix_filepath,
+            &args,
+        );
+        return send_op_res(stdout, stderr, res, format);
     }
-}
 
-const EMPTY_TEMPLATE: &str = r#"{pkgs}: {
-  deps = [];
-}
-"#;
+    if let Some(merge_from) = args.merge_from.clone() {
+        if verbose {
+            writeln!(stderr, "merge from {}", merge_from).unwrap();
+        }
 
-fn perform_op<W: io::Write>(
-    stdout: &mut W,
-    op: OpKind,
-    dep: Option<String>,
-    dep_type: DepType,
-    replit_nix_filepath: &str,
-    verbose: bool,
-    return_output: bool,
-) -> (String, Option<String>) {
-    if verbose {
-        writeln!(stdout, "perform_op: {:?} {:?}", op, dep).unwrap();
+        let res = read_file(&merge_from, &args).and_then(|reference| {
+            let op = Op {
+                reference: Some(reference),
+                ..Op::new(OpKind::Merge, None)
+            };
+            perform_flag_op(
+                stderr,
+                with_arg_defaults(op, &args),
+                &replit_nix_filepath,
+                &args,
+            )
+        });
+        return send_op_res(stdout, stderr, res, format);
     }
 
-    // read replit.nix file
-    let contents = match fs::read_to_string(replit_nix_filepath) {
-        Ok(contents) => contents,
-        // if replit.nix doesn't exist start with an empty one
-        Err(err) if err.kind() == io::ErrorKind::NotFound => EMPTY_TEMPLATE.to_string(),
-        Err(_) => {
-            return (
-                "error".to_string(),
-                Some(format!("error: reading file - {:?}", &replit_nix_filepath)),
-            )
+    // swapping one dep for another, both ops run against one read and one parse
+    // of the file, remove first, and the file is written once
+    if let (Some(add_dep), Some(remove_dep)) = (args.add.clone(), args.remove.clone()) {
+        if verbose {
+            writeln!(stderr, "remove_dep and add_dep").unwrap();
         }
-    };
 
-    let root = rnix::Root::parse(&contents).syntax().clone_for_update();
+        let ops = vec![
+            with_arg_defaults(Op::new(OpKind::Remove, Some(remove_dep)), &args),
+            with_arg_defaults(Op::new(OpKind::Add, Some(add_dep)), &args),
+        ];
+        let results = perform_flag_ops(stderr, ops, &replit_nix_filepath, &args);
+        return send_batch_res(stdout, stderr, results, vec![None, None], &args);
+    }
 
-    let deps_list = match verify_get(&root, dep_type) {
-        Ok(deps_list) => deps_list,
-        Err(_) => {
-            return (
-                "error".to_string(),
-                Some("Could not verify and get".to_string()),
-            );
+    // if user explicitly passes in a add or remove dep, then we only handle that specific op
+    if let Some(add_dep) = args.add.clone() {
+        if verbose {
+            writeln!(stderr, "add_dep").unwrap();
         }
-    };
 
-    let op_res = match op {
-        OpKind::Add => add_dep(deps_list, dep).map(|_| root.to_string()),
-        OpKind::Remove => remove_dep(&contents, deps_list.node, dep),
-        OpKind::Get => {
-            let deps = match get_deps(deps_list.node) {
-                Ok(deps) => deps,
-                Err(_) => {
-                    return ("error".to_string(), Some("Could not get deps".to_string()));
-                }
-            };
-            return ("success".to_string(), Some(deps.join(",")));
+        let res = perform_flag_op(
+            stderr,
+            with_arg_defaults(Op::new(OpKind::Add, Some(add_dep)), &args),
+            &replit_nix_filepath,
+            &args,
+        );
+        return send_op_res(stdout, stderr, res, format);
+    }
+
+    if let Some(remove_dep) = args.remove.clone() {
+        if verbose {
+            writeln!(stderr, "remove_dep").unwrap();
         }
-    };
 
-    let new_contents = match op_res {
-        Ok(new_contents) => new_contents,
-        Err(_) => {
-            return (
-                "error".to_string(),
-                Some("Could not perform op".to_string()),
-            );
+        let res = perform_flag_op(
+            stderr,
+            with_arg_defaults(Op::new(OpKind::Remove, Some(remove_dep)), &args),
+            &replit_nix_filepath,
+            &args,
+        );
+        return send_op_res(stdout, stderr, res, format);
+    }
+
+    if let Some(op_json) = args.op_json.clone() {
+        if verbose {
+            writeln!(stderr, "op_json").unwrap();
         }
-    };
 
-    if return_output {
-        return ("success".to_string(), Some(new_contents));
+        return handle_op_json(stdout, stderr, &op_json, &replit_nix_filepath, &args);
     }
 
-    if new_contents == contents {
-        return ("success".to_string(), None);
+    if args.content_stdin {
+        // stdin holds the file, so the op can only come from the command line
+        let res = error_res(
+            "--content-stdin needs one of --add, --remove, --get, --get-all or --op-json"
+                .to_string(),
+        );
+        return send_res(stdout, stderr, &res, format);
     }
 
-    // write new replit.nix file
-    match fs::write(&replit_nix_filepath, new_contents) {
-        Ok(_) => ("success".to_string(), None),
-        Err(err) => (
-            "error".to_string(),
-            Some(format!(
-                "Could not write to file {}: {}",
-                replit_nix_filepath, err
-            )),
-        ),
+    if args.json_input_array {
+        if verbose {
+            writeln!(stderr, "reading a JSON array of ops from stdin").unwrap();
+        }
+
+        let input = match read_limited(io::stdin(), "stdin", args.file_size_limit()) {
+            Ok(input) => input,
+            Err(err) => return send_op_res(stdout, stderr, Err(err), format),
+        };
+        return handle_json_array(stdout, stderr, &input, &replit_nix_filepath, &args);
     }
-}
 
-fn send_res<W: io::Write>(
-    stdout: &mut W,
-    status: &str,
-    data: Option<String>,
-    human_readable: bool,
-) {
-    if human_readable {
-        let mut out = status.to_owned();
+    if let Some(socket_path) = args.serve.clone() {
+        if verbose {
+            writeln!(stderr, "serving on {}", socket_path).unwrap();
+        }
+
+        // serving only ever stops when it fails
+        let err = match serve(&socket_path, &replit_nix_filepath, &args) {
+            Ok(()) => return 0,
+            Err(err) => err,
+        };
+        let res = error_res(format!("Could not serve on {}: {}", socket_path, err));
+        return send_res(stdout, stderr, &res, format);
+    }
+
+    if verbose {
+        writeln!(stderr, "reading from stdin").unwrap();
+    }
 
-        if let Some(data) = data {
-            out += &(": ".to_string() + &data);
+    // the first op that fails decides the exit code, later ones still run
+    let mut exit_code = 0;
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line_exit_code = match line {
+            Ok(line) => handle_line(stdout, stderr, &line, &replit_nix_filepath, &args),
+            Err(_) => send_res(
+                stdout,
+                stderr,
+                &error_res("Could not read stdin".to_string()),
+                format,
+            ),
+        };
+        if exit_code == 0 {
+            exit_code = line_exit_code;
         }
-        writeln!(stdout, "{}", out).unwrap();
-        return;
     }
 
-    let res = Res {
-        status: status.to_string(),
-        data,
-    };
+    exit_code
+}
 
-    let json = match to_string(&res) {
-        Ok(json) => json,
-        Err(_) => {
-            if human_readable {
-                writeln!(stdout, "error: Could not serialize to JSON").unwrap();
-            } else {
-                let err_msg = r#"{"status": "error", "data": "Could not serialize to JSON"}"#;
-                writeln!(stdout, "{}", err_msg).unwrap();
-            }
-            return;
+// Runs get (or get_all) on each file in --path and responds with an object of
+// each file's response keyed by its path. A file that fails only fails its
+// own entry, the first one to fail decides the exit code.
+fn perform_on_paths<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    args: &Args,
+) -> i32 {
+    let format = args.output_format();
+    let op_kind = match (args.get, args.get_all) {
+        (true, _) => OpKind::Get,
+        (_, true) => OpKind::GetAll,
+        _ => {
+            let res = error_res(
+                "several --path values only work with --get or --get-all, files can only be edited one at a time"
+                    .to_string(),
+            );
+            return send_res(stdout, stderr, &res, format);
         }
     };
 
-    writeln!(stdout, "{}", json).unwrap();
+    let mut code = 0;
+    let mut responses = serde_json::Map::new();
+    for path in &args.path {
+        let op = with_arg_defaults(Op::new(op_kind, None), args);
+        let res = op_res(perform_op(stderr, op, path, args), None);
+        if code == 0 {
+            code = exit_code(&res);
+        }
+        responses.insert(path.clone(), serde_json::to_value(&res).unwrap());
+    }
+
+    print_res(
+        stdout,
+        stderr,
+        &op_res(Ok(Some(Value::Object(responses))), None),
+        format,
+    );
+    code
 }
 
-fn get_deps(deps_list: SyntaxNode) -> Result<Vec<String>> {
-    Ok(deps_list
-        .children()
-        .map(|child| child.text().to_string())
-        .collect())
+// Handles one line of the protocol: either a single op or an array of ops run as one batch.
+// Returns the exit code for what happened.
+fn handle_line<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    line: &str,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> i32 {
+    // a line holding an array of ops is run as one batch
+    if line.trim_start().starts_with('[') {
+        return perform_batch(stdout, stderr, line, replit_nix_filepath, args);
+    }
+
+    handle_op_json(stdout, stderr, line, replit_nix_filepath, args)
 }
 
-#[cfg(test)]
-mod integration_tests {
-    use super::*;
+// Runs input, which has to be a JSON array of ops, as one batch, see run_ops
+fn handle_json_array<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    input: &str,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> i32 {
+    if !input.trim_start().starts_with('[') {
+        let res = error_res("--json-input-array expects a JSON array of ops".to_string());
+        return send_res(stdout, stderr, &res, args.output_format());
+    }
 
-    const TEMPLATE: &str = r#"{pkgs}: {
-  deps = [
-    pkgs.cowsay
-  ];
+    perform_batch(stdout, stderr, input, replit_nix_filepath, args)
 }
-"#;
 
-    #[test]
-    fn test_integration_makes_template_if_missing() {
-        let dir = tempfile::tempdir().unwrap();
-        let repl_nix_file = dir.path().join("replit.nix");
-        env::set_var("REPL_HOME", dir.path().display().to_string());
+// Runs a single op given as JSON, along with the path to run it on if the
+// request has one, and responds with the result
+fn handle_op_json<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    json: &str,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> i32 {
+    let request: Request = match from_str(json) {
+        Ok(request) => request,
+        Err(_) => {
+            return send_res(
+                stdout,
+                stderr,
+                &error_res("Invalid JSON".to_string()),
+                args.output_format(),
+            );
+        }
+    };
 
-        let args = Args {
-            add: Some("pkgs.ncdu".to_string()),
-            ..Default::default()
+    let filepath = request
+        .path
+        .unwrap_or_else(|| replit_nix_filepath.to_string());
+    let id = request.op.id.clone();
+    let res = perform_flag_op(stderr, with_arg_defaults(request.op, args), &filepath, args);
+    send_res(stdout, stderr, &op_res(res, id), args.output_format())
+}
+
+// Listens on a unix socket and handles each connection like stdin, one line per
+// request. The file is read fresh for every request so edits made by anyone
+// else in the meantime are picked up.
+#[cfg(all(unix, not(feature = "no-fs")))]
+fn serve(socket_path: &str, replit_nix_filepath: &str, args: &Args) -> io::Result<()> {
+    // a socket left behind by a previous run would make binding fail. Anything
+    // else at the path isn't ours to delete
+    if let Ok(metadata) = fs::symlink_metadata(socket_path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a socket", socket_path),
+            ));
+        }
+        fs::remove_file(socket_path)?;
+    }
+    let listener = UnixListener::bind(socket_path)?;
+
+    // requests from different connections can touch the same file, so only one runs at a time
+    let lock = Arc::new(Mutex::new(()));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
         };
-        real_main(&mut io::stdout(), args);
 
-        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+        let lock = lock.clone();
+        let replit_nix_filepath = replit_nix_filepath.to_string();
+        let args = args.clone();
+        thread::spawn(move || handle_connection(stream, &replit_nix_filepath, &args, &lock));
+    }
 
-        assert_eq!(
-            r#"{pkgs}: {
-  deps = [
-    pkgs.ncdu
-  ];
+    Ok(())
 }
-"#,
-            contents
-        );
 
-        drop(repl_nix_file);
-        dir.close().unwrap();
-    }
+#[cfg(all(not(unix), not(feature = "no-fs")))]
+fn serve(_socket_path: &str, _replit_nix_filepath: &str, _args: &Args) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets aren't supported on this platform",
+    ))
+}
 
-    #[test]
-    fn test_integration_makes_python_ld_library_if_missing() {
-        let dir = tempfile::tempdir().unwrap();
-        let repl_nix_file = dir.path().join("replit.nix");
+// binding a unix socket creates a file for it
+#[cfg(feature = "no-fs")]
+fn serve(_socket_path: &str, _replit_nix_filepath: &str, _args: &Args) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "this build has no filesystem access",
+    ))
+}
 
-        fs::write(repl_nix_file.as_os_str(), EMPTY_TEMPLATE.as_bytes()).unwrap();
+#[cfg(all(unix, not(feature = "no-fs")))]
+fn handle_connection(stream: UnixStream, replit_nix_filepath: &str, args: &Args, lock: &Mutex<()>) {
+    let reader = match stream.try_clone() {
+        Ok(reader) => io::BufReader::new(reader),
+        Err(_) => return,
+    };
+    let mut writer = stream;
 
-        let args = Args {
-            path: Some(repl_nix_file.clone().display().to_string()),
-            dep_type: DepType::Python,
-            add: Some("pkgs.zlib".to_string()),
-            ..Default::default()
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            // the client went away
+            Err(_) => return,
         };
-        real_main(&mut io::stdout(), args);
 
-        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+        // a request that panicked doesn't leave anything half done, so carry on
+        let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+        handle_line(
+            &mut writer,
+            &mut io::stderr(),
+            &line,
+            replit_nix_filepath,
+            args,
+        );
+    }
+}
 
-        assert_eq!(
-            r#"{pkgs}: {
-  deps = [];
-  env = {
-    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
-      pkgs.zlib
-    ];
-  };
+// Fills in anything the op didn't specify itself with what was passed on the command line
+fn with_arg_defaults(mut op: Op, args: &Args) -> Op {
+    let sorted = match op.op {
+        OpKind::Get | OpKind::GetAll => args.sort_get,
+        _ => args.sorted,
+    };
+    op.sorted = op.sorted.or(Some(sorted));
+    op.arg_name = op.arg_name.or_else(|| args.arg_name.clone());
+    op.file_kind = op.file_kind.or(Some(args.file_kind));
+    op.target_path = op.target_path.or_else(|| args.target_path.clone());
+    op.env_key = op.env_key.or_else(|| args.env_key.clone());
+    op.force = op.force.or(Some(args.force));
+    op.apply = op.apply.or(Some(args.apply));
+    op.indent = op.indent.or(args.indent);
+    op.group = op.group.or_else(|| args.group.clone());
+    op.comment = op.comment.or_else(|| args.comment.clone());
+    op.position = op.position.or(args.position);
+    op.all = op.all.or(Some(args.all));
+    op.ignore_missing = op.ignore_missing.or(Some(args.ignore_missing));
+    op.prune_empty = op.prune_empty.or(Some(args.prune_empty));
+    op.unquote = op.unquote.or(Some(args.unquote));
+    op.names_only = op.names_only.or(Some(args.names_only));
+    op.with_range = op.with_range.or(Some(args.with_range));
+    op.always_sort = op.always_sort.or(Some(args.always_sort));
+    // the patterns from the command line always apply. An op can deny more deps,
+    // but only allow its own when the command line doesn't restrict them
+    op.deny = Some([args.deny.clone(), op.deny.unwrap_or_default()].concat());
+    if !args.allow_only.is_empty() {
+        op.allow_only = Some(args.allow_only.clone());
+    }
+    op
+}
+
+fn perform_batch<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    line: &str,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> i32 {
+    let ops: Vec<Op> = match from_str(line) {
+        Ok(ops) => ops,
+        Err(_) => {
+            return send_res(
+                stdout,
+                stderr,
+                &error_res("Invalid JSON".to_string()),
+                args.output_format(),
+            );
+        }
+    };
+
+    let ids: Vec<Option<Value>> = ops.iter().map(|op| op.id.clone()).collect();
+    let ops = ops
+        .into_iter()
+        .map(|op| with_arg_defaults(op, args))
+        .collect();
+
+    let results = perform_ops(stderr, ops, replit_nix_filepath, args);
+    send_batch_res(stdout, stderr, results, ids, args)
+}
+
+// Responds to a batch with one response per op, in an array for JSON output
+fn send_batch_res<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    results: Result<Vec<Result<OpResponse>>>,
+    ids: Vec<Option<Value>>,
+    args: &Args,
+) -> i32 {
+    let results = match results {
+        Ok(results) => results,
+        // the file couldn't be read or written, so none of the ops took effect
+        Err(err) => {
+            return send_res(
+                stdout,
+                stderr,
+                &op_res(Err(err), None),
+                args.output_format(),
+            )
+        }
+    };
+
+    let responses: Vec<Res> = results
+        .into_iter()
+        .zip(ids)
+        .map(|(res, id)| match res {
+            Ok(response) => Res {
+                changed: response.changed,
+                ..op_res(Ok(response.data), id)
+            },
+            Err(err) => op_res(Err(err), id),
+        })
+        .collect();
+
+    let format = args.output_format();
+    if format != OutputFormat::Json {
+        for res in &responses {
+            print_res(stdout, stderr, res, format);
+        }
+    } else {
+        write_json(stdout, &responses);
+    }
+
+    // the batch fails if any op in it failed
+    responses
+        .iter()
+        .map(exit_code)
+        .find(|code| *code != 0)
+        .unwrap_or(0)
+}
+
+fn perform_op<W: io::Write>(
+    stderr: &mut W,
+    op: Op,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> Result<Option<Value>> {
+    let mut results = perform_ops(stderr, vec![op], replit_nix_filepath, args)?;
+    results.pop().unwrap().map(|response| response.data)
+}
+
+// Runs an op given on the command line, against stdin's contents with --content-stdin
+fn perform_flag_op<W: io::Write>(
+    stderr: &mut W,
+    op: Op,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> Result<Option<Value>> {
+    if args.content_stdin {
+        let mut results = perform_ops_on_stdin(stderr, vec![op], args)?;
+        return results.pop().unwrap().map(|response| response.data);
+    }
+
+    perform_op(stderr, op, replit_nix_filepath, args)
+}
+
+// Like perform_flag_op, but runs several ops one after another
+fn perform_flag_ops<W: io::Write>(
+    stderr: &mut W,
+    ops: Vec<Op>,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> Result<Vec<Result<OpResponse>>> {
+    if args.content_stdin {
+        return perform_ops_on_stdin(stderr, ops, args);
+    }
+
+    perform_ops(stderr, ops, replit_nix_filepath, args)
+}
+
+// Runs ops in order against a single read of the file and writes the file once
+// at the end. An op that fails only fails itself, later ops still run on the
+// contents as they were before it. Failing to read or write the file fails
+// the whole batch.
+#[cfg(not(feature = "no-fs"))]
+fn perform_ops<W: io::Write>(
+    stderr: &mut W,
+    ops: Vec<Op>,
+    replit_nix_filepath: &str,
+    args: &Args,
+) -> Result<Vec<Result<OpResponse>>> {
+    let verbose = args.verbose > 0;
+    let output_filepath = args.output.as_deref().unwrap_or(replit_nix_filepath);
+
+    // hold the lock from reading the file until it's written so concurrent
+    // edits take turns instead of overwriting each other
+    let edits = ops.iter().any(|op| !op.op.is_read_only());
+    let writes = !args.dry_run && !args.return_output && edits;
+    let _lock = match writes {
+        true => Some(lock_file(output_filepath, LOCK_TIMEOUT)?),
+        false => None,
+    };
+
+    // init only replaces a file that's already there when it's forced to
+    let init = ops.iter().any(|op| op.op == OpKind::Init);
+    let forced = ops
+        .iter()
+        .all(|op| op.op != OpKind::Init || op.force == Some(true));
+    if init && !forced && Path::new(output_filepath).exists() {
+        return Err(NixEditorError::FileExists {
+            path: output_filepath.to_string(),
+        });
+    }
+
+    // read replit.nix file
+    let max_size = args.file_size_limit();
+    let (contents, existed) = match fs::File::open(replit_nix_filepath) {
+        Ok(file) => (read_limited(file, replit_nix_filepath, max_size)?, true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound && args.no_create && !init => {
+            return Err(NixEditorError::FileNotFound {
+                path: replit_nix_filepath.to_string(),
+            })
+        }
+        // if replit.nix doesn't exist start with an empty one
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (EMPTY_TEMPLATE.to_string(), false),
+        Err(err) => {
+            return Err(NixEditorError::FileRead {
+                path: replit_nix_filepath.to_string(),
+                source: err,
+            })
+        }
+    };
+
+    let name = file_name(replit_nix_filepath);
+    let (new_contents, results) = run_ops(stderr, ops, &contents, &name, args);
+
+    // what's in the file that gets written, which with --output isn't the one read
+    let existing = match args.output {
+        Some(_) => match fs::File::open(output_filepath) {
+            Ok(file) => Some(read_limited(file, output_filepath, max_size)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(NixEditorError::FileRead {
+                    path: output_filepath.to_string(),
+                    source: err,
+                })
+            }
+        },
+        None => existed.then(|| contents.clone()),
+    };
+    // read only ops never write, not even a copy of the file to --output. Init
+    // writes the template even though that's what a missing file is read as
+    let creates = init && !existed;
+    let unchanged = !edits
+        || !creates
+            && match args.output {
+                Some(_) => existing.as_deref() == Some(new_contents.as_str()),
+                None => new_contents == contents,
+            };
+
+    if args.dry_run {
+        if verbose {
+            if unchanged {
+                writeln!(stderr, "dry run: no changes").unwrap();
+            } else {
+                writeln!(stderr, "dry run: would write {}", output_filepath).unwrap();
+            }
+        }
+        return Ok(results);
+    }
+
+    if args.return_output || unchanged {
+        return Ok(results);
+    }
+
+    // a last line of defense against an edit (or formatter) breaking the file
+    if !args.no_reparse {
+        check_parses(&new_contents).map_err(|err| NixEditorError::UnparseableOutput {
+            path: output_filepath.to_string(),
+            message: match err {
+                NixEditorError::ParseFailed(message) => message,
+                err => err.to_string(),
+            },
+        })?;
+    }
+
+    if let (true, Some(existing)) = (args.backup, existing) {
+        let backup_filepath = format!("{}.bak", output_filepath);
+        fs::write(&backup_filepath, existing).map_err(|err| NixEditorError::WriteFailed {
+            path: backup_filepath,
+            source: err,
+        })?;
+    }
+
+    // write new replit.nix file
+    write_atomically(output_filepath, &new_contents).map_err(|err| {
+        NixEditorError::WriteFailed {
+            path: output_filepath.to_string(),
+            source: err,
+        }
+    })?;
+
+    Ok(results)
+}
+
+// Built with the no-fs feature there's no file to read, the contents have to
+// come in on stdin with --content-stdin
+#[cfg(feature = "no-fs")]
+fn perform_ops<W: io::Write>(
+    _stderr: &mut W,
+    _ops: Vec<Op>,
+    replit_nix_filepath: &str,
+    _args: &Args,
+) -> Result<Vec<Result<OpResponse>>> {
+    Err(NixEditorError::InvalidOp(format!(
+        "can't read {}, this build has no filesystem access. Pass the file with --content-stdin instead",
+        replit_nix_filepath
+    )))
+}
+
+// Runs ops in order starting from contents, returning the contents after the
+// last op along with what each op responded with. With --parse-timeout the
+// whole batch runs on a worker thread, and fails if it doesn't finish in time
+fn run_ops<W: io::Write>(
+    stderr: &mut W,
+    ops: Vec<Op>,
+    contents: &str,
+    name: &str,
+    args: &Args,
+) -> (String, Vec<Result<OpResponse>>) {
+    let timeout = match args.parse_time_limit() {
+        Some(timeout) => timeout,
+        None => return run_ops_on_tree(stderr, ops, contents, name, args),
+    };
+
+    let op_count = ops.len();
+    let (worker_contents, name, worker_args) =
+        (contents.to_string(), name.to_string(), args.clone());
+    // the tree can't be shared between threads, so the worker logs to a buffer
+    let res = with_deadline(timeout, move || {
+        let mut log = vec![];
+        let output = run_ops_on_tree(&mut log, ops, &worker_contents, &name, &worker_args);
+        (output, log)
+    });
+    match res {
+        Ok((output, log)) => {
+            stderr.write_all(&log).unwrap();
+            output
+        }
+        Err(err) => {
+            let results = (0..op_count).map(|_| Err(err.error(timeout))).collect();
+            (contents.to_string(), results)
+        }
+    }
+}
+
+// Runs ops one after the other on a single parse of contents, see NixEditor::run_op.
+// The tree is only parsed again after an op that can't be done on it, or when
+// --format-output changed what the op left
+fn run_ops_on_tree<W: io::Write>(
+    stderr: &mut W,
+    ops: Vec<Op>,
+    contents: &str,
+    name: &str,
+    args: &Args,
+) -> (String, Vec<Result<OpResponse>>) {
+    let mut editor = NixEditor::unparsed(contents, VerifyOptions::default());
+    let mut new_contents = contents.to_string();
+    let mut results = vec![];
+    for op in ops {
+        if args.verbose > 0 {
+            writeln!(stderr, "perform_op: {:?}", op).unwrap();
+        }
+        let edits = !op.op.is_read_only();
+        let previous_contents = new_contents.clone();
+        // with --parse-timeout this is on the worker too, so it can't hang the caller
+        if args.verbose > 1 {
+            let root = rnix::Root::parse(&new_contents).syntax();
+            writeln!(stderr, "syntax tree:\n{:#?}", root).unwrap();
+        }
+
+        let res = editor
+            .run_op(op, args.dep_type)
+            .and_then(|output| match output {
+                OpOutput::Contents(op_contents) => {
+                    apply_edit(&mut new_contents, contents, name, op_contents, None, args)
+                }
+                OpOutput::ContentsWithData(op_contents, data) => apply_edit(
+                    &mut new_contents,
+                    contents,
+                    name,
+                    op_contents,
+                    Some(data),
+                    args,
+                ),
+                OpOutput::Data(data) => Ok(Some(Value::from(data))),
+                OpOutput::Deps(deps) => Ok(Some(match (args.output_format(), args.get_format) {
+                    // plain prints a list one dep per line
+                    (OutputFormat::Plain, _) | (_, GetFormat::JsonArray) => Value::from(deps),
+                    (_, GetFormat::Joined) => Value::from(deps.join(",")),
+                })),
+                // e.g. {"deps":[...],"range":{"start":14,"end":40}}, with a null range
+                // when the list isn't in the file as written
+                OpOutput::DepsWithRange(deps, range) => {
+                    Ok(Some(json!({"deps": deps, "range": range})))
+                }
+                OpOutput::PartialDeps(deps, range) => Ok(Some(partial_deps_json(deps, range))),
+                OpOutput::EnvKeys(keys) => Ok(Some(Value::from(keys))),
+                // json is an object like any other data, toml is a string holding the document
+                OpOutput::Export(export, ExportFormat::Json) => {
+                    Ok(Some(serde_json::to_value(export).unwrap()))
+                }
+                OpOutput::Export(export, ExportFormat::Toml) => {
+                    Ok(Some(Value::from(export.to_toml())))
+                }
+                OpOutput::Info(info) => Ok(Some(serde_json::to_value(info).unwrap())),
+                // null when the file doesn't pin pkgs
+                OpOutput::Pin(pin) => Ok(Some(serde_json::to_value(pin).unwrap())),
+                // an object of arrays keyed by dep type, e.g. {"regular":[...],"python":[...]}
+                OpOutput::DepsByType(deps_by_type) => Ok(Some(Value::Object(
+                    deps_by_type
+                        .into_iter()
+                        .map(|(dep_type, deps)| (dep_type_name(dep_type), Value::from(deps)))
+                        .collect(),
+                ))),
+            });
+        results.push(res.map(|data| OpResponse {
+            data,
+            changed: edits.then(|| new_contents != previous_contents),
+        }));
+
+        // the tree only has to be parsed again when something other than the op
+        // changed the contents, e.g. the formatter, or an edit failing after the op
+        if keep_trailing_newline(contents, editor.to_string()) != new_contents {
+            editor.set_contents(&new_contents);
+        }
+    }
+
+    (new_contents, results)
+}
+
+// Takes the contents an op produced as the new contents and returns what the op responds with
+fn apply_edit(
+    new_contents: &mut String,
+    original: &str,
+    name: &str,
+    op_contents: String,
+    data: Option<String>,
+    args: &Args,
+) -> Result<Option<Value>> {
+    // only format when the op changed something, an op that did nothing shouldn't rewrite the file
+    let op_contents = match op_contents == *new_contents {
+        true => op_contents,
+        false => postprocess(op_contents, args)?,
+    };
+
+    let previous_contents =
+        std::mem::replace(new_contents, keep_trailing_newline(original, op_contents));
+    Ok(edit_data(
+        &previous_contents,
+        new_contents,
+        name,
+        data,
+        args,
+    ))
+}
+
+// Last chance to change an op's new contents before they're used, e.g. to run
+// them through the formatter given with --format-output
+fn postprocess(contents: String, args: &Args) -> Result<String> {
+    match &args.format_output {
+        Some(command) => run_formatter(command, contents),
+        None => Ok(contents),
+    }
+}
+
+// Pipes contents through command (e.g. `nixpkgs-fmt` or `alejandra -q -`) and
+// returns what it printed
+#[cfg(not(feature = "no-fs"))]
+fn run_formatter(command: &str, contents: String) -> Result<String> {
+    let format_failed = |message: String| NixEditorError::FormatFailed(message);
+
+    let mut parts = command.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| format_failed("no formatter command given".to_string()))?;
+
+    let mut child = process::Command::new(program)
+        .args(parts)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .map_err(|err| format_failed(format!("could not run {}: {}", program, err)))?;
+
+    // write from another thread so a formatter that starts printing before it has
+    // read everything can't deadlock us
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(contents.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format_failed(format!("could not run {}: {}", program, err)))?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(format_failed(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|_| format_failed(format!("{} printed invalid UTF-8", program)))
+}
+
+// the formatter is a program of its own, which could touch anything
+#[cfg(feature = "no-fs")]
+fn run_formatter(command: &str, _contents: String) -> Result<String> {
+    Err(NixEditorError::InvalidOp(format!(
+        "can't run {}, this build has no filesystem access",
+        command
+    )))
+}
+
+// Applies op to contents piped in on stdin instead of a file. The filesystem is
+// never touched, edits only come back in the response.
+fn perform_ops_on_stdin<W: io::Write>(
+    stderr: &mut W,
+    ops: Vec<Op>,
+    args: &Args,
+) -> Result<Vec<Result<OpResponse>>> {
+    let contents = read_limited(io::stdin(), "stdin", args.file_size_limit())?;
+
+    Ok(perform_ops_on_contents(stderr, ops, &contents, args))
+}
+
+fn perform_ops_on_contents<W: io::Write>(
+    stderr: &mut W,
+    ops: Vec<Op>,
+    contents: &str,
+    args: &Args,
+) -> Vec<Result<OpResponse>> {
+    // there's no file to write to, so the new contents have to go in the response
+    let args = Args {
+        return_output: true,
+        ..args.clone()
+    };
+    let (_, results) = run_ops(stderr, ops, contents, "replit.nix", &args);
+    results
+}
+
+// How many workers can be running at once, timed out ones included, before
+// with_deadline turns new ones away
+const MAX_WORKERS: usize = 8;
+
+static WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+// Why with_deadline has nothing to return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeadlineError {
+    TimedOut,
+    Panicked,
+    // MAX_WORKERS are still running, so f wasn't started
+    Busy(usize),
+}
+
+impl DeadlineError {
+    // What each op given to the worker fails with
+    fn error(self, timeout: Duration) -> NixEditorError {
+        match self {
+            DeadlineError::TimedOut => NixEditorError::ParseTimeout {
+                timeout_ms: timeout.as_millis() as u64,
+            },
+            DeadlineError::Panicked => NixEditorError::InvalidOp("op panicked".to_string()),
+            DeadlineError::Busy(workers) => NixEditorError::Busy { workers },
+        }
+    }
+}
+
+// Counts a worker as running until it's dropped, which happens when the worker
+// is done, whether it returned or panicked
+struct Worker(&'static AtomicUsize);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Runs f on a worker thread, waiting at most timeout for it to finish. A thread
+// can't be stopped, so one that's stuck carries on in the background
+fn with_deadline<T: Send + 'static>(
+    timeout: Duration,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> std::result::Result<T, DeadlineError> {
+    with_deadline_in(&WORKERS, MAX_WORKERS, timeout, f)
+}
+
+// with_deadline counting workers in workers, and refusing to start f when
+// max_workers are already running
+fn with_deadline_in<T: Send + 'static>(
+    workers: &'static AtomicUsize,
+    max_workers: usize,
+    timeout: Duration,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> std::result::Result<T, DeadlineError> {
+    let running = workers.fetch_add(1, Ordering::SeqCst);
+    let worker = Worker(workers);
+    if running >= max_workers {
+        return Err(DeadlineError::Busy(running));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _worker = worker;
+        // nobody's listening anymore if it took too long
+        let _ = sender.send(f());
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(res) => Ok(res),
+        Err(RecvTimeoutError::Timeout) => Err(DeadlineError::TimedOut),
+        Err(RecvTimeoutError::Disconnected) => Err(DeadlineError::Panicked),
+    }
+}
+
+// What an op that edited the file responds with
+fn edit_data(
+    previous_contents: &str,
+    new_contents: &str,
+    name: &str,
+    data: Option<String>,
+    args: &Args,
+) -> Option<Value> {
+    if args.delta {
+        // null when the op didn't change anything
+        return Some(serde_json::to_value(text_delta(previous_contents, new_contents)).unwrap());
+    }
+
+    if args.diff {
+        // empty when the op didn't change anything
+        return Some(Value::from(unified_diff(
+            previous_contents,
+            new_contents,
+            name,
+        )));
+    }
+
+    if args.dry_run || args.return_output {
+        return Some(Value::from(new_contents));
+    }
+
+    data.map(Value::from)
+}
+
+// The last part of path, what diffs label the file as
+#[cfg(not(feature = "no-fs"))]
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map_or_else(
+        || path.to_string(),
+        |name| name.to_string_lossy().to_string(),
+    )
+}
+
+// Makes new_contents end with the same newlines (if any) the original file
+// ended with, so edits don't add or drop blank lines at the end of the file
+fn keep_trailing_newline(original: &str, new_contents: String) -> String {
+    let trimmed = new_contents.trim_end_matches(['\r', '\n']);
+    let original_end = original.trim_end_matches(['\r', '\n']).len();
+    let mut ending = &original[original_end..];
+
+    // a blank file's newlines don't end anything, so only the last one is kept
+    if original.trim().is_empty() {
+        ending = if ending.ends_with("\r\n") {
+            "\r\n"
+        } else if ending.ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        };
+    }
+
+    format!("{}{}", trimmed, ending)
+}
+
+// Reads a file other than the one being edited, held to the same size limit
+#[cfg(not(feature = "no-fs"))]
+fn read_file(path: &str, args: &Args) -> Result<String> {
+    let file = fs::File::open(path).map_err(|err| NixEditorError::FileRead {
+        path: path.to_string(),
+        source: err,
+    })?;
+    read_limited(file, path, args.file_size_limit())
+}
+
+#[cfg(feature = "no-fs")]
+fn read_file(path: &str, _args: &Args) -> Result<String> {
+    Err(NixEditorError::InvalidOp(format!(
+        "can't read {}, this build has no filesystem access",
+        path
+    )))
+}
+
+// Reads all of reader, giving up once it's past max_size bytes so a huge file
+// (or something endless like /dev/zero) never ends up in memory
+fn read_limited<R: io::Read>(reader: R, path: &str, max_size: u64) -> Result<String> {
+    let mut contents = String::new();
+    reader
+        .take(max_size.saturating_add(1))
+        .read_to_string(&mut contents)
+        .map_err(|err| NixEditorError::FileRead {
+            path: path.to_string(),
+            source: err,
+        })?;
+
+    if contents.len() as u64 > max_size {
+        return Err(NixEditorError::FileTooLarge {
+            path: path.to_string(),
+            max_size,
+        });
+    }
+
+    Ok(contents)
+}
+
+// How long to wait for another edit of the same file before giving up
+#[cfg(not(feature = "no-fs"))]
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Takes an exclusive lock on path's .lock file, waiting up to timeout for
+// whoever holds it. The lock is released when the returned file is dropped.
+// The lock is on a separate file because writes replace path with a new file.
+// The .lock file stays behind, deleting it could leave two runs each holding a
+// lock on a different file.
+#[cfg(not(feature = "no-fs"))]
+fn lock_file(path: &str, timeout: Duration) -> Result<fs::File> {
+    let lock_path = format!("{}.lock", path);
+    let write_failed = |err| NixEditorError::WriteFailed {
+        path: lock_path.clone(),
+        source: err,
+    };
+
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(write_failed)?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match lock.try_lock() {
+            Ok(()) => return Ok(lock),
+            Err(fs::TryLockError::WouldBlock) => {}
+            Err(fs::TryLockError::Error(err)) => return Err(write_failed(err)),
+        }
+
+        if Instant::now() >= deadline {
+            return Err(NixEditorError::LockTimeout {
+                path: path.to_string(),
+            });
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// Writes to a temp file next to the target and renames it into place, so a
+// crash or a full disk part way through can't leave a truncated replit.nix behind
+#[cfg(not(feature = "no-fs"))]
+fn write_atomically(path: &str, contents: &str) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp-{}", path, process::id());
+
+    let res = fs::write(&tmp_path, contents).and_then(|_| {
+        match fs::metadata(path) {
+            Ok(metadata) => keep_metadata(&tmp_path, &metadata)?,
+            Err(_) => set_new_file_permissions(&tmp_path)?,
+        }
+        fs::rename(&tmp_path, path)
+    });
+
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    res
+}
+
+// Gives the new file the mode and owner of the file it's replacing. Changing the
+// owner needs privileges we usually don't have, so that's only done when possible.
+#[cfg(not(feature = "no-fs"))]
+fn keep_metadata(tmp_path: &str, metadata: &fs::Metadata) -> io::Result<()> {
+    fs::set_permissions(tmp_path, metadata.permissions())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = std::os::unix::fs::chown(tmp_path, Some(metadata.uid()), Some(metadata.gid()));
+    }
+
+    Ok(())
+}
+
+// A new file gets the usual 0644 whatever the umask is
+#[cfg(all(unix, not(feature = "no-fs")))]
+fn set_new_file_permissions(tmp_path: &str) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(tmp_path, fs::Permissions::from_mode(0o644))
+}
+
+#[cfg(all(not(unix), not(feature = "no-fs")))]
+fn set_new_file_permissions(_tmp_path: &str) -> io::Result<()> {
+    Ok(())
+}
+
+fn send_op_res<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    res: Result<Option<Value>>,
+    format: OutputFormat,
+) -> i32 {
+    send_res(stdout, stderr, &op_res(res, None), format)
+}
+
+fn op_res(res: Result<Option<Value>>, id: Option<Value>) -> Res {
+    match res {
+        Ok(data) => Res {
+            id,
+            status: "success".to_string(),
+            data,
+            code: None,
+            changed: None,
+        },
+        Err(err) => Res {
+            id,
+            code: Some(err.code().to_string()),
+            ..error_res(err.to_string())
+        },
+    }
+}
+
+fn error_res(data: String) -> Res {
+    Res {
+        id: None,
+        status: "error".to_string(),
+        data: Some(Value::from(data)),
+        code: None,
+        changed: None,
+    }
+}
+
+// Prints res and returns the exit code that goes with it
+fn send_res<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    res: &Res,
+    format: OutputFormat,
+) -> i32 {
+    print_res(stdout, stderr, res, format);
+    exit_code(res)
+}
+
+// 0 for success. Errors exit with 1, except for a few kinds scripts are likely to
+// want to tell apart
+fn exit_code(res: &Res) -> i32 {
+    if res.status == "success" {
+        return 0;
+    }
+
+    match res.code.as_deref() {
+        Some("parse_failed") => 2,
+        Some("dep_not_found") | Some("env_not_found") => 3,
+        _ => 1,
+    }
+}
+
+fn print_res<W: io::Write, E: io::Write>(
+    stdout: &mut W,
+    stderr: &mut E,
+    res: &Res,
+    format: OutputFormat,
+) {
+    match format {
+        OutputFormat::Json => write_json(stdout, res),
+        OutputFormat::Human => {
+            let mut out = res.status.to_owned();
+
+            match &res.data {
+                Some(Value::String(data)) => out += &(": ".to_string() + data),
+                Some(data) => out += &(": ".to_string() + &data.to_string()),
+                None => {}
+            }
+            writeln!(stdout, "{}", out).unwrap();
+        }
+        OutputFormat::Plain => {
+            if res.status != "success" {
+                let message = res.data.as_ref().map(plain_value).unwrap_or_default();
+                writeln!(stderr, "{}: {}", res.status, message).unwrap();
+                return;
+            }
+
+            match &res.data {
+                Some(Value::Array(items)) => {
+                    for item in items {
+                        writeln!(stdout, "{}", plain_value(item)).unwrap();
+                    }
+                }
+                Some(Value::Null) | None => {}
+                Some(data) => writeln!(stdout, "{}", plain_value(data)).unwrap(),
+            }
+        }
+    }
+}
+
+// Strings are printed without their quotes, anything else as JSON
+fn plain_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn write_json<W: io::Write, T: Serialize>(stdout: &mut W, value: &T) {
+    let json = match to_string(value) {
+        Ok(json) => json,
+        Err(_) => {
+            let err_msg = r#"{"status": "error", "data": "Could not serialize to JSON"}"#;
+            writeln!(stdout, "{}", err_msg).unwrap();
+            return;
+        }
+    };
+
+    writeln!(stdout, "{}", json).unwrap();
+}
+
+// these work with real files, so they can't run without filesystem access
+#[cfg(all(test, not(feature = "no-fs")))]
+mod integration_tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"{pkgs}: {
+  deps = [
+    pkgs.cowsay
+  ];
+}
+"#;
+
+    #[test]
+    fn test_integration_makes_template_if_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        env::set_var("REPL_HOME", dir.path().display().to_string());
+
+        let args = Args {
+            add: Some("pkgs.ncdu".to_string()),
+            ..Default::default()
+        };
+        real_main(&mut io::stdout(), &mut io::sink(), args);
+
+        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+
+        assert_eq!(
+            r#"{pkgs}: {
+  deps = [
+    pkgs.ncdu
+  ];
 }
 "#,
             contents
         );
-        drop(repl_nix_file);
-        dir.close().unwrap();
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_makes_python_ld_library_if_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), EMPTY_TEMPLATE.as_bytes()).unwrap();
+
+        let args = Args {
+            path: vec![repl_nix_file.clone().display().to_string()],
+            dep_type: DepType::Python,
+            add: Some("pkgs.zlib".to_string()),
+            ..Default::default()
+        };
+        real_main(&mut io::stdout(), &mut io::sink(), args);
+
+        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+
+        assert_eq!(
+            r#"{pkgs}: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}
+"#,
+            contents
+        );
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_no_change_no_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), EMPTY_TEMPLATE.as_bytes()).unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.clone().display().to_string()],
+            dep_type: DepType::Python,
+            add: Some("pkgs.zlib".to_string()),
+            ..Default::default()
+        };
+        real_main(&mut io::stdout(), &mut io::sink(), args.clone());
+
+        let metadata = fs::metadata(repl_nix_file.as_os_str()).unwrap();
+        let modification_time = metadata.modified().unwrap();
+
+        real_main(&mut io::stdout(), &mut io::sink(), args);
+
+        let metadata = fs::metadata(repl_nix_file.as_os_str()).unwrap();
+        let modification_time2 = metadata.modified().unwrap();
+
+        assert_eq!(modification_time, modification_time2);
+    }
+
+    #[test]
+    fn test_integration_remove_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.clone().display().to_string()],
+            dep_type: DepType::Regular,
+            remove: Some("pkgs.cowsay".to_string()),
+            ..Default::default()
+        };
+        real_main(&mut io::stdout(), &mut io::sink(), args.clone());
+
+        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+
+        assert_eq!("{pkgs}: {\n  deps = [\n  ];\n}\n", contents);
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.clone().display().to_string()],
+            get: true,
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        real_main(&mut stdout, &mut io::sink(), args.clone());
+
+        assert_eq!(
+            stdout,
+            br#"{"status":"success","data":"pkgs.cowsay"}
+"#
+        );
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    fn perform_has(path: &str, dep: &str, dep_type: DepType) -> Option<Value> {
+        let args = Args {
+            dep_type,
+            ..Default::default()
+        };
+        perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Has, Some(dep.to_string())),
+            path,
+            &args,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_integration_has() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(
+            repl_nix_file.as_os_str(),
+            r#"{pkgs}: {
+  deps = [
+    pkgs.cowsay
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        assert_eq!(
+            perform_has(&path, "pkgs.cowsay", DepType::Regular),
+            Some(Value::from("true"))
+        );
+        assert_eq!(
+            perform_has(&path, "pkgs.zlib", DepType::Regular),
+            Some(Value::from("false"))
+        );
+        assert_eq!(
+            perform_has(&path, "pkgs.zlib", DepType::Python),
+            Some(Value::from("true"))
+        );
+        assert_eq!(
+            perform_has(&path, "pkgs.cowsay", DepType::Python),
+            Some(Value::from("false"))
+        );
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+
+        fs::write(
+            repl_nix_file.as_os_str(),
+            r#"{pkgs}: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let count = |dep_type| {
+            let args = Args {
+                dep_type,
+                ..Default::default()
+            };
+            perform_op(&mut io::sink(), Op::new(OpKind::Count, None), &path, &args).unwrap()
+        };
+
+        assert_eq!(count(DepType::Regular), Some(Value::from("2")));
+        assert_eq!(count(DepType::Python), Some(Value::from("1")));
+
+        fs::write(repl_nix_file.as_os_str(), "{ pkgs }: {}".as_bytes()).unwrap();
+        assert_eq!(count(DepType::Regular), Some(Value::from("0")));
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_error_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), "{ foo }: {}".as_bytes()).unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        real_main(&mut stdout, &mut io::sink(), args);
+
+        let res: Res = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert_eq!(res.status, "error");
+        assert_eq!(res.code, Some("verify_failed".to_string()));
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            dry_run: true,
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        real_main(&mut stdout, &mut io::sink(), args);
+
+        let res: Res = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert_eq!(res.status, "success");
+        assert_eq!(
+            res.data,
+            Some(Value::from(
+                "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+            ))
+        );
+
+        // the file on disk is left alone
+        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+        assert_eq!(contents, TEMPLATE);
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_dry_run_does_not_create_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            dry_run: true,
+            ..Default::default()
+        };
+        let op = Op {
+            ignore_missing: Some(true),
+            ..Op::new(OpKind::Remove, Some("pkgs.cowsay".to_string()))
+        };
+        let res = perform_op(
+            &mut io::sink(),
+            op,
+            &repl_nix_file.display().to_string(),
+            &args,
+        );
+
+        // no change is still reported with the contents that would be written
+        assert_eq!(res.unwrap(), Some(Value::from(EMPTY_TEMPLATE)));
+        assert!(!repl_nix_file.exists());
+
+        dir.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_integration_write_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
+        fs::set_permissions(&repl_nix_file, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            ..Default::default()
+        };
+        real_main(&mut io::sink(), &mut io::sink(), args);
+
+        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+        assert_eq!(
+            contents,
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
+
+        let mode = fs::metadata(&repl_nix_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // the temp file was renamed into place, so only replit.nix and its lock file are left
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+        assert!(dir.path().join("replit.nix.lock").exists());
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_integration_new_file_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            ..Default::default()
+        };
+        real_main(&mut io::sink(), &mut io::sink(), args.clone());
+
+        let metadata = fs::metadata(&repl_nix_file).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o644);
+
+        // later edits keep the mode and owner
+        fs::set_permissions(&repl_nix_file, fs::Permissions::from_mode(0o664)).unwrap();
+        let args = Args {
+            add: Some("pkgs.htop".to_string()),
+            ..args
+        };
+        real_main(&mut io::sink(), &mut io::sink(), args);
+
+        let new_metadata = fs::metadata(&repl_nix_file).unwrap();
+        assert_eq!(new_metadata.permissions().mode() & 0o777, 0o664);
+        assert_eq!(new_metadata.uid(), metadata.uid());
+        assert_eq!(new_metadata.gid(), metadata.gid());
+    }
+
+    #[test]
+    fn test_integration_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let backup_file = dir.path().join("replit.nix.bak");
+
+        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
+        fs::write(backup_file.as_os_str(), "stale".as_bytes()).unwrap();
+
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            backup: true,
+            ..Default::default()
+        };
+
+        // ops that don't change anything leave the backup alone
+        real_main(
+            &mut io::sink(),
+            &mut io::sink(),
+            Args {
+                get: true,
+                ..args.clone()
+            },
+        );
+        assert_eq!(fs::read_to_string(&backup_file).unwrap(), "stale");
+
+        real_main(
+            &mut io::sink(),
+            &mut io::sink(),
+            Args {
+                add: Some("pkgs.ncdu".to_string()),
+                ..args
+            },
+        );
+        assert_eq!(fs::read_to_string(&backup_file).unwrap(), TEMPLATE);
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_no_backup_for_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            backup: true,
+            ..Default::default()
+        };
+        real_main(&mut io::sink(), &mut io::sink(), args);
+
+        assert!(repl_nix_file.exists());
+        assert!(!dir.path().join("replit.nix.bak").exists());
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            ..Default::default()
+        };
+
+        let mut stdout = Vec::new();
+        perform_batch(
+            &mut stdout,
+            &mut io::sink(),
+            r#"[{"id": 1, "op": "add", "dep": "pkgs.ncdu"}, {"id": "two", "op": "rename", "from": "pkgs.missing", "to": "pkgs.foo"}, {"id": 3, "op": "get"}]"#,
+            &repl_nix_file.display().to_string(),
+            &args,
+        );
+
+        let responses: Vec<Res> = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert_eq!(responses.len(), 3);
+
+        assert_eq!(responses[0].id, Some(Value::from(1)));
+        assert_eq!(responses[0].status, "success");
+
+        // the failed op only fails itself
+        assert_eq!(responses[1].id, Some(Value::from("two")));
+        assert_eq!(responses[1].status, "error");
+        assert_eq!(responses[1].code, Some("dep_not_found".to_string()));
+
+        // later ops see the changes made earlier in the batch
+        assert_eq!(responses[2].id, Some(Value::from(3)));
+        assert_eq!(
+            responses[2].data,
+            Some(Value::from("pkgs.ncdu,pkgs.cowsay"))
+        );
+
+        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+        assert_eq!(
+            contents,
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_id_echoed() {
+        let res = op_res(Ok(None), Some(Value::from("abc")));
+        assert_eq!(
+            to_string(&res).unwrap(),
+            r#"{"id":"abc","status":"success","data":null}"#
+        );
+
+        // responses to ops without an id look the same as before
+        let res = op_res(Ok(None), None);
+        assert_eq!(
+            to_string(&res).unwrap(),
+            r#"{"status":"success","data":null}"#
+        );
+    }
+
+    #[test]
+    fn test_integration_get_json_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(
+            repl_nix_file.as_os_str(),
+            r#"{pkgs}: {
+  deps = [
+    pkgs.cowsay
+    "a,b"
+  ];
+}
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            get: true,
+            get_format: GetFormat::JsonArray,
+            ..Default::default()
+        };
+        let mut stdout = Vec::new();
+        real_main(&mut stdout, &mut io::sink(), args);
+
+        assert_eq!(
+            std::str::from_utf8(&stdout).unwrap(),
+            r#"{"status":"success","data":["pkgs.cowsay","\"a,b\""]}
+"#
+        );
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_dedup() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+
+        fs::write(
+            repl_nix_file.as_os_str(),
+            "{pkgs}: {\n  deps = [\n    pkgs.cowsay\n    pkgs.cowsay\n  ];\n}\n".as_bytes(),
+        )
+        .unwrap();
+
+        let res = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Dedup, None),
+            &path,
+            &Args::default(),
+        );
+        assert_eq!(res.unwrap(), Some(Value::from("1")));
+
+        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+        assert_eq!(contents, TEMPLATE);
+
+        drop(repl_nix_file);
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_integration_keeps_trailing_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+
+        let add = |contents: &str| {
+            fs::write(repl_nix_file.as_os_str(), contents.as_bytes()).unwrap();
+            perform_op(
+                &mut io::sink(),
+                Op::new(OpKind::Add, Some("pkgs.ncdu".to_string())),
+                &path,
+                &Args::default(),
+            )
+            .unwrap();
+            fs::read_to_string(repl_nix_file.clone()).unwrap()
+        };
+
+        assert_eq!(
+            add(TEMPLATE),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
+        assert_eq!(
+            add(TEMPLATE.trim_end()),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}"
+        );
+
+        // blank lines at the end are kept too
+        assert_eq!(
+            add(&format!("{}\n", TEMPLATE)),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n\n"
+        );
+
+        // an empty file gets filled in from the template, but doesn't gain a newline
+        assert_eq!(add(""), "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n  ];\n}");
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_keep_trailing_newline() {
+        assert_eq!(keep_trailing_newline("a\n", "b".to_string()), "b\n");
+        assert_eq!(keep_trailing_newline("a\r\n", "b".to_string()), "b\r\n");
+        assert_eq!(keep_trailing_newline("a", "b\n\n".to_string()), "b");
+        assert_eq!(keep_trailing_newline("a\n", "b\n".to_string()), "b\n");
+        assert_eq!(keep_trailing_newline("a\n\n", "b\n".to_string()), "b\n\n");
+        assert_eq!(
+            keep_trailing_newline("a\r\n\r\n", "b".to_string()),
+            "b\r\n\r\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_integration_serve() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let other_nix_file = dir.path().join("other.nix");
+        let socket_path = dir.path().join("nix-editor.sock");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        fs::write(&other_nix_file, TEMPLATE).unwrap();
+
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            serve: Some(socket_path.display().to_string()),
+            ..Default::default()
+        };
+        // the server never returns, so it's left running until the test process exits
+        thread::spawn(move || real_main(&mut io::sink(), &mut io::sink(), args));
+
+        let stream = loop {
+            match UnixStream::connect(&socket_path) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        let mut request = |line: &str| {
+            writeln!(writer, "{}", line).unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            response
+        };
+
+        assert_eq!(
+            request(r#"{"id": 1, "op": "add", "dep": "pkgs.ncdu"}"#),
+            "{\"id\":1,\"status\":\"success\",\"data\":null}\n"
+        );
+        assert_eq!(
+            request(r#"{"op": "get"}"#),
+            "{\"status\":\"success\",\"data\":\"pkgs.ncdu,pkgs.cowsay\"}\n"
+        );
+
+        // a request can name its own file
+        let other_path = other_nix_file.display().to_string();
+        request(&format!(
+            r#"{{"op": "remove", "dep": "pkgs.cowsay", "path": {:?}}}"#,
+            other_path
+        ));
+        assert_eq!(
+            fs::read_to_string(&other_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n  ];\n}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_integration_serve_keeps_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let victim = dir.path().join("victim.nix");
+        fs::write(&victim, TEMPLATE).unwrap();
+
+        let socket_path = victim.display().to_string();
+        let res = serve(
+            &socket_path,
+            &repl_nix_file.display().to_string(),
+            &Args::default(),
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&victim).unwrap(), TEMPLATE);
+    }
+
+    #[test]
+    fn test_integration_delta() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        let args = Args {
+            delta: true,
+            ..Default::default()
+        };
+        let res = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Add, Some("pkgs.ncdu".to_string())),
+            &path,
+            &args,
+        );
+        assert_eq!(
+            res.unwrap(),
+            Some(serde_json::json!({
+                "offset": 20,
+                "delete": 0,
+                "insert": "\n    pkgs.ncdu",
+                "start_line": 2,
+                "end_line": 3,
+            }))
+        );
+        // the file is still written as usual
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
+
+        let res = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Add, Some("pkgs.ncdu".to_string())),
+            &path,
+            &args,
+        );
+        assert_eq!(res.unwrap(), Some(Value::Null));
+    }
+
+    #[test]
+    fn test_perform_ops_on_contents() {
+        let mut results = perform_ops_on_contents(
+            &mut io::sink(),
+            vec![Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()))],
+            TEMPLATE,
+            &Args::default(),
+        );
+        assert_eq!(
+            results.pop().unwrap().unwrap(),
+            OpResponse {
+                data: Some(Value::from(
+                    "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+                )),
+                changed: Some(true),
+            }
+        );
+
+        let mut results = perform_ops_on_contents(
+            &mut io::sink(),
+            vec![Op::new(OpKind::Get, None)],
+            TEMPLATE,
+            &Args::default(),
+        );
+        assert_eq!(
+            results.pop().unwrap().unwrap(),
+            OpResponse {
+                data: Some(Value::from("pkgs.cowsay")),
+                changed: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_integration_get_plain() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(
+            &repl_nix_file,
+            "{pkgs}: {\n  deps = [\n    pkgs.cowsay\n    pkgs.ncdu\n  ];\n}\n",
+        )
+        .unwrap();
+
+        let mut stdout = vec![];
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            get: true,
+            format: OutputFormat::Plain,
+            ..Default::default()
+        };
+        real_main(&mut stdout, &mut io::sink(), args);
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "pkgs.cowsay\npkgs.ncdu\n"
+        );
+
+        // edits that don't hand anything back print nothing
+        let mut stdout = vec![];
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.htop".to_string()),
+            format: OutputFormat::Plain,
+            ..Default::default()
+        };
+        real_main(&mut stdout, &mut io::sink(), args);
+        assert_eq!(String::from_utf8(stdout).unwrap(), "");
+
+        // errors go to stderr
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            remove: Some("pkgs.zlib".to_string()),
+            format: OutputFormat::Plain,
+            ..Default::default()
+        };
+        real_main(&mut stdout, &mut stderr, args);
+        assert_eq!(String::from_utf8(stdout).unwrap(), "");
+        assert_eq!(
+            String::from_utf8(stderr).unwrap(),
+            "error: Could not find dep pkgs.zlib\n"
+        );
+    }
+
+    #[test]
+    fn test_human_is_alias_for_format_human() {
+        let args = Args {
+            human: true,
+            ..Default::default()
+        };
+        assert_eq!(args.output_format(), OutputFormat::Human);
+        assert_eq!(Args::default().output_format(), OutputFormat::Json);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_integration_format_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        let args = Args {
+            format_output: Some("sed s/ncdu/htop/".to_string()),
+            ..Default::default()
+        };
+        let res = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Add, Some("pkgs.ncdu".to_string())),
+            &path,
+            &args,
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.htop\n    pkgs.cowsay\n  ];\n}\n"
+        );
+
+        // a formatter that fails leaves the file alone
+        let args = Args {
+            format_output: Some("false".to_string()),
+            ..Default::default()
+        };
+        let res = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Add, Some("pkgs.ncdu".to_string())),
+            &path,
+            &args,
+        );
+        assert_eq!(res.unwrap_err().code(), "format_failed");
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.htop\n    pkgs.cowsay\n  ];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_integration_refuses_to_write_unparseable_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        // stands in for an edit that breaks the file
+        let args = Args {
+            format_output: Some("sed s/];/[;/".to_string()),
+            ..Default::default()
+        };
+        let add = |args: &Args| {
+            perform_op(
+                &mut io::sink(),
+                Op::new(OpKind::Add, Some("pkgs.ncdu".to_string())),
+                &path,
+                args,
+            )
+        };
+
+        assert_eq!(add(&args).unwrap_err().code(), "unparseable_output");
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+
+        let args = Args {
+            no_reparse: true,
+            ..args
+        };
+        assert!(add(&args).is_ok());
+        assert!(fs::read_to_string(&repl_nix_file).unwrap().contains("[;"));
+    }
+
+    #[test]
+    fn test_integration_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+
+        let args = Args {
+            diff: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let res = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Add, Some("pkgs.ncdu".to_string())),
+            &repl_nix_file.display().to_string(),
+            &args,
+        );
+        assert_eq!(
+            res.unwrap(),
+            Some(Value::from(
+                r#"--- a/replit.nix
++++ b/replit.nix
+@@ -1,5 +1,6 @@
+ {pkgs}: {
+   deps = [
++    pkgs.ncdu
+     pkgs.cowsay
+   ];
+ }
+"#
+            ))
+        );
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+    }
+
+    #[test]
+    fn test_integration_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let args = Args {
+            path: vec![path.clone()],
+            get: true,
+            ..Default::default()
+        };
+        assert_eq!(real_main(&mut io::sink(), &mut io::sink(), args.clone()), 0);
+
+        fs::write(&repl_nix_file, "{ pkgs }: { deps = [ }").unwrap();
+        assert_eq!(real_main(&mut io::sink(), &mut io::sink(), args), 2);
+
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let mut stdout = vec![];
+        let args = Args::default();
+        let batch = r#"[{"op": "get"}, {"op": "rename", "from": "pkgs.ncdu", "to": "pkgs.htop"}]"#;
+        assert_eq!(
+            perform_batch(&mut stdout, &mut io::sink(), batch, &path, &args),
+            3
+        );
+        assert_eq!(
+            handle_line(&mut stdout, &mut io::sink(), "not json", &path, &args),
+            1
+        );
+    }
+
+    #[test]
+    fn test_integration_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+        let lock_path = dir.path().join("replit.nix.lock");
+
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+
+        // reading doesn't need the lock
+        let args = Args::default();
+        let res = perform_op(&mut io::sink(), Op::new(OpKind::Get, None), &path, &args);
+        assert!(res.is_ok());
+        assert!(!lock_path.exists());
+
+        let lock = lock_file(&path, LOCK_TIMEOUT).unwrap();
+        let res = lock_file(&path, Duration::from_millis(50));
+        assert!(matches!(res, Err(NixEditorError::LockTimeout { .. })));
+
+        // an edit waits for the lock to be released and then goes ahead
+        let edit_path = path.clone();
+        let edit = thread::spawn(move || {
+            let op = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+            perform_op(&mut io::sink(), op, &edit_path, &Args::default())
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+
+        drop(lock);
+        assert!(edit.join().unwrap().is_ok());
+        assert!(fs::read_to_string(&repl_nix_file)
+            .unwrap()
+            .contains("pkgs.ncdu"));
+    }
+
+    #[test]
+    fn test_integration_op_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let args = Args {
+            path: vec![path.clone()],
+            op_json: Some(r#"{"op": "add", "dep": "pkgs.ncdu", "id": 7}"#.to_string()),
+            ..Default::default()
+        };
+        let mut stdout = vec![];
+        assert_eq!(real_main(&mut stdout, &mut io::sink(), args), 0);
+        assert_eq!(stdout, b"{\"id\":7,\"status\":\"success\",\"data\":null}\n");
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
+
+        let args = Args {
+            path: vec![path],
+            op_json: Some("{\"op\": \"add\"".to_string()),
+            ..Default::default()
+        };
+        let mut stdout = vec![];
+        assert_eq!(real_main(&mut stdout, &mut io::sink(), args), 1);
+        assert_eq!(
+            stdout,
+            b"{\"status\":\"error\",\"data\":\"Invalid JSON\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_integration_get_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            get_all: true,
+            ..Default::default()
+        };
+        let mut stdout = vec![];
+        assert_eq!(real_main(&mut stdout, &mut io::sink(), args), 0);
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            r#"{"status":"success","data":{"node":[],"python":[],"regular":["pkgs.cowsay"]}}
+"#
+        );
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+    }
+
+    #[test]
+    fn test_integration_add_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            remove: Some("pkgs.cowsay".to_string()),
+            ..Default::default()
+        };
+        let mut stdout = vec![];
+        assert_eq!(real_main(&mut stdout, &mut io::sink(), args), 0);
+
+        // one response for each op, remove first
+        let responses: Vec<Res> = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|res| res.status == "success"));
+
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n  ];\n}\n"
+        );
     }
 
     #[test]
-    fn test_integration_no_change_no_write() {
+    fn test_integration_add_and_remove_with_pkgs() {
         let dir = tempfile::tempdir().unwrap();
         let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(
+            &repl_nix_file,
+            "{ pkgs }: {\n  deps = with pkgs; [\n    cowsay # says things\n    htop\n  ];\n}\n",
+        )
+        .unwrap();
 
-        fs::write(repl_nix_file.as_os_str(), EMPTY_TEMPLATE.as_bytes()).unwrap();
         let args = Args {
-            path: Some(repl_nix_file.clone().display().to_string()),
-            dep_type: DepType::Python,
-            add: Some("pkgs.zlib".to_string()),
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            remove: Some("pkgs.cowsay".to_string()),
             ..Default::default()
         };
-        real_main(&mut io::stdout(), args.clone());
+        let mut stdout = vec![];
+        assert_eq!(real_main(&mut stdout, &mut io::sink(), args), 0);
 
-        let metadata = fs::metadata(repl_nix_file.as_os_str()).unwrap();
-        let modification_time = metadata.modified().unwrap();
+        let responses: Vec<Res> = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert!(responses.iter().all(|res| res.status == "success"));
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{ pkgs }: {\n  deps = with pkgs; [\n    ncdu\n    htop\n  ];\n}\n"
+        );
+    }
 
-        real_main(&mut io::stdout(), args);
+    #[test]
+    fn test_integration_verbose_keeps_stdout_for_responses() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
 
-        let metadata = fs::metadata(repl_nix_file.as_os_str()).unwrap();
-        let modification_time2 = metadata.modified().unwrap();
+        let args = Args {
+            path: vec![repl_nix_file.display().to_string()],
+            add: Some("pkgs.ncdu".to_string()),
+            dry_run: true,
+            verbose: 2,
+            ..Default::default()
+        };
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        assert_eq!(real_main(&mut stdout, &mut stderr, args), 0);
+
+        // exactly one line, and it's the response
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert_eq!(stdout.lines().count(), 1);
+        let res: Res = from_str(&stdout).unwrap();
+        assert_eq!(res.status, "success");
+
+        let stderr = String::from_utf8(stderr).unwrap();
+        assert!(stderr.contains("add_dep\n"));
+        assert!(stderr.contains("dry run: would write "));
+    }
 
-        assert_eq!(modification_time, modification_time2);
+    #[test]
+    fn test_integration_very_verbose_prints_syntax_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+
+        let path = repl_nix_file.display().to_string();
+        let verbose_output = |verbose| {
+            let args = Args {
+                path: vec![path.clone()],
+                get: true,
+                verbose,
+                ..Default::default()
+            };
+            let mut stderr = vec![];
+            real_main(&mut io::sink(), &mut stderr, args);
+            String::from_utf8(stderr).unwrap()
+        };
+
+        let output = verbose_output(1);
+        assert!(output.contains("perform_op: "));
+        assert!(!output.contains("NODE_ROOT"));
+
+        let output = verbose_output(2);
+        assert!(output.contains("syntax tree:\nNODE_ROOT@0..44\n"));
+        assert!(output.contains("NODE_LIST@"));
+
+        let args = Args::try_parse_from(["nix-editor", "-vv", "--get"]).unwrap();
+        assert_eq!(args.verbose, 2);
     }
 
     #[test]
-    fn test_integration_remove_writes() {
+    fn test_integration_output() {
         let dir = tempfile::tempdir().unwrap();
         let repl_nix_file = dir.path().join("replit.nix");
+        let output_file = dir.path().join("out.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
 
-        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
         let args = Args {
-            path: Some(repl_nix_file.clone().display().to_string()),
-            dep_type: DepType::Regular,
-            remove: Some("pkgs.cowsay".to_string()),
+            path: vec![repl_nix_file.display().to_string()],
+            output: Some(output_file.display().to_string()),
+            add: Some("pkgs.ncdu".to_string()),
             ..Default::default()
         };
-        real_main(&mut io::stdout(), args.clone());
+        real_main(&mut io::sink(), &mut io::sink(), args.clone());
 
-        let contents = fs::read_to_string(repl_nix_file.clone()).unwrap();
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+        assert_eq!(
+            fs::read_to_string(&output_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
 
-        assert_eq!("{pkgs}: {\n  deps = [\n  ];\n}\n", contents);
+        // the edit doesn't change the input, but the output is still brought in line with it
+        let args = Args {
+            add: Some("pkgs.cowsay".to_string()),
+            ..args
+        };
+        real_main(&mut io::sink(), &mut io::sink(), args.clone());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), TEMPLATE);
 
-        drop(repl_nix_file);
-        dir.close().unwrap();
+        // read only ops never write the output
+        fs::remove_file(&output_file).unwrap();
+        let args = Args {
+            add: None,
+            get: true,
+            ..args
+        };
+        real_main(&mut io::sink(), &mut io::sink(), args);
+        assert!(!output_file.exists());
     }
 
     #[test]
-    fn test_integration_get() {
+    fn test_args_from_env() {
+        // the only test that sets these, so nothing else sees them
+        let vars = [
+            ("NIX_EDITOR_ADD", "pkgs.ncdu"),
+            ("NIX_EDITOR_REMOVE", "pkgs.htop"),
+            ("NIX_EDITOR_GET", "1"),
+            ("NIX_EDITOR_DEP_TYPE", "python"),
+        ];
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+
+        let args = Args::try_parse_from(["nix-editor"]).unwrap();
+        assert_eq!(args.add.as_deref(), Some("pkgs.ncdu"));
+        assert_eq!(args.remove.as_deref(), Some("pkgs.htop"));
+        assert!(args.get);
+        assert_eq!(args.dep_type, DepType::Python);
+
+        // flags win over the environment
+        let args = Args::try_parse_from([
+            "nix-editor",
+            "--add",
+            "pkgs.cowsay",
+            "--remove",
+            "pkgs.zlib",
+            "--dep-type",
+            "node",
+        ])
+        .unwrap();
+        assert_eq!(args.add.as_deref(), Some("pkgs.cowsay"));
+        assert_eq!(args.remove.as_deref(), Some("pkgs.zlib"));
+        assert_eq!(args.dep_type, DepType::Node);
+
+        env::set_var("NIX_EDITOR_GET", "false");
+        assert!(!Args::try_parse_from(["nix-editor"]).unwrap().get);
+
+        for (name, _) in vars {
+            env::remove_var(name);
+        }
+        let args = Args::try_parse_from(["nix-editor"]).unwrap();
+        assert_eq!(args.add, None);
+        assert!(!args.get);
+        assert_eq!(args.dep_type, DepType::Regular);
+    }
+
+    #[test]
+    fn test_integration_max_file_size() {
         let dir = tempfile::tempdir().unwrap();
         let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        let add = |max_file_size| {
+            let args = Args {
+                max_file_size: Some(max_file_size),
+                ..Default::default()
+            };
+            let op = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+            perform_op(&mut io::sink(), op, &path, &args)
+        };
+
+        let err = add(TEMPLATE.len() as u64 - 1).unwrap_err();
+        assert_eq!(err.code(), "file_too_large");
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+
+        assert!(add(TEMPLATE.len() as u64).is_ok());
+        assert!(fs::read_to_string(&repl_nix_file)
+            .unwrap()
+            .contains("pkgs.ncdu"));
+    }
+
+    #[test]
+    fn test_read_limited_stops_early() {
+        // an endless reader is only read up to just past the limit
+        let err = read_limited(io::repeat(b'a'), "endless", 1024).unwrap_err();
+        assert!(matches!(
+            err,
+            NixEditorError::FileTooLarge { max_size: 1024, .. }
+        ));
+        assert_eq!(
+            read_limited("abc".as_bytes(), "short", 3).unwrap(),
+            "abc".to_string()
+        );
+    }
+
+    #[test]
+    fn test_integration_batch_reports_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        let mut stdout = Vec::new();
+        perform_batch(
+            &mut stdout,
+            &mut io::sink(),
+            r#"[{"op": "add", "dep": "pkgs.ncdu"}, {"op": "add", "dep": "pkgs.ncdu"}, {"op": "remove", "dep": "pkgs.htop", "ignore_missing": true}, {"op": "get"}]"#,
+            &path,
+            &Args::default(),
+        );
+
+        let responses: Vec<Res> = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        let changed: Vec<Option<bool>> = responses.iter().map(|res| res.changed).collect();
+        // read only ops don't say either way
+        assert_eq!(changed, vec![Some(true), Some(false), Some(false), None]);
+        let raw: Vec<Value> = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert!(raw[3].get("changed").is_none());
+    }
+
+    #[test]
+    fn test_integration_blank_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+        let add = || {
+            let op = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+            perform_op(&mut io::sink(), op, &path, &Args::default())
+        };
+
+        fs::write(&repl_nix_file, "\n\n").unwrap();
+        assert!(add().is_ok());
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n  ];\n}\n"
+        );
+
+        fs::write(&repl_nix_file, "# TODO\n").unwrap();
+        assert!(add().is_ok());
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "# TODO\n{pkgs}: {\n  deps = [\n    pkgs.ncdu\n  ];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_integration_json_input_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        let input = r#"[
+  {"op": "add", "dep": "pkgs.ncdu"},
+  {"op": "remove", "dep": "pkgs.missing"},
+  {"op": "remove", "dep": "pkgs.cowsay"}
+]
+"#;
+        let mut stdout = Vec::new();
+        let exit_code =
+            handle_json_array(&mut stdout, &mut io::sink(), input, &path, &Args::default());
+        assert_eq!(exit_code, 3);
+
+        let responses: Vec<Res> = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        let statuses: Vec<&str> = responses.iter().map(|res| res.status.as_str()).collect();
+        assert_eq!(statuses, vec!["success", "error", "success"]);
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.ncdu\n  ];\n}\n"
+        );
+
+        let mut stdout = Vec::new();
+        let exit_code = handle_json_array(
+            &mut stdout,
+            &mut io::sink(),
+            r#"{"op": "get"}"#,
+            &path,
+            &Args::default(),
+        );
+        assert_eq!(exit_code, 1);
+        let res: Res = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert_eq!(res.status, "error");
+    }
+
+    #[test]
+    fn test_integration_json_input_array_mixes_ops() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        // the ones done on the tree and the ones that reparse see each other's edits
+        let input = r#"[
+  {"op": "add", "dep": "pkgs.ncdu"},
+  {"op": "sort"},
+  {"op": "rename", "from": "pkgs.ncdu", "to": "pkgs.zsh"},
+  {"op": "remove", "dep": "cowsay"},
+  {"op": "add", "dep": "pkgs.htop", "position": "back"},
+  {"op": "get"}
+]
+"#;
+        let mut stdout = Vec::new();
+        let exit_code =
+            handle_json_array(&mut stdout, &mut io::sink(), input, &path, &Args::default());
+        assert_eq!(exit_code, 0);
+
+        let responses: Vec<Res> = from_str(std::str::from_utf8(&stdout).unwrap()).unwrap();
+        assert!(responses.iter().all(|res| res.status == "success"));
+        assert_eq!(responses[5].data, Some(Value::from("pkgs.zsh,pkgs.htop")));
+        assert_eq!(
+            fs::read_to_string(&repl_nix_file).unwrap(),
+            "{pkgs}: {\n  deps = [\n    pkgs.zsh\n    pkgs.htop\n  ];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_integration_no_create() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+        let args = Args {
+            no_create: true,
+            ..Default::default()
+        };
+
+        let op = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+        let err = perform_op(&mut io::sink(), op, &path, &args).unwrap_err();
+        assert_eq!(err.code(), "file_not_found");
+        assert!(!repl_nix_file.exists());
+
+        let op = Op::new(OpKind::Get, None);
+        let err = perform_op(&mut io::sink(), op, &path, &args).unwrap_err();
+        assert_eq!(err.code(), "file_not_found");
+
+        // without the flag the file is started from the template
+        let op = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+        assert!(perform_op(&mut io::sink(), op, &path, &Args::default()).is_ok());
+        assert!(repl_nix_file.exists());
+    }
+
+    #[test]
+    fn test_integration_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        let export = |export_format| {
+            let op = Op {
+                export_format: Some(export_format),
+                ..Op::new(OpKind::Export, None)
+            };
+            perform_op(&mut io::sink(), op, &path, &Args::default()).unwrap()
+        };
+
+        // the python and node lists aren't in the file, so they're empty
+        assert_eq!(
+            export(ExportFormat::Json),
+            Some(json!({
+                "deps": ["pkgs.cowsay"],
+                "env": {"LD_LIBRARY_PATH": [], "PYTHON_LD_LIBRARY_PATH": []}
+            }))
+        );
+        assert_eq!(
+            export(ExportFormat::Toml),
+            Some(Value::from(
+                "deps = [\"pkgs.cowsay\"]\n\n[env]\nLD_LIBRARY_PATH = []\nPYTHON_LD_LIBRARY_PATH = []\n"
+            ))
+        );
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+    }
+
+    #[test]
+    fn test_integration_deny_and_allow_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let path = repl_nix_file.display().to_string();
+        let args = Args {
+            deny: vec!["cuda*".to_string()],
+            allow_only: vec!["pkgs.*".to_string()],
+            ..Default::default()
+        };
+
+        let mut stdout = Vec::new();
+        for line in [
+            r#"{"op": "add", "dep": "pkgs.cudatoolkit"}"#,
+            // an op can't widen what the command line allows
+            r#"{"op": "add", "dep": "nixpkgs.hello", "allow_only": ["*"]}"#,
+            r#"{"op": "add", "dep": "pkgs.steam", "deny": ["steam"]}"#,
+            r#"{"op": "add", "dep": "pkgs.ncdu"}"#,
+        ] {
+            handle_line(&mut stdout, &mut io::sink(), line, &path, &args);
+        }
+
+        let responses: Vec<Res> = String::from_utf8(stdout)
+            .unwrap()
+            .lines()
+            .map(|line| from_str(line).unwrap())
+            .collect();
+        let codes: Vec<Option<&str>> = responses.iter().map(|res| res.code.as_deref()).collect();
+        assert_eq!(
+            codes,
+            vec![Some("rejected"), Some("rejected"), Some("rejected"), None]
+        );
+
+        let contents = fs::read_to_string(&repl_nix_file).unwrap();
+        assert!(contents.contains("pkgs.ncdu"));
+        assert!(!contents.contains("cudatoolkit") && !contents.contains("steam"));
+    }
+
+    #[test]
+    fn test_integration_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        fs::write(&repl_nix_file, "{ pkgs }: {\n  deps = [\n}\n").unwrap();
+        let path = repl_nix_file.display().to_string();
+
+        // a broken file is reported on rather than failing
+        let info = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Info, None),
+            &path,
+            &Args::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(info["parses"], json!(false));
+        assert!(info["parse_error"].as_str().unwrap().contains("line 3"));
+
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        let info = perform_op(
+            &mut io::sink(),
+            Op::new(OpKind::Info, None),
+            &path,
+            &Args::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            info,
+            json!({
+                "parses": true,
+                "parse_error": null,
+                "has_deps": true,
+                "has_env": false,
+                "has_python_path": false,
+                "has_node_path": false,
+                "dep_count": 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_integration_get_several_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        fs::write(path("a.nix"), TEMPLATE).unwrap();
+        fs::write(path("b.nix"), "{ pkgs }: {\n  deps = [\n}\n").unwrap();
 
-        fs::write(repl_nix_file.as_os_str(), TEMPLATE.as_bytes()).unwrap();
         let args = Args {
-            path: Some(repl_nix_file.clone().display().to_string()),
             get: true,
+            get_format: GetFormat::JsonArray,
+            path: vec![path("a.nix"), path("b.nix")],
             ..Default::default()
         };
         let mut stdout = Vec::new();
-        real_main(&mut stdout, args.clone());
+        let code = real_main(&mut stdout, &mut io::sink(), args);
 
+        // the broken file doesn't stop the other one from being read
+        assert_eq!(code, 2);
+        let res: Value = from_str(&String::from_utf8(stdout).unwrap()).unwrap();
+        assert_eq!(res["status"], "success");
         assert_eq!(
-            stdout,
-            br#"{"status":"success","data":"pkgs.cowsay"}
-"#
+            res["data"][path("a.nix")],
+            json!({"status": "success", "data": ["pkgs.cowsay"]})
+        );
+        assert_eq!(res["data"][path("b.nix")]["code"], "parse_failed");
+        assert_eq!(
+            fs::read_to_string(path("b.nix")).unwrap(),
+            "{ pkgs }: {\n  deps = [\n}\n"
         );
 
-        drop(repl_nix_file);
-        dir.close().unwrap();
+        // only reading is supported
+        let args = Args {
+            add: Some("pkgs.ncdu".to_string()),
+            path: vec![path("a.nix"), path("b.nix")],
+            ..Default::default()
+        };
+        assert_eq!(real_main(&mut io::sink(), &mut io::sink(), args), 1);
+        assert_eq!(fs::read_to_string(path("a.nix")).unwrap(), TEMPLATE);
+    }
+
+    #[test]
+    fn test_args_several_paths() {
+        let args =
+            Args::try_parse_from(["nix-editor", "--get", "--path", "a.nix", "b.nix"]).unwrap();
+        assert_eq!(args.path, vec!["a.nix", "b.nix"]);
+        assert!(args.get);
+    }
+
+    #[test]
+    fn test_with_deadline() {
+        assert_eq!(with_deadline(Duration::from_secs(10), || 1), Ok(1));
+
+        let slow = with_deadline(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_secs(1));
+        });
+        assert_eq!(slow, Err(DeadlineError::TimedOut));
+
+        let panicked = with_deadline(Duration::from_secs(10), || panic!("boom"));
+        assert_eq!(panicked, Err::<(), _>(DeadlineError::Panicked));
+        assert_eq!(
+            DeadlineError::Panicked.error(Duration::from_secs(1)).code(),
+            "invalid_op"
+        );
+
+        let args = Args {
+            parse_timeout: Some(10_000),
+            ..Default::default()
+        };
+        let ops = vec![Op::new(OpKind::Get, None)];
+        let (_, results) = run_ops(&mut io::sink(), ops, TEMPLATE, "replit.nix", &args);
+        assert_eq!(
+            results[0].as_ref().unwrap().data,
+            Some(Value::from("pkgs.cowsay"))
+        );
+    }
+
+    #[test]
+    fn test_with_deadline_caps_workers() {
+        static TEST_WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+        // two workers that time out and stay stuck until released
+        let mut releases = vec![];
+        for _ in 0..2 {
+            let (release, stuck) = mpsc::channel::<()>();
+            releases.push(release);
+            let res = with_deadline_in(&TEST_WORKERS, 2, Duration::from_millis(10), move || {
+                let _ = stuck.recv();
+            });
+            assert_eq!(res, Err(DeadlineError::TimedOut));
+        }
+        let busy = with_deadline_in(&TEST_WORKERS, 2, Duration::from_secs(10), || 1);
+        assert_eq!(busy, Err(DeadlineError::Busy(2)));
+        assert_eq!(
+            DeadlineError::Busy(2).error(Duration::from_secs(1)).code(),
+            "busy"
+        );
+
+        // there's room again once they're done
+        drop(releases);
+        while TEST_WORKERS.load(Ordering::SeqCst) > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let res = with_deadline_in(&TEST_WORKERS, 2, Duration::from_secs(10), || 1);
+        assert_eq!(res, Ok(1));
+    }
+
+    #[test]
+    fn test_parse_time_limit() {
+        assert_eq!(Args::default().parse_time_limit(), None);
+
+        let serve = Args {
+            serve: Some("nix-editor.sock".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(serve.parse_time_limit(), Some(Duration::from_secs(5)));
+
+        let off = Args {
+            parse_timeout: Some(0),
+            ..serve
+        };
+        assert_eq!(off.parse_time_limit(), None);
+
+        let args = Args {
+            parse_timeout: Some(250),
+            ..Default::default()
+        };
+        assert_eq!(args.parse_time_limit(), Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn test_integration_init() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let path = repl_nix_file.display().to_string();
+
+        let init = |force| {
+            let op = Op {
+                force: Some(force),
+                ..Op::new(OpKind::Init, None)
+            };
+            perform_op(&mut io::sink(), op, &path, &Args::default())
+        };
+
+        assert!(init(false).is_ok());
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), EMPTY_TEMPLATE);
+
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        assert_eq!(init(false).unwrap_err().code(), "file_exists");
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+
+        assert!(init(true).is_ok());
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), EMPTY_TEMPLATE);
+    }
+
+    #[test]
+    fn test_integration_merge_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let repl_nix_file = dir.path().join("replit.nix");
+        let reference_file = dir.path().join("reference.nix");
+        fs::write(&repl_nix_file, TEMPLATE).unwrap();
+        fs::write(
+            &reference_file,
+            "{ pkgs }: {\n  deps = with pkgs; [ cowsay ncdu ];\n}",
+        )
+        .unwrap();
+
+        let merge = |apply| {
+            let args = Args {
+                path: vec![repl_nix_file.display().to_string()],
+                merge_from: Some(reference_file.display().to_string()),
+                apply,
+                ..Default::default()
+            };
+            let mut stdout = vec![];
+            assert_eq!(real_main(&mut stdout, &mut io::sink(), args), 0);
+            String::from_utf8(stdout).unwrap()
+        };
+
+        assert!(merge(false).contains(r#""data":"pkgs.ncdu""#));
+        assert_eq!(fs::read_to_string(&repl_nix_file).unwrap(), TEMPLATE);
+
+        merge(true);
+        let contents = fs::read_to_string(&repl_nix_file).unwrap();
+        assert!(contents.contains("pkgs.ncdu"));
+        assert!(merge(false).contains(r#""data":"""#));
+    }
+}
+
+#[cfg(all(test, feature = "no-fs"))]
+mod no_fs_tests {
+    use super::*;
+
+    #[test]
+    fn test_no_fs_refuses_files() {
+        let op = Op::new(OpKind::Get, None);
+        let res = perform_ops(&mut io::sink(), vec![op], "replit.nix", &Args::default());
+        assert!(matches!(res, Err(NixEditorError::InvalidOp(_))));
+
+        assert!(serve("nix-editor.sock", "replit.nix", &Args::default()).is_err());
+    }
+
+    #[test]
+    fn test_no_fs_edits_contents() {
+        let op = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+        let results = perform_ops_on_contents(
+            &mut io::sink(),
+            vec![op],
+            "{ pkgs }: {\n  deps = [];\n}\n",
+            &Args::default(),
+        );
+        assert_eq!(
+            results[0].as_ref().unwrap().data,
+            Some(Value::from(
+                "{ pkgs }: {\n  deps = [\n    pkgs.ncdu\n  ];\n}\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_no_fs_refuses_formatter() {
+        let args = Args {
+            format_output: Some("nixpkgs-fmt".to_string()),
+            ..Default::default()
+        };
+        let op = Op::new(OpKind::Add, Some("pkgs.ncdu".to_string()));
+        let results = perform_ops_on_contents(
+            &mut io::sink(),
+            vec![op],
+            "{ pkgs }: {\n  deps = [];\n}\n",
+            &args,
+        );
+        assert!(matches!(results[0], Err(NixEditorError::InvalidOp(_))));
     }
 }
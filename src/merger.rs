@@ -0,0 +1,87 @@
+use rnix::{SyntaxKind, SyntaxNode};
+
+use crate::verify_getter::{dep_matches, is_with_pkgs, same_dep};
+
+// The deps in reference_list that deps_list doesn't have, in the order
+// reference_list has them. Deps from a `with pkgs;` list come back with their
+// `pkgs.` (or `arg_name.`) so they can be added to any list.
+pub fn missing_deps(
+    deps_list: &SyntaxNode,
+    reference_list: &SyntaxNode,
+    arg_name: &str,
+) -> Vec<String> {
+    let mut missing: Vec<String> = vec![];
+    for dep in reference_list.children() {
+        let dep = qualified_dep(reference_list, &dep, arg_name);
+        let present = deps_list
+            .children()
+            .any(|dep_node| dep_matches(deps_list, &dep_node, &dep, arg_name));
+        if !present && !missing.iter().any(|other| same_dep(other, &dep)) {
+            missing.push(dep);
+        }
+    }
+    missing
+}
+
+// dep as it's written outside of deps_list: under `with pkgs;` bare names get
+// their pkgs. (or arg_name.) back
+pub(crate) fn qualified_dep(deps_list: &SyntaxNode, dep: &SyntaxNode, arg_name: &str) -> String {
+    let bare = matches!(dep.kind(), SyntaxKind::NODE_IDENT | SyntaxKind::NODE_SELECT);
+    match bare && is_with_pkgs(deps_list, arg_name) {
+        true => format!("{}.{}", arg_name, dep),
+        false => dep.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod merger_tests {
+    use super::*;
+    use crate::verify_getter::verify_get_existing;
+    use crate::DepType;
+
+    fn missing(contents: &str, reference: &str) -> Vec<String> {
+        let root = rnix::Root::parse(contents).syntax();
+        let reference_root = rnix::Root::parse(reference).syntax();
+        let deps_list = verify_get_existing(&root, DepType::Regular).unwrap();
+        let reference_list = verify_get_existing(&reference_root, DepType::Regular).unwrap();
+        missing_deps(&deps_list.node, &reference_list.node, "pkgs")
+    }
+
+    #[test]
+    fn test_missing_deps() {
+        assert_eq!(
+            missing(
+                "{ pkgs }: {\n  deps = [ pkgs.cowsay ];\n}",
+                "{ pkgs }: {\n  deps = [\n    pkgs.ncdu # disk usage\n    pkgs.cowsay\n    pkgs.htop\n    pkgs.ncdu\n  ];\n}",
+            ),
+            vec!["pkgs.ncdu", "pkgs.htop"]
+        );
+
+        assert!(missing(
+            "{ pkgs }: {\n  deps = [ pkgs.cowsay ];\n}",
+            "{ pkgs }: {\n  deps = [];\n}"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_missing_deps_with_pkgs() {
+        // the reference uses `with pkgs;`
+        assert_eq!(
+            missing(
+                "{ pkgs }: {\n  deps = [ pkgs.cowsay ];\n}",
+                "{ pkgs }: {\n  deps = with pkgs; [ cowsay xorg.libX11 ];\n}",
+            ),
+            vec!["pkgs.xorg.libX11"]
+        );
+
+        // the file being merged into does
+        assert_eq!(
+            missing(
+                "{ pkgs }: {\n  deps = with pkgs; [ cowsay ];\n}",
+                "{ pkgs }: {\n  deps = [ pkgs.cowsay pkgs.ncdu ];\n}",
+            ),
+            vec!["pkgs.ncdu"]
+        );
+    }
+}
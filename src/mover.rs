@@ -0,0 +1,176 @@
+use crate::adder::{add_dep_with, AddOptions};
+use crate::error::{NixEditorError, Result};
+use crate::merger::qualified_dep;
+use crate::remover::{find_dep, remove_entry};
+use crate::verify_getter::SyntaxNodeAndWhitespace;
+
+// Moves dep out of the source list and into the target list, keeping the dep's
+// text as it was written, qualified with arg_name if it was bare under `with
+// pkgs;`. Both lists have to come from the same tree.
+pub fn move_dep(
+    source_list: SyntaxNodeAndWhitespace,
+    target_list: SyntaxNodeAndWhitespace,
+    dep_opt: Option<String>,
+    arg_name: &str,
+) -> Result<()> {
+    let dep =
+        dep_opt.ok_or_else(|| NixEditorError::InvalidOp("expected dep to move".to_string()))?;
+
+    let source_dep =
+        find_dep(&source_list.node, &dep, arg_name).ok_or(NixEditorError::DepNotFound(dep))?;
+    let dep_text = qualified_dep(&source_list.node, &source_dep, arg_name);
+
+    remove_entry(&source_list.node, &source_dep);
+    // if the target list already has the dep this just leaves it be
+    let options = AddOptions {
+        arg_name: Some(arg_name.to_string()),
+        ..Default::default()
+    };
+    add_dep_with(target_list, Some(dep_text), &options)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod move_tests {
+    use super::*;
+    use crate::verify_getter::verify_get;
+    use crate::DepType;
+
+    fn test_move(
+        dep: &str,
+        from: DepType,
+        to: DepType,
+        initial_contents: &str,
+    ) -> (Result<()>, String) {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+
+        let source_list = verify_get(&tree, from).unwrap();
+        let target_list = verify_get(&tree, to).unwrap();
+
+        let res = move_dep(source_list, target_list, Some(dep.to_string()), "pkgs");
+        (res, tree.to_string())
+    }
+
+    #[test]
+    fn test_move_regular_to_python() {
+        let (res, contents) = test_move(
+            "pkgs.zlib",
+            DepType::Regular,
+            DepType::Python,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+    pkgs.zlib
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.glib
+    ];
+  };
+}"#,
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            contents,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+      pkgs.glib
+    ];
+  };
+}"#
+        );
+    }
+
+    #[test]
+    fn test_move_python_to_regular() {
+        let (res, contents) = test_move(
+            "pkgs.cowsay",
+            DepType::Python,
+            DepType::Regular,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.cowsay
+      pkgs.glib
+    ];
+  };
+}"#,
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            contents,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.glib
+    ];
+  };
+}"#
+        );
+    }
+
+    #[test]
+    fn test_move_missing_dep() {
+        let (res, _) = test_move(
+            "pkgs.zlib",
+            DepType::Regular,
+            DepType::Python,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+  ];
+}"#,
+        );
+        assert!(matches!(res, Err(NixEditorError::DepNotFound(_))));
+    }
+
+    #[test]
+    fn test_move_out_of_with_pkgs() {
+        let (res, contents) = test_move(
+            "pkgs.zlib",
+            DepType::Regular,
+            DepType::Python,
+            r#"{ pkgs }: {
+  deps = with pkgs; [
+    python38Full
+    zlib
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.glib
+    ];
+  };
+}"#,
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            contents,
+            r#"{ pkgs }: {
+  deps = with pkgs; [
+    python38Full
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+      pkgs.glib
+    ];
+  };
+}"#
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use serde_json::{json, Value};
+
+use crate::{run_op, DepType, DepsRange, ExportFormat, NixEditorError, Op, OpOutput};
+
+// Runs op_json, a single op like a line of the stdin protocol, on contents without
+// touching the filesystem. Edits respond with the new contents, read only ops
+// with their data as JSON and failures with an error object like
+// {"status":"error","data":"Could not find dep pkgs.ncdu","code":"dep_not_found"}
+pub fn run_op_json(contents: &str, op_json: &str) -> String {
+    let op: Op = match serde_json::from_str(op_json) {
+        Ok(op) => op,
+        Err(_) => {
+            return json!({"status": "error", "data": "Invalid JSON", "code": "invalid_op"})
+                .to_string()
+        }
+    };
+
+    match run_op(contents, op, DepType::Regular) {
+        Ok(OpOutput::Contents(new_contents) | OpOutput::ContentsWithData(new_contents, _)) => {
+            new_contents
+        }
+        Ok(OpOutput::Data(data)) => Value::from(data).to_string(),
+        Ok(OpOutput::Deps(deps)) => Value::from(deps).to_string(),
+        Ok(OpOutput::DepsWithRange(deps, range)) => {
+            json!({"deps": deps, "range": range}).to_string()
+        }
+        Ok(OpOutput::PartialDeps(deps, range)) => partial_deps_json(deps, range).to_string(),
+        Ok(OpOutput::EnvKeys(keys)) => Value::from(keys).to_string(),
+        Ok(OpOutput::Pin(pin)) => serde_json::to_string(&pin).unwrap(),
+        // the TOML document goes in a JSON string like any other data
+        Ok(OpOutput::Export(export, ExportFormat::Toml)) => {
+            Value::from(export.to_toml()).to_string()
+        }
+        Ok(OpOutput::Export(export, ExportFormat::Json)) => export.render(ExportFormat::Json),
+        Ok(OpOutput::Info(info)) => serde_json::to_string(&info).unwrap(),
+        Ok(OpOutput::DepsByType(deps_by_type)) => {
+            let deps_by_type: serde_json::Map<String, Value> = deps_by_type
+                .into_iter()
+                .map(|(dep_type, deps)| (dep_type_name(dep_type), Value::from(deps)))
+                .collect();
+            Value::Object(deps_by_type).to_string()
+        }
+        Err(err) => error_json(&err),
+    }
+}
+
+// What dep_type is called in JSON, e.g. "python"
+pub fn dep_type_name(dep_type: DepType) -> String {
+    match serde_json::to_value(dep_type) {
+        Ok(Value::String(name)) => name,
+        _ => format!("{:?}", dep_type).to_lowercase(),
+    }
+}
+
+// e.g. {"deps":["pkgs.extra"],"partial":true}, with the range too if there is one
+pub fn partial_deps_json(deps: Vec<String>, range: Option<DepsRange>) -> Value {
+    let mut data = json!({"deps": deps, "partial": true});
+    if let Some(range) = range {
+        data["range"] = json!(range);
+    }
+    data
+}
+
+fn error_json(err: &NixEditorError) -> String {
+    json!({"status": "error", "data": err.to_string(), "code": err.code()}).to_string()
+}
+
+#[cfg(test)]
+mod op_json_tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+}
+"#;
+
+    #[test]
+    fn test_run_op_json_edit() {
+        assert_eq!(
+            run_op_json(TEMPLATE, r#"{"op": "add", "dep": "pkgs.ncdu"}"#),
+            "{ pkgs }: {\n  deps = [\n    pkgs.ncdu\n    pkgs.cowsay\n  ];\n}\n"
+        );
+        assert_eq!(
+            run_op_json(TEMPLATE, r#"{"op": "remove", "dep": "pkgs.cowsay"}"#),
+            "{ pkgs }: {\n  deps = [\n  ];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_run_op_json_export() {
+        assert_eq!(
+            run_op_json(TEMPLATE, r#"{"op": "export"}"#),
+            r#"{"deps":["pkgs.cowsay"],"env":{"LD_LIBRARY_PATH":[],"PYTHON_LD_LIBRARY_PATH":[]}}"#
+        );
+        assert_eq!(
+            run_op_json(TEMPLATE, r#"{"op": "export", "export_format": "toml"}"#),
+            r#""deps = [\"pkgs.cowsay\"]\n\n[env]\nLD_LIBRARY_PATH = []\nPYTHON_LD_LIBRARY_PATH = []\n""#
+        );
+    }
+
+    #[test]
+    fn test_run_op_json_get() {
+        assert_eq!(
+            run_op_json(TEMPLATE, r#"{"op": "get"}"#),
+            r#"["pkgs.cowsay"]"#
+        );
+        assert_eq!(
+            run_op_json(TEMPLATE, r#"{"op": "has", "dep": "pkgs.ncdu"}"#),
+            r#""false""#
+        );
+        assert_eq!(
+            run_op_json(TEMPLATE, r#"{"op": "get_all"}"#),
+            r#"{"node":[],"python":[],"regular":["pkgs.cowsay"]}"#
+        );
+    }
+
+    #[test]
+    fn test_run_op_json_errors() {
+        assert_eq!(
+            run_op_json(
+                TEMPLATE,
+                r#"{"op": "rename", "from": "pkgs.ncdu", "to": "pkgs.htop"}"#
+            ),
+            r#"{"code":"dep_not_found","data":"Could not find dep pkgs.ncdu","status":"error"}"#
+        );
+        assert_eq!(
+            run_op_json(TEMPLATE, "not json"),
+            r#"{"code":"invalid_op","data":"Invalid JSON","status":"error"}"#
+        );
+    }
+
+    #[test]
+    fn test_run_op_json_partial_deps() {
+        let contents = "{ pkgs }: {\n  deps = baseDeps ++ [ pkgs.extra ];\n}";
+        assert_eq!(
+            run_op_json(contents, r#"{"op":"get"}"#),
+            r#"{"deps":["pkgs.extra"],"partial":true}"#
+        );
+    }
+}
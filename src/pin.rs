@@ -0,0 +1,103 @@
+use rnix::{SyntaxKind, SyntaxNode};
+use serde::Serialize;
+
+use crate::verify_getter::{find_key_value_with_key, get_nth_child, string_dep_value};
+
+// The builtins that can pin where pkgs comes from
+const FETCHERS: [&str; 2] = ["fetchTarball", "fetchGit"];
+
+// Where the file pins pkgs to, e.g. from
+// `import (fetchTarball { url = "..."; sha256 = "..."; }) {}`
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Pin {
+    pub fetcher: String,
+    pub url: Option<String>,
+    pub rev: Option<String>,
+}
+
+// The first fetchTarball or fetchGit call in the file, called either directly
+// or through builtins. Only reads the tree.
+pub fn find_pin(root: &SyntaxNode) -> Option<Pin> {
+    root.descendants()
+        .filter(|node| node.kind() == SyntaxKind::NODE_APPLY)
+        .find_map(|apply| pin_of(&apply))
+}
+
+fn pin_of(apply: &SyntaxNode) -> Option<Pin> {
+    let fetcher = fetcher_name(&get_nth_child(apply, 0)?)?;
+    let argument = get_nth_child(apply, 1)?;
+
+    // the argument is either just the url or an attr set holding it
+    let (url, rev) = match argument.kind() {
+        SyntaxKind::NODE_STRING => (string_dep_value(&argument), None),
+        SyntaxKind::NODE_ATTR_SET => (string_attr(&argument, "url"), string_attr(&argument, "rev")),
+        _ => (None, None),
+    };
+
+    Some(Pin { fetcher, url, rev })
+}
+
+fn fetcher_name(function: &SyntaxNode) -> Option<String> {
+    let text = function.to_string();
+    let name = text.strip_prefix("builtins.").unwrap_or(&text);
+    FETCHERS.contains(&name).then(|| name.to_string())
+}
+
+fn string_attr(attr_set: &SyntaxNode, key: &str) -> Option<String> {
+    let key_value = find_key_value_with_key(attr_set, key)?;
+    string_dep_value(&get_nth_child(&key_value.node, 1)?)
+}
+
+#[cfg(test)]
+mod pin_tests {
+    use super::*;
+
+    fn pin(contents: &str) -> Option<Pin> {
+        find_pin(&rnix::Root::parse(contents).syntax())
+    }
+
+    #[test]
+    fn test_find_tarball_pin() {
+        assert_eq!(
+            pin(r#"{ pkgs ? import (builtins.fetchTarball {
+  url = "https://github.com/NixOS/nixpkgs/archive/abc123.tar.gz";
+  sha256 = "0000000000000000000000000000000000000000000000000000";
+}) {} }: {
+  deps = [];
+}"#),
+            Some(Pin {
+                fetcher: "fetchTarball".to_string(),
+                url: Some("https://github.com/NixOS/nixpkgs/archive/abc123.tar.gz".to_string()),
+                rev: None,
+            })
+        );
+
+        assert_eq!(
+            pin(r#"let pkgs = import (fetchTarball "https://example.com/nixpkgs.tar.gz") {}; in pkgs"#)
+                .and_then(|pin| pin.url),
+            Some("https://example.com/nixpkgs.tar.gz".to_string())
+        );
+    }
+
+    #[test]
+    fn test_find_git_pin() {
+        assert_eq!(
+            pin(r#"{ pkgs ? import (builtins.fetchGit {
+  url = "https://github.com/NixOS/nixpkgs";
+  rev = "abc123";
+}) {} }: {
+  deps = [];
+}"#),
+            Some(Pin {
+                fetcher: "fetchGit".to_string(),
+                url: Some("https://github.com/NixOS/nixpkgs".to_string()),
+                rev: Some("abc123".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_no_pin() {
+        assert_eq!(pin("{ pkgs }: {\n  deps = [ pkgs.cowsay ];\n}"), None);
+    }
+}
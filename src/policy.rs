@@ -0,0 +1,96 @@
+use crate::error::{NixEditorError, Result};
+use crate::remover::glob_matches;
+
+// Which deps may be added, as patterns where * matches any run of characters.
+// A pattern matches a dep either as written or by its name without the
+// `arg_name.` prefix, so `cuda*` and `pkgs.cuda*` both catch pkgs.cudatoolkit.
+#[derive(Debug, Default)]
+pub struct Policy<'a> {
+    // deps matching any of these are rejected
+    pub deny: &'a [String],
+    // if there are any, deps have to match one of them
+    pub allow_only: &'a [String],
+    pub arg_name: &'a str,
+}
+
+impl Policy<'_> {
+    // Fails with Rejected if dep can't be added
+    pub fn check(&self, dep: &str) -> Result<()> {
+        let rejected = |reason: String| NixEditorError::Rejected {
+            dep: dep.to_string(),
+            reason,
+        };
+
+        if let Some(pattern) = self.deny.iter().find(|pattern| self.matches(pattern, dep)) {
+            return Err(rejected(format!(
+                "it matches the denied pattern {}",
+                pattern
+            )));
+        }
+
+        let allowed = self.allow_only.is_empty()
+            || self
+                .allow_only
+                .iter()
+                .any(|pattern| self.matches(pattern, dep));
+        if !allowed {
+            return Err(rejected("it doesn't match any allowed pattern".to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, pattern: &str, dep: &str) -> bool {
+        let name = dep.strip_prefix(&format!("{}.", self.arg_name));
+        glob_matches(pattern, dep) || name.is_some_and(|name| glob_matches(pattern, name))
+    }
+}
+
+#[cfg(test)]
+mod policy_tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn test_deny() {
+        let deny = patterns(&["cuda*", "pkgs.steam"]);
+        let policy = Policy {
+            deny: &deny,
+            arg_name: "pkgs",
+            ..Default::default()
+        };
+
+        assert!(policy.check("pkgs.ncdu").is_ok());
+        assert!(matches!(
+            policy.check("pkgs.cudatoolkit"),
+            Err(NixEditorError::Rejected { reason, .. }) if reason.contains("cuda*")
+        ));
+        assert!(policy.check("pkgs.steam").is_err());
+        assert!(policy.check("pkgs.steam-run").is_ok());
+    }
+
+    #[test]
+    fn test_allow_only() {
+        let allow_only = patterns(&["python3Packages.*", "pkgs.ncdu"]);
+        let policy = Policy {
+            allow_only: &allow_only,
+            arg_name: "pkgs",
+            ..Default::default()
+        };
+
+        assert!(policy.check("pkgs.ncdu").is_ok());
+        assert!(policy.check("pkgs.python3Packages.pip").is_ok());
+        assert_eq!(policy.check("pkgs.cowsay").unwrap_err().code(), "rejected");
+
+        // denying wins over allowing
+        let deny = patterns(&["*pip"]);
+        let policy = Policy {
+            deny: &deny,
+            ..policy
+        };
+        assert!(policy.check("pkgs.python3Packages.pip").is_err());
+    }
+}
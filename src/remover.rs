@@ -1,57 +1,326 @@
-use anyhow::{Context, Result};
-use rnix::{SyntaxNode, TextRange};
+use rnix::{SyntaxElement, SyntaxKind, SyntaxNode, TextRange};
+
+use crate::error::{NixEditorError, Result};
+use crate::verify_getter::{dep_matches, normalize_dep, string_dep_value};
 
 pub fn remove_dep(
     contents: &str,
     deps_list: SyntaxNode,
     remove_dep_opt: Option<String>,
+    arg_name: &str,
+) -> Result<String> {
+    remove_matching_deps(contents, deps_list, remove_dep_opt, arg_name, false)
+}
+
+// Like remove_dep, but removes every occurrence of the dep instead of just the first
+pub fn remove_dep_all(
+    contents: &str,
+    deps_list: SyntaxNode,
+    remove_dep_opt: Option<String>,
+    arg_name: &str,
+) -> Result<String> {
+    remove_matching_deps(contents, deps_list, remove_dep_opt, arg_name, true)
+}
+
+fn remove_matching_deps(
+    contents: &str,
+    deps_list: SyntaxNode,
+    remove_dep_opt: Option<String>,
+    arg_name: &str,
+    all: bool,
 ) -> Result<String> {
-    let remove_dep = remove_dep_opt.context("error: expected dep to remove")?;
+    let remove_dep = remove_dep_opt
+        .ok_or_else(|| NixEditorError::InvalidOp("expected dep to remove".to_string()))?;
+
+    let mut ranges_to_remove = find_remove_deps(deps_list, &remove_dep, arg_name)?;
+    if !all {
+        ranges_to_remove.truncate(1);
+    }
+
+    Ok(remove_ranges(contents, &ranges_to_remove))
+}
+
+// Removes the dep at index in the list, counting from 0, the same way remove_dep
+// would remove it by name
+pub fn remove_dep_at(contents: &str, deps_list: SyntaxNode, index: usize) -> Result<String> {
+    let deps: Vec<SyntaxNode> = deps_list.children().collect();
+    let dep = deps.get(index).ok_or_else(|| {
+        NixEditorError::InvalidOp(format!(
+            "no dep at index {}, the list has {} deps",
+            index,
+            deps.len()
+        ))
+    })?;
+
+    Ok(remove_ranges(
+        contents,
+        &dep_ranges(std::slice::from_ref(dep)),
+    ))
+}
+
+// Removes every dep matching pattern, where * matches any run of characters
+// (e.g. pkgs.python*). Returns the new contents and how many deps were removed.
+pub fn remove_deps_matching(
+    contents: &str,
+    deps_list: SyntaxNode,
+    pattern_opt: Option<String>,
+    arg_name: &str,
+) -> Result<(String, usize)> {
+    let pattern = pattern_opt
+        .ok_or_else(|| NixEditorError::InvalidOp("expected dep pattern to remove".to_string()))?;
+    let pattern = normalize_dep(&deps_list, &pattern, arg_name);
+
+    let matching: Vec<SyntaxNode> = deps_list
+        .children()
+        .filter(|dep| {
+            glob_matches(
+                &pattern,
+                &normalize_dep(&deps_list, &dep.to_string(), arg_name),
+            ) || string_dep_value(dep).is_some_and(|value| glob_matches(&pattern, &value))
+        })
+        .collect();
+    let ranges_to_remove = dep_ranges(&matching);
+
+    Ok((
+        remove_ranges(contents, &ranges_to_remove),
+        ranges_to_remove.len(),
+    ))
+}
+
+// Whether dep is a pattern for remove_deps_matching rather than a single dep
+pub fn is_dep_pattern(dep: &str) -> bool {
+    dep.contains('*')
+}
+
+// Whether text matches pattern, where * matches any run of characters
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let mut rest = match text.strip_prefix(parts.next().unwrap()) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // no * at all, so it has to be an exact match
+        None => return rest.is_empty(),
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+// Cuts ranges out of contents along with the whitespace leading up to each one
+fn remove_ranges(contents: &str, ranges_to_remove: &[TextRange]) -> String {
+    // go back to front so removing a range doesn't shift the ones before it
+    let mut new_contents = contents.to_string();
+    let mut removed_from = contents.len();
+    for range_to_remove in ranges_to_remove.iter().rev() {
+        // text ranges are byte offsets, so the contents can be sliced directly.
+        // neighboring entries can share the whitespace between them, which is only removed once
+        let remove_start: usize = range_to_remove.start().into();
+        let remove_end = usize::from(range_to_remove.end()).min(removed_from);
+        new_contents.replace_range(remove_start..remove_end, "");
+        removed_from = remove_start;
+    }
+
+    new_contents
+}
 
-    let search = find_remove_dep(deps_list, &remove_dep);
-    if search.is_err() {
-        return Ok(contents.to_string());
+// Like remove_dep, but edits deps_list in place instead of working on the
+// file's text, so the tree can keep being used for more edits
+pub fn remove_dep_in_place(
+    deps_list: &SyntaxNode,
+    remove_dep_opt: Option<String>,
+    arg_name: &str,
+) -> Result<()> {
+    let remove_dep = remove_dep_opt
+        .ok_or_else(|| NixEditorError::InvalidOp("expected dep to remove".to_string()))?;
+
+    if let Some(dep) = find_dep(deps_list, &remove_dep, arg_name) {
+        remove_entry(deps_list, &dep);
     }
-    let range_to_remove = search?;
-    let text_start: usize = range_to_remove.start().into();
 
-    // since there may be leading white space, we need to remove the leading white space
-    // go backwards char by char until we find non whitespace char
-    let remove_start: usize = search_backwards_non_whitespace(text_start, contents);
-    let remove_end: usize = range_to_remove.end().into();
+    Ok(())
+}
+
+// Removes dep along with the whitespace leading up to it, a comment trailing it on
+// the same line and comments above it that are only about it
+pub(crate) fn remove_entry(deps_list: &SyntaxNode, dep: &SyntaxNode) {
+    let (first, last) = entry_bounds(dep);
+    deps_list.splice_children(first.index()..last.index() + 1, vec![]);
+}
+
+// The first and last of the list's elements that go when dep is removed: the
+// whitespace leading up to it, comments above it that are only about it, dep
+// itself and a comment trailing it on the same line
+fn entry_bounds(dep: &SyntaxNode) -> (SyntaxElement, SyntaxElement) {
+    let first = leading_comment(dep).unwrap_or_else(|| dep.clone().into());
+    let last = trailing_comment(dep).unwrap_or_else(|| dep.clone().into());
 
-    let (before, rest) = contents.split_at(remove_start);
-    let (_, after) = rest.split_at(remove_end - remove_start);
+    let whitespace = match first.prev_sibling_or_token() {
+        Some(prev) if prev.kind() == SyntaxKind::TOKEN_WHITESPACE => prev,
+        _ => return (first, last),
+    };
+
+    // a comment runs to the end of its line, so when one comes right before the
+    // entry and nothing but spaces come after it (`# x\n  pkgs.b ]`), the line
+    // break after the comment has to stay. The spaces after the entry go instead.
+    let after_comment = whitespace
+        .prev_sibling_or_token()
+        .is_some_and(|prev| prev.kind() == SyntaxKind::TOKEN_COMMENT);
+    let next = last.next_sibling_or_token();
+    let next_line_break = next.as_ref().is_some_and(|next| {
+        next.kind() == SyntaxKind::TOKEN_WHITESPACE && next.to_string().contains('\n')
+    });
+    if after_comment && !next_line_break {
+        let spaces = next.filter(|next| next.kind() == SyntaxKind::TOKEN_WHITESPACE);
+        return (first, spaces.unwrap_or(last));
+    }
 
-    Ok(format!("{}{}", before, after))
+    (whitespace, last)
 }
 
-fn search_backwards_non_whitespace(start_pos: usize, contents: &str) -> usize {
-    let mut pos = start_pos;
-    while pos > 0 {
-        let c = contents.chars().nth(pos - 1).unwrap();
-        if !c.is_whitespace() {
-            return pos;
+// The deps to remove for remove_dep. When nothing matches it exactly, a short
+// name like ncdu also finds pkgs.ncdu, as long as only one dep goes by that name.
+fn find_remove_deps(
+    deps_list: SyntaxNode,
+    remove_dep: &str,
+    arg_name: &str,
+) -> Result<Vec<TextRange>> {
+    let deps = find_deps(&deps_list, remove_dep, arg_name);
+    if !deps.is_empty() {
+        return Ok(dep_ranges(&deps));
+    }
+
+    let deps: Vec<SyntaxNode> = deps_list
+        .children()
+        .filter(|child| short_name_matches(child, remove_dep, arg_name))
+        .collect();
+
+    // the same dep listed twice isn't ambiguous
+    let mut candidates: Vec<String> = vec![];
+    for dep in &deps {
+        let dep = dep.to_string();
+        if !candidates.contains(&dep) {
+            candidates.push(dep);
         }
-        pos -= 1;
     }
-    0
+    if candidates.len() > 1 {
+        return Err(NixEditorError::AmbiguousDep {
+            dep: remove_dep.to_string(),
+            candidates,
+        });
+    }
+
+    Ok(dep_ranges(&deps))
 }
 
-fn find_remove_dep(deps_list: SyntaxNode, remove_dep: &str) -> Result<TextRange> {
-    let mut deps = deps_list.children();
+// Whether name is the last part of dep's path, or its path without pkgs. (or
+// arg_name.), so both libX11 and xorg.libX11 name pkgs.xorg.libX11
+fn short_name_matches(dep: &SyntaxNode, name: &str, arg_name: &str) -> bool {
+    if dep.kind() != SyntaxKind::NODE_SELECT {
+        return false;
+    }
 
-    let dep = deps
-        .find(|dep| dep.text() == remove_dep)
-        .context("error: could not find dep to remove")?;
+    let text = dep.to_string();
+    text.rsplit('.').next() == Some(name)
+        || text.strip_prefix(&format!("{}.", arg_name)) == Some(name)
+}
 
-    Ok(dep.text_range())
+// The text ranges to remove for deps, see entry_bounds
+fn dep_ranges(deps: &[SyntaxNode]) -> Vec<TextRange> {
+    deps.iter()
+        .map(|dep| {
+            let (first, last) = entry_bounds(dep);
+            first.text_range().cover(last.text_range())
+        })
+        .collect()
+}
+
+// The first of the comment lines directly above dep, if they're only about dep:
+// what follows dep is the end of the list, a blank line or another comment.
+// A comment heading a group of deps isn't about any one of them, so it stays.
+pub(crate) fn leading_comment(dep: &SyntaxNode) -> Option<SyntaxElement> {
+    let mut first = None;
+    let mut prev = dep.prev_sibling_or_token();
+    while let Some(whitespace) = prev.filter(is_line_break) {
+        match whitespace.prev_sibling_or_token() {
+            Some(comment) if comment.kind() == SyntaxKind::TOKEN_COMMENT => {
+                prev = comment.prev_sibling_or_token();
+                first = Some(comment);
+            }
+            _ => break,
+        }
+    }
+
+    // the comment has to start its line, otherwise it's trailing the dep before it
+    let first = first?;
+    match first.prev_sibling_or_token() {
+        Some(whitespace) if whitespace.to_string().contains('\n') => {}
+        _ => return None,
+    }
+
+    let last: SyntaxElement = trailing_comment(dep).unwrap_or_else(|| dep.clone().into());
+    let mut next = last.next_sibling_or_token()?;
+    if next.kind() == SyntaxKind::TOKEN_WHITESPACE {
+        if next.to_string().matches('\n').count() > 1 {
+            return Some(first);
+        }
+        next = next.next_sibling_or_token()?;
+    }
+
+    match next.kind() {
+        SyntaxKind::TOKEN_COMMENT | SyntaxKind::TOKEN_R_BRACK => Some(first),
+        _ => None,
+    }
+}
+
+// Whitespace going onto the next line without leaving a blank line
+fn is_line_break(element: &SyntaxElement) -> bool {
+    element.kind() == SyntaxKind::TOKEN_WHITESPACE && element.to_string().matches('\n').count() == 1
+}
+
+// The comment following dep on the same line, if there is one
+pub(crate) fn trailing_comment(dep: &SyntaxNode) -> Option<SyntaxElement> {
+    let mut next = dep.next_sibling_or_token()?;
+    if next.kind() == SyntaxKind::TOKEN_WHITESPACE {
+        if next.to_string().contains('\n') {
+            return None;
+        }
+        next = next.next_sibling_or_token()?;
+    }
+
+    match next.kind() {
+        SyntaxKind::TOKEN_COMMENT => Some(next),
+        _ => None,
+    }
+}
+
+pub fn find_dep(deps_list: &SyntaxNode, dep: &str, arg_name: &str) -> Option<SyntaxNode> {
+    find_deps(deps_list, dep, arg_name).into_iter().next()
+}
+
+// Every occurrence of dep in deps_list, in the order they appear
+pub fn find_deps(deps_list: &SyntaxNode, dep: &str, arg_name: &str) -> Vec<SyntaxNode> {
+    deps_list
+        .children()
+        .filter(|child| dep_matches(deps_list, child, dep, arg_name))
+        .collect()
 }
 
 #[cfg(test)]
 mod remove_tests {
     use super::*;
-    use crate::verify_getter::verify_get;
+    use crate::verify_getter::{verify_get, verify_get_with, VerifyOptions};
     use crate::DepType;
 
     fn python_replit_nix() -> String {
@@ -85,7 +354,7 @@ mod remove_tests {
 }
         "#;
 
-        let tree = rnix::Root::parse(&contents).syntax();
+        let tree = rnix::Root::parse(contents).syntax();
         let deps_list_res = verify_get(&tree, DepType::Regular);
         assert!(deps_list_res.is_ok());
 
@@ -93,7 +362,12 @@ mod remove_tests {
 
         let dep_to_remove = "pkgs.ncdu";
 
-        let new_contents = remove_dep(&contents, deps_list.node, Some(dep_to_remove.to_string()));
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some(dep_to_remove.to_string()),
+            "pkgs",
+        );
         assert!(new_contents.is_ok());
 
         let new_contents = new_contents.unwrap();
@@ -115,7 +389,7 @@ mod remove_tests {
 }
         "#;
 
-        let tree = rnix::Root::parse(&contents).syntax();
+        let tree = rnix::Root::parse(contents).syntax();
         let deps_list_res = verify_get(&tree, DepType::Regular);
         assert!(deps_list_res.is_ok());
 
@@ -123,7 +397,12 @@ mod remove_tests {
 
         let dep_to_remove = "pkgs.cowsay";
 
-        let new_contents = remove_dep(&contents, deps_list.node, Some(dep_to_remove.to_string()));
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some(dep_to_remove.to_string()),
+            "pkgs",
+        );
         assert!(new_contents.is_ok());
 
         let new_contents = new_contents.unwrap();
@@ -142,7 +421,12 @@ mod remove_tests {
 
         let dep_to_remove = "pkgs.python38Full";
 
-        let new_contents = remove_dep(&contents, deps_list.node, Some(dep_to_remove.to_string()));
+        let new_contents = remove_dep(
+            &contents,
+            deps_list.node,
+            Some(dep_to_remove.to_string()),
+            "pkgs",
+        );
         assert!(new_contents.is_ok());
 
         let new_contents = new_contents.unwrap();
@@ -178,7 +462,12 @@ mod remove_tests {
 
         let dep_to_remove = "pkgs.glib";
 
-        let new_contents = remove_dep(&contents, deps_list.node, Some(dep_to_remove.to_string()));
+        let new_contents = remove_dep(
+            &contents,
+            deps_list.node,
+            Some(dep_to_remove.to_string()),
+            "pkgs",
+        );
         assert!(new_contents.is_ok());
 
         let new_contents = new_contents.unwrap();
@@ -202,4 +491,558 @@ mod remove_tests {
         .to_string();
         assert_eq!(new_contents, expected_contents);
     }
+
+    #[test]
+    fn test_remove_dep_with_trailing_comment() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.zlib
+    pkgs.glib # needed for pygame
+    pkgs.xorg.libX11
+  ];
+}
+"#;
+
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some("pkgs.glib".to_string()),
+            "pkgs",
+        );
+        assert!(new_contents.is_ok());
+
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.zlib
+    pkgs.xorg.libX11
+  ];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_remove_dep_before_closing_comment() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.zlib
+    pkgs.glib
+    # TODO add more
+  ];
+}
+"#;
+        let remove = |contents: &str, dep: &str| {
+            let tree = rnix::Root::parse(contents).syntax();
+            let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+            remove_dep(contents, deps_list.node, Some(dep.to_string()), "pkgs").unwrap()
+        };
+
+        // the comment stays where it is, next to the closing bracket
+        let new_contents = remove(contents, "pkgs.glib");
+        assert_eq!(
+            new_contents,
+            "{ pkgs }: {\n  deps = [\n    pkgs.zlib\n    # TODO add more\n  ];\n}\n"
+        );
+        assert_eq!(
+            remove(&new_contents, "pkgs.zlib"),
+            "{ pkgs }: {\n  deps = [\n    # TODO add more\n  ];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_dep_keeps_preceding_comment() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    # Needed for pandas / numpy
+    pkgs.stdenv.cc.cc.lib
+    pkgs.zlib
+  ];
+}
+"#;
+
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some("pkgs.stdenv.cc.cc.lib".to_string()),
+            "pkgs",
+        );
+        assert!(new_contents.is_ok());
+
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{ pkgs }: {
+  deps = [
+    # Needed for pandas / numpy
+    pkgs.zlib
+  ];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_remove_dep_after_non_ascii_comment() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    # für pandas – numpy
+    pkgs.zlib
+    pkgs.cowsay
+  ];
+}
+"#;
+
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some("pkgs.cowsay".to_string()),
+            "pkgs",
+        );
+        assert!(new_contents.is_ok());
+
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{ pkgs }: {
+  deps = [
+    # für pandas – numpy
+    pkgs.zlib
+  ];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_remove_with_pkgs_bare_name() {
+        let contents = r#"{ pkgs }: {
+  deps = with pkgs; [
+    pkgs.ncdu
+    cowsay
+  ];
+}
+"#;
+
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node.clone(),
+            Some("ncdu".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            "{ pkgs }: {\n  deps = with pkgs; [\n    cowsay\n  ];\n}\n"
+        );
+
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some("pkgs.cowsay".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            "{ pkgs }: {\n  deps = with pkgs; [\n    pkgs.ncdu\n  ];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_by_short_name() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.ncdu
+    pkgs.xorg.libX11
+    pkgs.ncdu
+  ];
+}
+"#;
+        let remove = |dep: &str, all: bool| {
+            let tree = rnix::Root::parse(contents).syntax();
+            let deps_list = verify_get(&tree, DepType::Regular).unwrap().node;
+            match all {
+                true => remove_dep_all(contents, deps_list, Some(dep.to_string()), "pkgs"),
+                false => remove_dep(contents, deps_list, Some(dep.to_string()), "pkgs"),
+            }
+        };
+
+        assert_eq!(
+            remove("ncdu", false).unwrap(),
+            "{ pkgs }: {\n  deps = [\n    pkgs.xorg.libX11\n    pkgs.ncdu\n  ];\n}\n"
+        );
+        assert_eq!(
+            remove("ncdu", true).unwrap(),
+            "{ pkgs }: {\n  deps = [\n    pkgs.xorg.libX11\n  ];\n}\n"
+        );
+        assert_eq!(
+            remove("libX11", false).unwrap(),
+            remove("xorg.libX11", false).unwrap()
+        );
+        // only whole parts of the path count
+        assert_eq!(remove("X11", false).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_remove_by_ambiguous_short_name() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.foo
+    other.foo
+  ];
+}
+"#;
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let res = remove_dep(
+            contents,
+            deps_list.node.clone(),
+            Some("foo".to_string()),
+            "pkgs",
+        );
+        assert!(matches!(
+            res,
+            Err(NixEditorError::AmbiguousDep { candidates, .. })
+                if candidates == vec!["pkgs.foo", "other.foo"]
+        ));
+
+        // the full name is never ambiguous
+        let res = remove_dep(
+            contents,
+            deps_list.node,
+            Some("other.foo".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            res.unwrap(),
+            "{ pkgs }: {\n  deps = [\n    pkgs.foo\n  ];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_dep_all_occurrences() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.zlib
+    pkgs.cowsay
+    pkgs.zlib # added again by mistake
+  ];
+}
+"#;
+
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        // by default only the first one goes
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node.clone(),
+            Some("pkgs.zlib".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.zlib # added again by mistake
+  ];
+}
+"#
+        );
+
+        let new_contents = remove_dep_all(
+            contents,
+            deps_list.node,
+            Some("pkgs.zlib".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_remove_deps_matching() {
+        let contents = r#"{ pkgs }: {
+  deps = with pkgs; [
+    python310
+    pkgs.cowsay
+    python310Packages.pip # for installing packages
+    pkgs.python310Packages.poetry
+  ];
+}
+"#;
+
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let res = remove_deps_matching(
+            contents,
+            deps_list.node,
+            Some("pkgs.python*".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            res.unwrap(),
+            (
+                r#"{ pkgs }: {
+  deps = with pkgs; [
+    pkgs.cowsay
+  ];
+}
+"#
+                .to_string(),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("pkgs.python*", "pkgs.python310"));
+        assert!(glob_matches(
+            "pkgs.*Packages.pip",
+            "pkgs.python310Packages.pip"
+        ));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("pkgs.zlib", "pkgs.zlib"));
+        assert!(!glob_matches("pkgs.zlib", "pkgs.zlib2"));
+        assert!(!glob_matches("pkgs.python*", "pkgs.cowsay"));
+        assert!(!glob_matches("a*a", "a"));
+    }
+
+    #[test]
+    fn test_remove_dep_takes_its_own_comment() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    # Needed for pandas / numpy
+    pkgs.zlib
+
+    # Needed for pygame
+    pkgs.SDL2
+    # Needed for matplotlib
+    pkgs.xorg.libX11
+  ];
+}
+"#;
+
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node.clone(),
+            Some("pkgs.SDL2".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            r#"{ pkgs }: {
+  deps = [
+    # Needed for pandas / numpy
+    pkgs.zlib
+    # Needed for matplotlib
+    pkgs.xorg.libX11
+  ];
+}
+"#
+        );
+
+        let tree = rnix::Root::parse(contents).syntax().clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+        let res = remove_dep_in_place(
+            &deps_list.node,
+            Some("pkgs.xorg.libX11".to_string()),
+            "pkgs",
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            tree.to_string(),
+            r#"{ pkgs }: {
+  deps = [
+    # Needed for pandas / numpy
+    pkgs.zlib
+
+    # Needed for pygame
+    pkgs.SDL2
+  ];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_remove_with_same_line_comments() {
+        let contents = r#"{ pkgs }: {
+  deps = [ pkgs.a # x
+    pkgs.b ];
+}
+"#;
+        let remove = |dep: &str| {
+            let tree = rnix::Root::parse(contents).syntax();
+            let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+            let new_contents =
+                remove_dep(contents, deps_list.node, Some(dep.to_string()), "pkgs").unwrap();
+
+            // removing in place has to end up the same
+            let tree = rnix::Root::parse(contents).syntax().clone_for_update();
+            let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+            remove_dep_in_place(&deps_list.node, Some(dep.to_string()), "pkgs").unwrap();
+            assert_eq!(tree.to_string(), new_contents);
+
+            new_contents
+        };
+
+        // the comment trailing pkgs.a is about it, so it goes with it
+        assert_eq!(
+            remove("pkgs.a"),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.b ];
+}
+"#
+        );
+
+        // pkgs.a's comment still has to end before the closing bracket
+        assert_eq!(
+            remove("pkgs.b"),
+            r#"{ pkgs }: {
+  deps = [ pkgs.a # x
+    ];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_remove_dep_at() {
+        let contents = python_replit_nix();
+        let tree = rnix::Root::parse(&contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Python).unwrap();
+
+        let new_contents = remove_dep_at(&contents, deps_list.node.clone(), 2).unwrap();
+        let by_name = remove_dep(
+            &contents,
+            deps_list.node.clone(),
+            Some("pkgs.glib".to_string()),
+            "pkgs",
+        );
+        assert_eq!(new_contents, by_name.unwrap());
+
+        let res = remove_dep_at(&contents, deps_list.node, 4);
+        assert!(matches!(res, Err(NixEditorError::InvalidOp(_))));
+    }
+
+    #[test]
+    fn test_remove_multi_line_dep() {
+        let dep = "(pkgs.callPackage ./foo.nix {\n      bar = true;\n    })";
+        let remove = |contents: &str| {
+            let tree = rnix::Root::parse(contents).syntax();
+            let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+            remove_dep(contents, deps_list.node, Some(dep.to_string()), "pkgs").unwrap()
+        };
+
+        // the whole span goes along with its indentation, wherever it is in the list
+        for (contents, expected) in [
+            (
+                format!(
+                    "{{ pkgs }}: {{\n  deps = [\n    pkgs.a\n    {}\n    pkgs.b\n  ];\n}}\n",
+                    dep
+                ),
+                "{ pkgs }: {\n  deps = [\n    pkgs.a\n    pkgs.b\n  ];\n}\n",
+            ),
+            (
+                format!(
+                    "{{ pkgs }}: {{\n  deps = [\n    {}\n    pkgs.b\n  ];\n}}\n",
+                    dep
+                ),
+                "{ pkgs }: {\n  deps = [\n    pkgs.b\n  ];\n}\n",
+            ),
+            (
+                format!(
+                    "{{ pkgs }}: {{\n  deps = [\n    pkgs.a\n    {} # foo\n  ];\n}}\n",
+                    dep
+                ),
+                "{ pkgs }: {\n  deps = [\n    pkgs.a\n  ];\n}\n",
+            ),
+            (
+                format!("{{ pkgs }}: {{\n  deps = [ {} pkgs.b ];\n}}\n", dep),
+                "{ pkgs }: {\n  deps = [ pkgs.b ];\n}\n",
+            ),
+        ] {
+            assert_eq!(remove(&contents), expected);
+        }
+    }
+
+    #[test]
+    fn test_remove_overridden_dep_spaced_differently() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    (pkgs.python3.withPackages (ps: [
+      ps.numpy
+    ]))
+    pkgs.cowsay
+  ];
+}
+"#;
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some("(pkgs.python3.withPackages (ps: [ ps.numpy ]))".to_string()),
+            "pkgs",
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            "{ pkgs }: {\n  deps = [\n    pkgs.cowsay\n  ];\n}\n"
+        );
+
+        // whitespace inside strings still counts
+        let contents = "{ pkgs }: {\n  deps = [ \"a b\" ];\n}\n";
+        let tree = rnix::Root::parse(contents).syntax();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+        assert!(find_dep(&deps_list.node, "\"a  b\"", "pkgs").is_none());
+        assert!(find_dep(&deps_list.node, "\"a b\"", "pkgs").is_some());
+    }
+
+    #[test]
+    fn test_remove_by_short_name_arg_name() {
+        let contents = "{ p }: {\n  deps = [\n    p.xorg.libX11\n    p.ncdu\n  ];\n}\n";
+        let options = VerifyOptions {
+            arg_name: "p".to_string(),
+            ..Default::default()
+        };
+        let tree = rnix::Root::parse(contents).syntax().clone_for_update();
+        let deps_list = verify_get_with(&tree, DepType::Regular, &options).unwrap();
+
+        let new_contents = remove_dep(
+            contents,
+            deps_list.node,
+            Some("xorg.libX11".to_string()),
+            "p",
+        );
+        assert_eq!(
+            new_contents.unwrap(),
+            "{ p }: {\n  deps = [\n    p.ncdu\n  ];\n}\n"
+        );
+    }
 }
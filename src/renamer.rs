@@ -0,0 +1,261 @@
+use rnix::{SyntaxKind, SyntaxNode};
+
+use crate::error::{NixEditorError, Result};
+use crate::remover::{find_dep, remove_entry};
+use crate::verify_getter::normalize_dep;
+
+pub fn rename_dep(
+    deps_list: SyntaxNode,
+    from_opt: Option<String>,
+    to_opt: Option<String>,
+    arg_name: &str,
+) -> Result<SyntaxNode> {
+    let from = from_opt
+        .ok_or_else(|| NixEditorError::InvalidOp("expected dep to rename from".to_string()))?;
+    let to =
+        to_opt.ok_or_else(|| NixEditorError::InvalidOp("expected dep to rename to".to_string()))?;
+
+    if from == to {
+        // nothing to rename, we're done
+        return Ok(deps_list);
+    }
+
+    let dep = find_dep(&deps_list, &from, arg_name).ok_or(NixEditorError::DepNotFound(from))?;
+
+    // under `with pkgs;` the list uses bare names, like add
+    let to = normalize_dep(&deps_list, to.trim(), arg_name);
+    let new_dep = parse_list_element(&to)?;
+
+    // the list already has the new dep, so the old one just goes away
+    if let Some(existing) = find_dep(&deps_list, &to, arg_name) {
+        if existing != dep {
+            remove_entry(&deps_list, &dep);
+            return Ok(deps_list);
+        }
+    }
+
+    // only swap out the dep node itself so the surrounding whitespace and comments stay put
+    let index = dep.index();
+    deps_list.splice_children(index..index + 1, vec![rnix::NodeOrToken::Node(new_dep)]);
+
+    Ok(deps_list)
+}
+
+// dep parsed as the one element of a list. Something like `pkgs.b pkgs.c` would
+// be two elements, and has to be wrapped in parentheses to be one
+fn parse_list_element(dep: &str) -> Result<SyntaxNode> {
+    let ast = rnix::Root::parse(&format!("[ {} ]", dep));
+    if !ast.errors().is_empty() {
+        return Err(NixEditorError::InvalidOp(format!(
+            "could not parse new dep {}",
+            dep
+        )));
+    }
+
+    let elements: Vec<SyntaxNode> = ast
+        .syntax()
+        .clone_for_update()
+        .first_child()
+        .filter(|list| list.kind() == SyntaxKind::NODE_LIST)
+        .map(|list| list.children().collect())
+        .unwrap_or_default();
+    match elements.as_slice() {
+        [new_dep] => {
+            new_dep.detach();
+            Ok(new_dep.clone())
+        }
+        [] => Err(NixEditorError::InvalidOp(
+            "expected new dep to be an expression".to_string(),
+        )),
+        _ => Err(NixEditorError::InvalidOp(format!(
+            "new dep {} would be {} list elements, wrap it in parentheses",
+            dep,
+            elements.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod rename_tests {
+    use super::*;
+    use crate::verify_getter::verify_get;
+    use crate::DepType;
+
+    const PYTHON_REPLIT_NIX: &str = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.python38Full
+    pkgs.ncdu
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+    ];
+  };
+}"#;
+
+    fn test_rename(dep_type: DepType, from: &str, to: &str, expected_contents: &str) {
+        let tree = rnix::Root::parse(PYTHON_REPLIT_NIX)
+            .syntax()
+            .clone_for_update();
+
+        let deps_list_res = verify_get(&tree, dep_type);
+        assert!(deps_list_res.is_ok());
+
+        let deps_list = deps_list_res.unwrap();
+
+        let new_deps_list = rename_dep(
+            deps_list.node,
+            Some(from.to_string()),
+            Some(to.to_string()),
+            "pkgs",
+        );
+        assert!(new_deps_list.is_ok());
+
+        assert_eq!(tree.to_string(), expected_contents.to_string());
+    }
+
+    #[test]
+    fn test_regular_rename_keeps_position() {
+        test_rename(
+            DepType::Regular,
+            "pkgs.python38Full",
+            "pkgs.python310Full",
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.python310Full
+    pkgs.ncdu
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+    ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_python_rename_keeps_comments() {
+        test_rename(
+            DepType::Python,
+            "pkgs.stdenv.cc.cc.lib",
+            "pkgs.gcc.cc.lib",
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.python38Full
+    pkgs.ncdu
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.gcc.cc.lib
+      pkgs.zlib
+    ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_rename_same_dep_is_noop() {
+        test_rename(
+            DepType::Regular,
+            "pkgs.ncdu",
+            "pkgs.ncdu",
+            PYTHON_REPLIT_NIX,
+        );
+    }
+
+    #[test]
+    fn test_rename_missing_dep() {
+        let tree = rnix::Root::parse(PYTHON_REPLIT_NIX)
+            .syntax()
+            .clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let res = rename_dep(
+            deps_list.node,
+            Some("pkgs.htop".to_string()),
+            Some("pkgs.btop".to_string()),
+            "pkgs",
+        );
+        assert!(res.is_err());
+        assert_eq!(tree.to_string(), PYTHON_REPLIT_NIX);
+    }
+
+    #[test]
+    fn test_rename_to_dep_already_there() {
+        test_rename(
+            DepType::Regular,
+            "pkgs.python38Full",
+            "pkgs.ncdu",
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+  ];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.stdenv.cc.cc.lib
+      pkgs.zlib
+    ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_rename_to_several_elements() {
+        let tree = rnix::Root::parse(PYTHON_REPLIT_NIX)
+            .syntax()
+            .clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let res = rename_dep(
+            deps_list.node.clone(),
+            Some("pkgs.ncdu".to_string()),
+            Some("pkgs.b pkgs.c".to_string()),
+            "pkgs",
+        );
+        assert!(matches!(res, Err(NixEditorError::InvalidOp(_))));
+        assert_eq!(tree.to_string(), PYTHON_REPLIT_NIX);
+
+        // as one element it's fine
+        let res = rename_dep(
+            deps_list.node,
+            Some("pkgs.ncdu".to_string()),
+            Some("(pkgs.b pkgs.c)".to_string()),
+            "pkgs",
+        );
+        assert!(res.is_ok());
+        assert!(tree.to_string().contains("    (pkgs.b pkgs.c)\n"));
+    }
+
+    #[test]
+    fn test_rename_with_pkgs() {
+        let tree = rnix::Root::parse("{ pkgs }: {\n  deps = with pkgs; [\n    cowsay\n  ];\n}")
+            .syntax()
+            .clone_for_update();
+        let deps_list = verify_get(&tree, DepType::Regular).unwrap();
+
+        let res = rename_dep(
+            deps_list.node,
+            Some("pkgs.cowsay".to_string()),
+            Some("pkgs.ncdu".to_string()),
+            "pkgs",
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            tree.to_string(),
+            "{ pkgs }: {\n  deps = with pkgs; [\n    ncdu\n  ];\n}"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use rnix::{SyntaxElement, SyntaxKind, SyntaxNode};
+
+use crate::error::{NixEditorError, Result};
+use crate::remover::trailing_comment;
+use crate::verify_getter::SyntaxNodeAndWhitespace;
+
+// A dep in a list along with the comments that belong to it: any comment lines
+// directly above it and a comment trailing it on the same line
+struct Entry {
+    // whitespace separating this entry from whatever came before it
+    separator: String,
+    // the comments, dep and trailing comment
+    content: String,
+    dep: String,
+}
+
+pub fn sort_deps(deps_list: SyntaxNodeAndWhitespace) -> Result<SyntaxNode> {
+    let deps_list = deps_list.node;
+
+    let (mut entries, tail) = list_entries(&deps_list);
+    let separators: Vec<String> = entries
+        .iter()
+        .map(|entry| entry.separator.clone())
+        .collect();
+
+    entries.sort_by_key(|entry| dep_sort_key(&entry.dep));
+
+    // the separators stay where they were, only the entries move around,
+    // that way the indentation of every line stays the same
+    let mut new_list = String::from("[");
+    for (separator, entry) in separators.iter().zip(entries.iter()) {
+        new_list.push_str(separator);
+        new_list.push_str(&entry.content);
+    }
+    new_list.push_str(&tail);
+    new_list.push(']');
+
+    if new_list == deps_list.to_string() {
+        return Ok(deps_list);
+    }
+
+    replace_list(deps_list, &new_list)
+}
+
+// What deps are compared by when sorting, the full dotted path ignoring case
+pub fn dep_sort_key(dep: &str) -> String {
+    dep.to_lowercase()
+}
+
+// Splits the inside of a list into its entries, plus whatever trails the last
+// entry before the closing bracket
+fn list_entries(deps_list: &SyntaxNode) -> (Vec<Entry>, String) {
+    let elements: Vec<SyntaxElement> = deps_list.children_with_tokens().collect();
+    // skip the brackets
+    let inner = &elements[1..elements.len() - 1];
+
+    let mut entries: Vec<Entry> = vec![];
+    let mut pending: Vec<&SyntaxElement> = vec![];
+    let mut i = 0;
+    while i < inner.len() {
+        let element = &inner[i];
+        i += 1;
+
+        let node = match element.as_node() {
+            Some(node) => node,
+            None => {
+                pending.push(element);
+                continue;
+            }
+        };
+
+        let mut separator = String::new();
+        let mut content = String::new();
+        for (j, pending_element) in pending.iter().enumerate() {
+            if j == 0 && pending_element.kind() == SyntaxKind::TOKEN_WHITESPACE {
+                separator = pending_element.to_string();
+            } else {
+                content.push_str(&pending_element.to_string());
+            }
+        }
+        pending.clear();
+        content.push_str(&node.to_string());
+
+        // a comment on the same line as the dep belongs to it
+        if let Some(comment) = trailing_comment(node) {
+            // inner is offset by one from the list's children since it skips the opening bracket
+            let comment_end = comment.index();
+            for trailing in &inner[i..comment_end] {
+                content.push_str(&trailing.to_string());
+            }
+            i = comment_end;
+        }
+
+        entries.push(Entry {
+            separator,
+            content,
+            dep: node.to_string(),
+        });
+    }
+
+    let tail = pending.iter().map(|element| element.to_string()).collect();
+
+    (entries, tail)
+}
+
+fn replace_list(deps_list: SyntaxNode, new_list: &str) -> Result<SyntaxNode> {
+    let ast = rnix::Root::parse(new_list);
+    let new_deps_list = ast
+        .syntax()
+        .clone_for_update()
+        .first_child()
+        .filter(|node| ast.errors().is_empty() && node.kind() == SyntaxKind::NODE_LIST)
+        .ok_or_else(|| NixEditorError::InvalidOp("could not rebuild deps list".to_string()))?;
+    new_deps_list.detach();
+
+    let parent = deps_list.parent().ok_or_else(|| {
+        NixEditorError::InvalidOp("expected deps list to have a parent".to_string())
+    })?;
+    let index = deps_list.index();
+    parent.splice_children(
+        index..index + 1,
+        vec![rnix::NodeOrToken::Node(new_deps_list.clone())],
+    );
+
+    Ok(new_deps_list)
+}
+
+#[cfg(test)]
+mod sort_tests {
+    use super::*;
+    use crate::verify_getter::verify_get;
+    use crate::DepType;
+
+    fn test_sort(dep_type: DepType, initial_contents: &str, expected_contents: &str) {
+        let tree = rnix::Root::parse(initial_contents)
+            .syntax()
+            .clone_for_update();
+
+        let deps_list_res = verify_get(&tree, dep_type);
+        assert!(deps_list_res.is_ok());
+
+        let deps_list = deps_list_res.unwrap();
+
+        let new_deps_list = sort_deps(deps_list);
+        assert!(new_deps_list.is_ok());
+
+        assert_eq!(tree.to_string(), expected_contents.to_string());
+    }
+
+    #[test]
+    fn test_regular_sort() {
+        test_sort(
+            DepType::Regular,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.python38Full
+    pkgs.cowsay
+    pkgs.ncdu
+  ];
+}"#,
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+    pkgs.python38Full
+  ];
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_sort_keeps_comments_with_their_dep() {
+        test_sort(
+            DepType::Python,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pandas / numpy
+      pkgs.zlib
+      # Needed for pygame
+      pkgs.glib
+      pkgs.xorg.libX11 # Needed for matplotlib
+      pkgs.stdenv.cc.cc.lib
+    ];
+  };
+}"#,
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      # Needed for pygame
+      pkgs.glib
+      pkgs.stdenv.cc.cc.lib
+      pkgs.xorg.libX11 # Needed for matplotlib
+      # Needed for pandas / numpy
+      pkgs.zlib
+    ];
+  };
+}"#,
+        );
+    }
+
+    #[test]
+    fn test_with_pkgs_one_line_sort() {
+        test_sort(
+            DepType::Regular,
+            r#"{ pkgs }: { deps = with pkgs; [ ncdu cowsay ]; }"#,
+            r#"{ pkgs }: { deps = with pkgs; [ cowsay ncdu ]; }"#,
+        );
+    }
+
+    #[test]
+    fn test_sort_is_stable_for_sorted_list() {
+        let contents = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+  ];
+}"#;
+        test_sort(DepType::Regular, contents, contents);
+    }
+}
@@ -1,22 +1,28 @@
 use anyhow::{bail, Context, Result};
 use rnix::*;
 
-use crate::{DepType, EMPTY_TEMPLATE};
+use crate::adder::parse_elements;
+use crate::error::{self, NixEditorError};
+use crate::merger::qualified_dep;
+use crate::remover::{find_dep, remove_entry};
+use crate::{DepType, FileKind, EMPTY_TEMPLATE};
 
-// kind of like assert! but returns an error instead of panicking
+// kind of like assert_eq! but returns an error instead of panicking. The error
+// has the actual values, along with the expression that was checked
 macro_rules! verify_eq {
     ($a:expr, $b:expr) => {
         if $a != $b {
             bail!(
-                "error: expected {} but got {}",
-                stringify!($b),
+                "error: expected {:?} but got {:?} (from {})",
+                $b,
+                $a,
                 stringify!($a)
             );
         }
     };
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SyntaxNodeAndWhitespace {
     pub whitespace: Option<SyntaxToken>,
     pub node: SyntaxNode,
@@ -26,191 +32,990 @@ pub struct SyntaxNodeAndWhitespace {
 // If at any point, the tree is not *exactly* how we expect it to look,
 // it will return an error. Since nix is so complex, we have to require some
 // assumptions about the AST, or else it'll be impossible to do anything.
-pub fn verify_get(root: &SyntaxNode, dep_type: DepType) -> Result<SyntaxNodeAndWhitespace> {
+pub fn verify_get(root: &SyntaxNode, dep_type: DepType) -> error::Result<SyntaxNodeAndWhitespace> {
+    verify_get_with(root, dep_type, &VerifyOptions::default())
+}
+
+// Like verify_get, but errors on missing keys instead of inserting them,
+// so the tree is never modified
+pub fn verify_get_existing(
+    root: &SyntaxNode,
+    dep_type: DepType,
+) -> error::Result<SyntaxNodeAndWhitespace> {
+    let options = VerifyOptions {
+        insert_missing: false,
+        ..Default::default()
+    };
+    verify_get_with(root, dep_type, &options)
+}
+
+pub fn verify_get_with(
+    root: &SyntaxNode,
+    dep_type: DepType,
+    options: &VerifyOptions,
+) -> error::Result<SyntaxNodeAndWhitespace> {
+    verify_get_deps_list(root, dep_type, options).map_err(verify_error)
+}
+
+// Like verify_get, but stops at the attr set the lambda returns
+// instead of descending into a specific deps list
+pub fn verify_get_attr_set(root: &SyntaxNode) -> error::Result<SyntaxNode> {
+    verify_get_attr_set_with(root, &VerifyOptions::default())
+}
+
+pub fn verify_get_attr_set_with(
+    root: &SyntaxNode,
+    options: &VerifyOptions,
+) -> error::Result<SyntaxNode> {
+    verify_get_lambda_attr_set(root, options).map_err(verify_error)
+}
+
+// Errors that already say exactly what's wrong (like NotAList) are passed
+// through, anything else is a generic VerifyFailed
+fn verify_error(err: anyhow::Error) -> NixEditorError {
+    match err.downcast::<NixEditorError>() {
+        Ok(err) => err,
+        Err(err) => NixEditorError::VerifyFailed(err.to_string()),
+    }
+}
+
+// What verify_get expects the file to look like
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    // the name of the function's argument that nixpkgs is passed in as
+    pub arg_name: String,
+
+    // fill in missing keys from a template instead of erroring
+    pub insert_missing: bool,
+
+    // what kind of file it is, which decides where the deps are looked for
+    pub file_kind: FileKind,
+
+    // a dotted path of keys to the list to use instead of the dep type's list,
+    // e.g. nativeBuildInputs or env.PYTHON_LD_LIBRARY_PATH
+    pub target_path: Option<String>,
+
+    // an env key whose `pkgs.lib.makeLibraryPath [ ... ]` list to use instead of
+    // the dep type's list, e.g. GI_TYPELIB_PATH
+    pub env_key: Option<String>,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            arg_name: "pkgs".to_string(),
+            insert_missing: true,
+            file_kind: FileKind::Replit,
+            target_path: None,
+            env_key: None,
+        }
+    }
+}
+
+fn verify_get_deps_list(
+    root: &SyntaxNode,
+    dep_type: DepType,
+    options: &VerifyOptions,
+) -> Result<SyntaxNodeAndWhitespace> {
+    if let Some(target_path) = &options.target_path {
+        return verify_get_target_path(root, target_path, options);
+    }
+
+    if let Some(env_key) = &options.env_key {
+        if options.file_kind != FileKind::Replit {
+            bail!(
+                "error: env lists can only be edited in replit.nix, not {}",
+                options.file_kind.file_name()
+            );
+        }
+        if !is_plain_key(env_key) {
+            bail!("error: {} is not a valid env key", env_key);
+        }
+        let attr_set = verify_get_lambda_attr_set(root, options)?;
+        return verify_get_ld_library_path(&attr_set, env_key, options);
+    }
+
+    // flakes aren't shaped like replit.nix at all, so they get walked from the root
+    if let DepType::Flake = dep_type {
+        return verify_get_flake(root, options);
+    }
+
+    if options.file_kind != FileKind::Replit {
+        if dep_type != DepType::Regular {
+            bail!(
+                "error: {:?} deps can only be edited in replit.nix, not {}",
+                dep_type,
+                options.file_kind.file_name()
+            );
+        }
+        return verify_get_shell(root, options);
+    }
+
+    let attr_set = verify_get_lambda_attr_set(root, options)?;
+
+    let deps_list = match dep_type {
+        DepType::Regular => verify_get_regular(&attr_set, options)?,
+        DepType::Python | DepType::Node => {
+            verify_get_ld_library_path(&attr_set, lib_path_key(dep_type).unwrap(), options)?
+        }
+        DepType::Flake => unreachable!(),
+    };
+
+    Ok(deps_list)
+}
+
+// The env key a dep type's library path list is bound to in replit.nix
+pub(crate) fn lib_path_key(dep_type: DepType) -> Option<&'static str> {
+    match dep_type {
+        DepType::Python => Some("PYTHON_LD_LIBRARY_PATH"),
+        DepType::Node => Some("LD_LIBRARY_PATH"),
+        DepType::Regular | DepType::Flake => None,
+    }
+}
+
+fn verify_get_lambda_attr_set(root: &SyntaxNode, options: &VerifyOptions) -> Result<SyntaxNode> {
     verify_eq!(root.kind(), SyntaxKind::NODE_ROOT);
+    if options.file_kind != FileKind::Replit {
+        bail!(
+            "error: expected replit.nix but the file kind is {}",
+            options.file_kind.file_name()
+        );
+    }
 
-    if root.children().count() == 0 {
-        root.splice_children(0..0, vec![rnix::NodeOrToken::Node(template_empty())]);
+    // an empty file is started from the template. Any comments in it stay above
+    if options.insert_missing && root.children().count() == 0 {
+        let text = root.to_string();
+        let end = root.children_with_tokens().count();
+        let mut to_insert = vec![rnix::NodeOrToken::Node(template_empty(&options.arg_name)?)];
+        let replaced = match text.trim().is_empty() {
+            true => 0..end,
+            false => {
+                if !text.ends_with('\n') {
+                    to_insert.insert(0, rnix::NodeOrToken::Token(whitespace_token("\n")));
+                }
+                end..end
+            }
+        };
+        root.splice_children(replaced, to_insert);
     }
 
-    let lambda = get_nth_child(&root, 0).context("expected to have a child")?;
+    // lets around the function or inside it just bind values for it to use
+    let lambda = skip_lets(get_nth_child(root, 0).context("expected to have a child")?)?;
     verify_eq!(lambda.kind(), SyntaxKind::NODE_LAMBDA);
 
     let arg_pattern = get_nth_child(&lambda, 0).context("expected to have a child")?;
     verify_eq!(arg_pattern.kind(), SyntaxKind::NODE_PATTERN);
 
-    if find_child_with_value(&arg_pattern, "pkgs").is_none() {
-        bail!("error: expected pkgs");
+    if !pattern_binds(&arg_pattern, &options.arg_name) {
+        bail!("error: expected {}", options.arg_name);
     }
 
-    let attr_set = get_nth_child(&lambda, 1).context("expected to have two children")?;
+    let attr_set = skip_lets(get_nth_child(&lambda, 1).context("expected to have two children")?)?;
+    if attr_set.kind() == SyntaxKind::NODE_APPLY {
+        bail!(
+            "error: expected replit.nix to return an attribute set but got a function call, \
+             use the shell or default file kind for shell.nix and default.nix files"
+        );
+    }
     verify_eq!(attr_set.kind(), SyntaxKind::NODE_ATTR_SET);
 
-    let deps_list = match dep_type {
-        DepType::Regular => verify_get_regular(&attr_set)?,
-        DepType::Python => verify_get_python(&attr_set)?,
-    };
+    Ok(attr_set)
+}
 
-    Ok(deps_list)
+// The body of node if it's a `let ... in` (or several of them), node otherwise
+fn skip_lets(mut node: SyntaxNode) -> Result<SyntaxNode> {
+    while node.kind() == SyntaxKind::NODE_LET_IN {
+        node = node
+            .children()
+            .last()
+            .context("expected let to have a body")?;
+    }
+    Ok(node)
 }
 
-fn verify_get_regular(attr_set: &SyntaxNode) -> Result<SyntaxNodeAndWhitespace> {
-    let deps = find_or_insert_key_value_with_key(&attr_set, "deps", template_deps())
+fn verify_get_regular(
+    attr_set: &SyntaxNode,
+    options: &VerifyOptions,
+) -> Result<SyntaxNodeAndWhitespace> {
+    let deps = find_key_value_maybe_inserting(attr_set, "deps", template_deps, options)?
         .context("expected to have a deps key")?;
-    let whitespace = deps.whitespace;
-    let deps = deps.node;
-    verify_eq!(deps.kind(), SyntaxKind::NODE_ATTRPATH_VALUE);
+    let deps_list = verify_get_list_value(deps, "deps")?;
+
+    merge_duplicate_lists(attr_set, "deps", &deps_list, options)?;
+
+    Ok(deps_list)
+}
+
+// A file can end up binding the same key twice (e.g. after a bad merge), which
+// would hide whatever is in the later lists. Folds them into deps_list, the
+// first one, so nothing gets lost: the later lists' entries go at its back in
+// the order they were in, along with their comments. Deps deps_list already
+// has are left out.
+fn merge_duplicate_lists(
+    attr_set: &SyntaxNode,
+    key: &str,
+    deps_list: &SyntaxNodeAndWhitespace,
+    options: &VerifyOptions,
+) -> Result<()> {
+    let duplicates: Vec<SyntaxNode> = attr_set
+        .children()
+        .filter(|child| child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+        .filter(|child| {
+            get_nth_child(child, 0).map(|key_node| key_node.to_string()) == Some(key.to_string())
+        })
+        .skip(1)
+        .collect();
+
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+    if !options.insert_missing {
+        bail!("error: {} is defined {} times", key, duplicates.len() + 1);
+    }
+
+    for duplicate in duplicates {
+        let duplicate_list = verify_get_list_value(
+            SyntaxNodeAndWhitespace {
+                whitespace: None,
+                node: duplicate.clone(),
+            },
+            key,
+        )?;
+
+        move_entries(&duplicate_list.node, &deps_list.node, &options.arg_name);
+        remove_key_value(attr_set, &duplicate);
+    }
+
+    Ok(())
+}
+
+// Moves the entries of from_list that to_list doesn't have yet to the back of
+// to_list, with everything between them (comments, line breaks) kept as it was
+fn move_entries(from_list: &SyntaxNode, to_list: &SyntaxNode, arg_name: &str) {
+    let deps: Vec<SyntaxNode> = from_list.children().collect();
+    for dep in deps {
+        if find_dep(to_list, &dep.to_string(), arg_name).is_some() {
+            remove_entry(from_list, &dep);
+        } else if !is_with_pkgs(to_list, arg_name) {
+            // bare names only mean the same thing inside another `with pkgs;`
+            let qualified = qualified_dep(from_list, &dep, arg_name);
+            if qualified != dep.to_string() {
+                let index = dep.index();
+                from_list.splice_children(index..index + 1, parse_elements(&qualified));
+            }
+        }
+    }
+
+    // everything between the brackets, leaving out the whitespace before `]`
+    let mut elements: Vec<SyntaxElement> = from_list.children_with_tokens().collect();
+    elements.retain(|element| {
+        !matches!(
+            element.kind(),
+            SyntaxKind::TOKEN_L_BRACK | SyntaxKind::TOKEN_R_BRACK
+        )
+    });
+    while elements
+        .last()
+        .is_some_and(|element| element.kind() == SyntaxKind::TOKEN_WHITESPACE)
+    {
+        elements.pop();
+    }
+    if elements.is_empty() {
+        return;
+    }
+    for element in &elements {
+        element.detach();
+    }
+    if elements[0].kind() != SyntaxKind::TOKEN_WHITESPACE {
+        elements.splice(0..0, parse_elements(" "));
+    }
+
+    // in front of the whitespace before `]`, if there is any
+    let close = to_list
+        .children_with_tokens()
+        .last()
+        .map(|close| close.index())
+        .unwrap_or(0);
+    let index = match to_list.children_with_tokens().nth(close.saturating_sub(1)) {
+        Some(before) if before.kind() == SyntaxKind::TOKEN_WHITESPACE => close - 1,
+        _ => close,
+    };
+    to_list.splice_children(index..index, elements);
+}
+
+// Expects flake.nix to look something like
+// {
+//   outputs = { self, nixpkgs }: let pkgs = ...; in {
+//     devShells.x86_64-linux.default = pkgs.mkShell {
+//       buildInputs = [ ... ];
+//     };
+//   };
+// }
+fn verify_get_flake(root: &SyntaxNode, options: &VerifyOptions) -> Result<SyntaxNodeAndWhitespace> {
+    verify_eq!(root.kind(), SyntaxKind::NODE_ROOT);
+
+    let flake = get_nth_child(root, 0).context("expected flake to have a child")?;
+    verify_eq!(flake.kind(), SyntaxKind::NODE_ATTR_SET);
+
+    let outputs = find_key_value_with_key(&flake, "outputs")
+        .context("expected flake to have an outputs key")?
+        .node;
+
+    let lambda = get_nth_child(&outputs, 1).context("expected to have two children")?;
+    verify_eq!(lambda.kind(), SyntaxKind::NODE_LAMBDA);
+
+    let body = get_nth_child(&lambda, 1).context("expected to have two children")?;
+    // outputs usually binds pkgs in a let before returning the attr set
+    let body = skip_lets(body)?;
+    verify_eq!(body.kind(), SyntaxKind::NODE_ATTR_SET);
+
+    let dev_shell = find_key_value(&body, |key| {
+        key.starts_with("devShells.") && key.ends_with(".default")
+    })
+    .context("expected outputs to have a devShells.<system>.default key")?
+    .node;
+
+    let mk_shell = get_nth_child(&dev_shell, 1).context("expected to have two children")?;
+    verify_eq!(mk_shell.kind(), SyntaxKind::NODE_APPLY);
+
+    let mk_shell_fn = get_nth_child(&mk_shell, 0).context("expected to have a child")?;
+    if !matches!(mk_shell_fn.to_string().as_str(), "pkgs.mkShell" | "mkShell") {
+        bail!("error: expected devShell to be made with mkShell");
+    }
+
+    let shell_attr_set = get_nth_child(&mk_shell, 1).context("expected to have two children")?;
+    verify_eq!(shell_attr_set.kind(), SyntaxKind::NODE_ATTR_SET);
+
+    verify_get_build_inputs(&shell_attr_set, options)
+}
+
+// Expects shell.nix to look something like
+// { pkgs ? import <nixpkgs> {} }:
+// pkgs.mkShell {
+//   buildInputs = [ ... ];
+// }
+// where the function and any lets or withs around the mkShell call are optional.
+// default.nix can also make a package with mkDerivation instead.
+fn verify_get_shell(root: &SyntaxNode, options: &VerifyOptions) -> Result<SyntaxNodeAndWhitespace> {
+    let attr_set = verify_get_shell_attr_set(root, options)?;
+    verify_get_build_inputs(&attr_set, options)
+}
+
+// The attr set passed to the mkShell or mkDerivation call in shell.nix or default.nix
+fn verify_get_shell_attr_set(root: &SyntaxNode, options: &VerifyOptions) -> Result<SyntaxNode> {
+    verify_eq!(root.kind(), SyntaxKind::NODE_ROOT);
+    let file_name = options.file_kind.file_name();
+    let builders: &[&str] = match options.file_kind {
+        FileKind::Default => &["mkShell", "mkDerivation"],
+        _ => &["mkShell"],
+    };
+
+    let mut body = get_nth_child(root, 0)
+        .with_context(|| format!("expected {} to have a child", file_name))?;
+    while matches!(
+        body.kind(),
+        SyntaxKind::NODE_LAMBDA
+            | SyntaxKind::NODE_WITH
+            | SyntaxKind::NODE_LET_IN
+            | SyntaxKind::NODE_PAREN
+    ) {
+        body = body.children().last().context("expected to have a body")?;
+    }
+
+    if body.kind() != SyntaxKind::NODE_APPLY {
+        bail!(
+            "error: expected {} to call {} but got {}",
+            file_name,
+            builders.join(" or "),
+            node_kind_name(body.kind())
+        );
+    }
+
+    // the builder can be called through anything, e.g. pkgs.mkShell or stdenv.mkDerivation
+    let builder = get_nth_child(&body, 0).context("expected to have a child")?;
+    let builder_name = builder.to_string();
+    if !builders.contains(&builder_name.rsplit('.').next().unwrap_or_default()) {
+        bail!(
+            "error: expected {} to call {} but it calls {}",
+            file_name,
+            builders.join(" or "),
+            builder_name
+        );
+    }
+
+    let attr_set = get_nth_child(&body, 1).context("expected to have two children")?;
+    verify_eq!(attr_set.kind(), SyntaxKind::NODE_ATTR_SET);
+
+    Ok(attr_set)
+}
+
+// The list at a dotted path of keys, starting from the attr set the file's deps
+// are normally in. Missing keys along the way are filled in with `{}` and the
+// last one with `[]`. A list passed to a function, like the one in
+// `pkgs.lib.makeLibraryPath [ ... ]`, is used as is.
+fn verify_get_target_path(
+    root: &SyntaxNode,
+    target_path: &str,
+    options: &VerifyOptions,
+) -> Result<SyntaxNodeAndWhitespace> {
+    let keys: Vec<&str> = target_path.split('.').collect();
+    if !keys.iter().all(|key| is_plain_key(key)) {
+        bail!("error: {} is not a dotted path of keys", target_path);
+    }
+    let (last_key, parent_keys) = keys.split_last().unwrap();
+
+    let mut attr_set = match options.file_kind {
+        FileKind::Replit => verify_get_lambda_attr_set(root, options)?,
+        _ => verify_get_shell_attr_set(root, options)?,
+    };
+    for key in parent_keys {
+        let template = || parse_binding_template(&format!("{{\n  {} = {{}};\n}}", key));
+        let key_value = find_key_value_maybe_inserting(&attr_set, key, template, options)?
+            .with_context(|| format!("expected to have a {} key", key))?;
+        attr_set = get_nth_child(&key_value.node, 1).context("expected to have two children")?;
+        if attr_set.kind() != SyntaxKind::NODE_ATTR_SET {
+            bail!(
+                "error: expected {} to be an attribute set but got {}",
+                key,
+                node_kind_name(attr_set.kind())
+            );
+        }
+    }
+
+    let template = || parse_binding_template(&format!("{{\n  {} = [];\n}}", last_key));
+    let key_value = find_key_value_maybe_inserting(&attr_set, last_key, template, options)?
+        .with_context(|| format!("expected to have a {} key", last_key))?;
 
-    let value = get_nth_child(&deps, 1).context("expected to have two children")?;
+    let value = get_nth_child(&key_value.node, 1).context("expected to have two children")?;
+    let argument = get_nth_child(&value, 1).filter(|_| value.kind() == SyntaxKind::NODE_APPLY);
+    match argument {
+        Some(list) if list.kind() == SyntaxKind::NODE_LIST => Ok(SyntaxNodeAndWhitespace {
+            whitespace: key_value.whitespace,
+            node: list,
+        }),
+        _ => verify_get_list_value(key_value, last_key),
+    }
+}
+
+// Whether key can be written as is in a binding, without quotes
+fn is_plain_key(key: &str) -> bool {
+    key.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'')
+}
+
+// The buildInputs list in a mkShell or mkDerivation call's attr set
+fn verify_get_build_inputs(
+    attr_set: &SyntaxNode,
+    options: &VerifyOptions,
+) -> Result<SyntaxNodeAndWhitespace> {
+    let build_inputs =
+        find_key_value_maybe_inserting(attr_set, "buildInputs", template_build_inputs, options)?
+            .context("expected to have a buildInputs key")?;
+    let build_inputs_list = verify_get_list_value(build_inputs, "buildInputs")?;
 
-    let deps_list = match value.kind() {
-        SyntaxKind::NODE_LIST => value,
-        SyntaxKind::NODE_WITH => {
-            get_nth_child(&value, 1).context("expected to have at least two children")?
+    merge_duplicate_lists(attr_set, "buildInputs", &build_inputs_list, options)?;
+
+    Ok(build_inputs_list)
+}
+
+// The list a key is bound to, either directly or through `with pkgs;`
+fn verify_get_list_value(
+    key_value: SyntaxNodeAndWhitespace,
+    key: &str,
+) -> Result<SyntaxNodeAndWhitespace> {
+    let mut whitespace = key_value.whitespace;
+    let mut key_value = key_value.node;
+    let mut key = key.to_string();
+
+    // a key bound to a variable gets its list from where the variable is bound in a
+    // let, e.g. `let deps = [ ... ]; in { pkgs }: { deps = deps; }`. Only a few
+    // variables are followed so ones bound to each other can't loop forever
+    let mut variables_followed = 0;
+    let list = loop {
+        verify_eq!(key_value.kind(), SyntaxKind::NODE_ATTRPATH_VALUE);
+        let value = get_nth_child(&key_value, 1).context("expected to have two children")?;
+
+        let binding = match value.kind() {
+            SyntaxKind::NODE_IDENT if variables_followed < MAX_VARIABLES_FOLLOWED => {
+                find_let_binding(&key_value, &value.to_string())
+            }
+            _ => None,
+        };
+        match binding {
+            Some(binding) => {
+                variables_followed += 1;
+                key = value.to_string();
+                whitespace = binding.whitespace;
+                key_value = binding.node;
+            }
+            None if value.kind() == SyntaxKind::NODE_WITH => {
+                break get_nth_child(&value, 1)
+                    .context("expected to have at least two children")?;
+            }
+            None => break value,
         }
-        _ => bail!("unexpected value for deps, expected either with pkgs; or a list"),
     };
-    verify_eq!(deps_list.kind(), SyntaxKind::NODE_LIST);
+
+    // in `deps = baseDeps ++ [ ... ];` only the literal list can be edited
+    let list = if is_concatenation(&list) {
+        concatenated_list(&list).ok_or_else(|| NixEditorError::NotAList {
+            key: key.clone(),
+            kind: "a `++` concatenation of non-literal lists".to_string(),
+        })?
+    } else {
+        list
+    };
+    verify_is_list(&list, &key)?;
 
     Ok(SyntaxNodeAndWhitespace {
         whitespace,
-        node: deps_list,
+        node: list,
     })
 }
 
-fn find_or_insert_key_value_with_key(
+const MAX_VARIABLES_FOLLOWED: usize = 8;
+
+// The binding of name in the closest let around node, other than node itself
+fn find_let_binding(node: &SyntaxNode, name: &str) -> Option<SyntaxNodeAndWhitespace> {
+    node.ancestors()
+        .filter(|ancestor| ancestor.kind() == SyntaxKind::NODE_LET_IN)
+        .find_map(|let_in| find_key_value_with_key(&let_in, name))
+        .filter(|binding| binding.node != *node)
+}
+
+// Whether the deps list is wrapped in `with pkgs;` (or whatever arg_name the
+// file calls the package set), which brings everything in the package set into
+// scope so `ncdu` and `pkgs.ncdu` refer to the same dep. A list concatenated
+// onto others, like `with pkgs; [ cowsay ] ++ [ htop ]`, is wrapped too
+pub(crate) fn is_with_pkgs(deps_list: &SyntaxNode, arg_name: &str) -> bool {
+    deps_list
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| {
+            !matches!(
+                ancestor.kind(),
+                SyntaxKind::NODE_BIN_OP | SyntaxKind::NODE_PAREN
+            )
+        })
+        .filter(|parent| parent.kind() == SyntaxKind::NODE_WITH)
+        .and_then(|with| get_nth_child(&with, 0))
+        .map(|namespace| namespace.to_string() == arg_name)
+        .unwrap_or(false)
+}
+
+// Whether the dep node in deps_list is the dep a caller asked for. String
+// deps also match on their unquoted value, so `foo` finds `"foo"`.
+pub(crate) fn dep_matches(
+    deps_list: &SyntaxNode,
+    dep_node: &SyntaxNode,
+    dep: &str,
+    arg_name: &str,
+) -> bool {
+    let dep = normalize_dep(deps_list, dep, arg_name);
+    same_dep(
+        &normalize_dep(deps_list, &dep_node.to_string(), arg_name),
+        &dep,
+    ) || string_dep_value(dep_node).as_deref() == Some(dep.as_str())
+}
+
+// Whether two deps are the same expression, ignoring how they're spaced out.
+// Long deps like `(pkgs.python3.withPackages (ps: [ ps.numpy ]))` are easy to
+// write with slightly different spacing than the list has.
+pub(crate) fn same_dep(a: &str, b: &str) -> bool {
+    // without any whitespace, the tokens are only the same if the text is
+    let has_whitespace = |dep: &str| dep.contains(char::is_whitespace);
+    a == b || ((has_whitespace(a) || has_whitespace(b)) && dep_key(a) == dep_key(b))
+}
+
+// dep's tokens with a single space between each, leaving out whitespace and
+// comments, so deps only differing in spacing have the same key
+pub(crate) fn dep_key(dep: &str) -> String {
+    rnix::Root::parse(dep)
+        .syntax()
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .filter(|token| {
+            !matches!(
+                token.kind(),
+                SyntaxKind::TOKEN_WHITESPACE | SyntaxKind::TOKEN_COMMENT
+            )
+        })
+        .map(|token| token.text().to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The value of a `"..."` string dep without its quotes or escapes
+pub(crate) fn string_dep_value(dep_node: &SyntaxNode) -> Option<String> {
+    if dep_node.kind() != SyntaxKind::NODE_STRING {
+        return None;
+    }
+
+    let text = dep_node.to_string();
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some(escaped) => value.push(escaped),
+                None => value.push('\\'),
+            },
+            _ => value.push(c),
+        }
+    }
+
+    Some(value)
+}
+
+// The form of a dep used when returning or comparing deps in deps_list,
+// under `with pkgs;` the pkgs. prefix is dropped so both spellings line up
+pub(crate) fn normalize_dep(deps_list: &SyntaxNode, dep: &str, arg_name: &str) -> String {
+    if is_with_pkgs(deps_list, arg_name) {
+        if let Some(name) = dep.strip_prefix(&format!("{}.", arg_name)) {
+            return name.to_string();
+        }
+    }
+
+    dep.to_string()
+}
+
+pub(crate) fn find_or_insert_key_value_with_key(
     node: &SyntaxNode,
     key: &str,
     if_missing_template: SyntaxNode,
 ) -> Option<SyntaxNodeAndWhitespace> {
-    let found = find_key_value_with_key(&node, key);
+    let found = find_key_value_with_key(node, key);
     if found.is_some() {
         return found;
     }
-    let count = node.children().count() + 2;
 
-    node.splice_children(
-        count..count,
-        vec![
-            rnix::NodeOrToken::Node(rnix::Root::parse("\n  ").syntax().clone_for_update()),
-            rnix::NodeOrToken::Node(if_missing_template),
-        ],
-    );
+    insert_key_value(node, if_missing_template);
 
-    let result = find_key_value_with_key(&node, key);
-    result
+    find_key_value_with_key(node, key)
 }
 
-fn template_empty() -> SyntaxNode {
-    let ast = rnix::Root::parse(EMPTY_TEMPLATE);
-    let errors = ast.errors();
-    if errors.len() > 0 {
-        panic!("template_empty had an error: {:#?}", errors)
+fn find_key_value_maybe_inserting(
+    node: &SyntaxNode,
+    key: &str,
+    if_missing_template: impl FnOnce() -> Result<SyntaxNode>,
+    options: &VerifyOptions,
+) -> Result<Option<SyntaxNodeAndWhitespace>> {
+    if !options.insert_missing {
+        return Ok(find_key_value_with_key(node, key));
     }
-    ast.syntax().first_child().unwrap().clone_for_update()
+
+    // only build the template when the key is actually missing
+    if let Some(found) = find_key_value_with_key(node, key) {
+        return Ok(Some(found));
+    }
+    Ok(find_or_insert_key_value_with_key(
+        node,
+        key,
+        if_missing_template()?,
+    ))
 }
 
-fn template_deps() -> SyntaxNode {
-    let python_env_template = r#"{
-  deps = [];
-}"#;
-    let ast = rnix::Root::parse(python_env_template);
-    let errors = ast.errors();
-    if errors.len() > 0 {
-        panic!("template_deps had an error: {:#?}", errors)
+// Removes key_value from attr_set along with the whitespace leading up to it,
+// so it doesn't leave a blank line behind
+pub(crate) fn remove_key_value(attr_set: &SyntaxNode, key_value: &SyntaxNode) {
+    let index = key_value.index();
+    let start = match key_value.prev_sibling_or_token() {
+        Some(prev) if prev.kind() == SyntaxKind::TOKEN_WHITESPACE => index - 1,
+        _ => index,
+    };
+    attr_set.splice_children(start..index + 1, vec![]);
+}
+
+// Appends key_value after the last binding in attr_set, indented to match the
+// existing bindings (or one level deeper than the attr set if it has none)
+pub(crate) fn insert_key_value(attr_set: &SyntaxNode, key_value: SyntaxNode) {
+    let last_key_value = attr_set.children().last();
+
+    let indent = match attr_set.children().next() {
+        Some(first_key_value) => line_indent(&first_key_value),
+        None => line_indent(attr_set) + "  ",
+    };
+
+    let mut to_insert = vec![
+        rnix::NodeOrToken::Token(whitespace_token(&format!("\n{}", indent))),
+        rnix::NodeOrToken::Node(key_value),
+    ];
+
+    // a one line attr set like `{}` needs a newline before its closing brace too
+    if !attr_set.to_string().contains('\n') {
+        to_insert.push(rnix::NodeOrToken::Token(whitespace_token(&format!(
+            "\n{}",
+            line_indent(attr_set)
+        ))));
     }
-    ast.syntax()
-        .first_child()
-        .unwrap()
-        .first_child()
-        .unwrap()
-        .clone_for_update()
+
+    let index = match last_key_value {
+        Some(last_key_value) => last_key_value.index() + 1,
+        // right after the opening brace
+        None => 1,
+    };
+
+    attr_set.splice_children(index..index, to_insert);
 }
 
-fn template_env() -> SyntaxNode {
-    let python_env_template = r#"{
-  env = {
-    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [];
-  };
-}"#;
-    let ast = rnix::Root::parse(python_env_template);
-    let errors = ast.errors();
-    if errors.len() > 0 {
-        panic!("template_env had an error: {:#?}", errors)
+// The leading whitespace of the line that node starts on
+pub(crate) fn line_indent(node: &SyntaxNode) -> String {
+    let mut token = node.first_token().and_then(|token| token.prev_token());
+    while let Some(t) = token {
+        if let Some((_, indent)) = t.text().rsplit_once('\n') {
+            if t.kind() != SyntaxKind::TOKEN_WHITESPACE {
+                return String::new();
+            }
+            return indent.to_string();
+        }
+        token = t.prev_token();
     }
-    ast.syntax()
-        .first_child()
-        .unwrap()
+    String::new()
+}
+
+fn whitespace_token(whitespace: &str) -> SyntaxToken {
+    let root = rnix::Root::parse(whitespace).syntax().clone_for_update();
+    let token = root.first_token().unwrap();
+    token.detach();
+    token
+}
+
+// Parses src and returns the expression in it, detached so it can be spliced into another tree
+fn parse_template(src: &str) -> Result<SyntaxNode> {
+    Ok(parse_template_expr(src)?.clone_for_update())
+}
+
+// Parses a template of the form `{ key = value; }` and returns its binding
+pub(crate) fn parse_binding_template(src: &str) -> Result<SyntaxNode> {
+    let binding = parse_template_expr(src)?
         .first_child()
-        .unwrap()
+        .context("expected template to have a binding")?;
+    Ok(binding.clone_for_update())
+}
+
+// A detached `key = value;` binding, or None if that isn't valid nix or key
+// isn't a single key
+pub(crate) fn parse_key_value(key: &str, value: &str) -> Option<SyntaxNode> {
+    let ast = rnix::Root::parse(&format!("{{ {} = {}; }}", key, value));
+    let key_value = ast
+        .syntax()
         .clone_for_update()
+        .first_child()
+        .and_then(|attr_set| attr_set.first_child())?;
+
+    let is_key =
+        get_nth_child(&key_value, 0).map(|key_node| key_node.to_string()) == Some(key.to_string());
+    if !ast.errors().is_empty() || key_value.kind() != SyntaxKind::NODE_ATTRPATH_VALUE || !is_key {
+        return None;
+    }
+
+    key_value.detach();
+    Some(key_value)
 }
 
-fn template_python() -> SyntaxNode {
-    let python_env_template = r#"{
-    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [];
-}"#;
-    let ast = rnix::Root::parse(python_env_template);
+fn parse_template_expr(src: &str) -> Result<SyntaxNode> {
+    let ast = rnix::Root::parse(src);
     let errors = ast.errors();
-    if errors.len() > 0 {
-        panic!("template_python had an error: {:#?}", errors)
+    if !errors.is_empty() {
+        bail!("error: template had an error: {:?}", errors);
     }
+
     ast.syntax()
         .first_child()
-        .unwrap()
-        .first_child()
-        .unwrap()
-        .clone_for_update()
+        .context("expected template to have an expression")
 }
 
-fn verify_get_python(attr_set: &SyntaxNode) -> Result<SyntaxNodeAndWhitespace> {
-    let env = find_or_insert_key_value_with_key(&attr_set, "env", template_env())
-        .context("expected to have env key")?
-        .node;
+fn template_empty(arg_name: &str) -> Result<SyntaxNode> {
+    parse_template(&EMPTY_TEMPLATE.replacen("pkgs", arg_name, 1))
+}
+
+fn template_deps() -> Result<SyntaxNode> {
+    parse_binding_template("{\n  deps = [];\n}")
+}
+
+fn template_build_inputs() -> Result<SyntaxNode> {
+    parse_binding_template("{\n  buildInputs = [];\n}")
+}
+
+fn template_env(arg_name: &str, env_key: &str) -> Result<SyntaxNode> {
+    parse_binding_template(&format!(
+        "{{\n  env = {{\n    {} = {}.lib.makeLibraryPath [];\n  }};\n}}",
+        env_key, arg_name
+    ))
+}
+
+fn template_ld_library_path(arg_name: &str, env_key: &str) -> Result<SyntaxNode> {
+    parse_binding_template(&format!(
+        "{{\n    {} = {}.lib.makeLibraryPath [];\n}}",
+        env_key, arg_name
+    ))
+}
+
+// The list in `env_key = pkgs.lib.makeLibraryPath [ ... ];` inside the env block,
+// e.g. PYTHON_LD_LIBRARY_PATH for python deps
+fn verify_get_ld_library_path(
+    attr_set: &SyntaxNode,
+    env_key: &str,
+    options: &VerifyOptions,
+) -> Result<SyntaxNodeAndWhitespace> {
+    let env = find_key_value_maybe_inserting(
+        attr_set,
+        "env",
+        || template_env(&options.arg_name, env_key),
+        options,
+    )?
+    .context("expected to have env key")?
+    .node;
     verify_eq!(env.kind(), SyntaxKind::NODE_ATTRPATH_VALUE);
 
     let env_attr_set = get_nth_child(&env, 1).context("expected to have two children")?;
     verify_eq!(env_attr_set.kind(), SyntaxKind::NODE_ATTR_SET);
 
-    let py_lib_path = find_or_insert_key_value_with_key(
+    let lib_path = find_key_value_maybe_inserting(
         &env_attr_set,
-        "PYTHON_LD_LIBRARY_PATH",
-        template_python(),
-    )
-    .context("expected to have PYTHON_LD_LIBRARY_PATH key")?;
-    let whitespace = py_lib_path.whitespace;
-    let py_lib_path = py_lib_path.node;
-    verify_eq!(py_lib_path.kind(), SyntaxKind::NODE_ATTRPATH_VALUE);
+        env_key,
+        || template_ld_library_path(&options.arg_name, env_key),
+        options,
+    )?
+    .with_context(|| format!("expected to have {} key", env_key))?;
+    let whitespace = lib_path.whitespace;
+    let lib_path = lib_path.node;
+    verify_eq!(lib_path.kind(), SyntaxKind::NODE_ATTRPATH_VALUE);
 
-    let py_lib_apply = get_nth_child(&py_lib_path, 1).context("expected to have two children")?;
-    verify_eq!(py_lib_apply.kind(), SyntaxKind::NODE_APPLY);
+    let lib_apply = get_nth_child(&lib_path, 1).context("expected to have two children")?;
+    verify_eq!(lib_apply.kind(), SyntaxKind::NODE_APPLY);
 
-    let py_lib_node_select = get_nth_child(&py_lib_apply, 0).context("expected to have a child")?;
-    verify_eq!(py_lib_node_select.kind(), SyntaxKind::NODE_SELECT);
-    verify_eq!(py_lib_node_select.text(), "pkgs.lib.makeLibraryPath");
+    let lib_node_select = get_nth_child(&lib_apply, 0).context("expected to have a child")?;
+    verify_eq!(lib_node_select.kind(), SyntaxKind::NODE_SELECT);
+    if !is_make_library_path(&lib_node_select.to_string(), &options.arg_name) {
+        bail!(
+            "error: expected {}.lib.makeLibraryPath but got {}",
+            options.arg_name,
+            lib_node_select.text()
+        );
+    }
 
-    let py_lib_node_list =
-        get_nth_child(&py_lib_apply, 1).context("expected to have two children")?;
-    verify_eq!(py_lib_node_list.kind(), SyntaxKind::NODE_LIST);
+    let lib_node_list = get_nth_child(&lib_apply, 1).context("expected to have two children")?;
+    verify_is_list(&lib_node_list, env_key)?;
 
     Ok(SyntaxNodeAndWhitespace {
         whitespace,
-        node: py_lib_node_list,
+        node: lib_node_list,
     })
 }
 
-fn get_nth_child(node: &SyntaxNode, index: usize) -> Option<SyntaxNode> {
-    node.children().into_iter().nth(index)
+// Whether select names makeLibraryPath. With `with pkgs;` or `lib` in scope it
+// doesn't need the prefix, and pkgs works even if the argument is named something else
+fn is_make_library_path(select: &str, arg_name: &str) -> bool {
+    let full_name = format!("{}.lib.makeLibraryPath", arg_name);
+    [
+        full_name.as_str(),
+        "pkgs.lib.makeLibraryPath",
+        "lib.makeLibraryPath",
+    ]
+    .iter()
+    .any(|name| same_dep(select, name))
 }
 
-fn find_child_with_value(node: &SyntaxNode, name: &str) -> Option<SyntaxNode> {
+fn is_concatenation(node: &SyntaxNode) -> bool {
+    node.kind() == SyntaxKind::NODE_BIN_OP
+        && node
+            .children_with_tokens()
+            .any(|child| child.kind() == SyntaxKind::TOKEN_CONCAT)
+}
+
+// Whether deps_list is only part of the deps, concatenated with other lists
+pub(crate) fn is_concatenated(deps_list: &SyntaxNode) -> bool {
+    deps_list
+        .parent()
+        .is_some_and(|parent| is_concatenation(&parent))
+}
+
+// The last literal list among the lists node concatenates, which is usually
+// the one added on to a shared list of deps
+fn concatenated_list(node: &SyntaxNode) -> Option<SyntaxNode> {
     node.children()
-        .into_iter()
-        .find(|child| child.text() == name)
+        .filter_map(|operand| match operand.kind() {
+            SyntaxKind::NODE_LIST => Some(operand),
+            _ if is_concatenation(&operand) => concatenated_list(&operand),
+            _ => None,
+        })
+        .last()
+}
+
+// Deps bound to anything but a literal list (e.g. `deps = import ./deps.nix;`)
+// can't be edited, so this errors with what key is bound to instead
+fn verify_is_list(node: &SyntaxNode, key: &str) -> Result<()> {
+    if node.kind() == SyntaxKind::NODE_LIST {
+        return Ok(());
+    }
+
+    Err(NixEditorError::NotAList {
+        key: key.to_string(),
+        kind: node_kind_name(node.kind()),
+    }
+    .into())
+}
+
+// A readable name for the kind of expression a node is, e.g. "a function call"
+fn node_kind_name(kind: SyntaxKind) -> String {
+    match kind {
+        SyntaxKind::NODE_APPLY => "a function call".to_string(),
+        SyntaxKind::NODE_SELECT => "an attribute lookup".to_string(),
+        SyntaxKind::NODE_IDENT => "a variable".to_string(),
+        SyntaxKind::NODE_ATTR_SET => "an attribute set".to_string(),
+        SyntaxKind::NODE_BIN_OP => "a binary operation".to_string(),
+        SyntaxKind::NODE_LET_IN => "a let expression".to_string(),
+        SyntaxKind::NODE_IF_ELSE => "an if expression".to_string(),
+        SyntaxKind::NODE_STRING => "a string".to_string(),
+        SyntaxKind::NODE_PATH => "a path".to_string(),
+        _ => format!("{:?}", kind)
+            .trim_start_matches("NODE_")
+            .to_lowercase()
+            .replace('_', " "),
+    }
+}
+
+pub(crate) fn get_nth_child(node: &SyntaxNode, index: usize) -> Option<SyntaxNode> {
+    node.children().nth(index)
+}
+
+// Whether one of the entries in a `{ a, b ? default, ... }` pattern binds name,
+// the ellipsis and any other args don't matter
+fn pattern_binds(pattern: &SyntaxNode, name: &str) -> bool {
+    pattern
+        .children()
+        .filter(|child| child.kind() == SyntaxKind::NODE_PAT_ENTRY)
+        .filter_map(|entry| get_nth_child(&entry, 0))
+        .any(|ident| ident.text() == name)
 }
 
-fn find_key_value_with_key(node: &SyntaxNode, key: &str) -> Option<SyntaxNodeAndWhitespace> {
-    if node.kind() != SyntaxKind::NODE_ATTR_SET {
+pub(crate) fn find_key_value_with_key(
+    node: &SyntaxNode,
+    key: &str,
+) -> Option<SyntaxNodeAndWhitespace> {
+    find_key_value(node, |key_text| key_text == key)
+}
+
+// Like find_key_value_with_key, but matches the first key for which is_key returns true
+fn find_key_value(
+    node: &SyntaxNode,
+    is_key: impl Fn(&str) -> bool,
+) -> Option<SyntaxNodeAndWhitespace> {
+    // a let's bindings are laid out just like an attr set's
+    if !matches!(
+        node.kind(),
+        SyntaxKind::NODE_ATTR_SET | SyntaxKind::NODE_LET_IN
+    ) {
         return None;
     }
 
@@ -243,16 +1048,13 @@ fn find_key_value_with_key(node: &SyntaxNode, key: &str) -> Option<SyntaxNodeAnd
             None => return false,
         };
 
-        key_node.text() == key
+        is_key(&key_node.to_string())
     });
 
-    match node {
-        Some(node_or_token) => Some(SyntaxNodeAndWhitespace {
-            whitespace: last_whitespace,
-            node: node_or_token.as_node().unwrap().clone(),
-        }),
-        _ => None,
-    }
+    node.map(|node_or_token| SyntaxNodeAndWhitespace {
+        whitespace: last_whitespace,
+        node: node_or_token.as_node().unwrap().clone(),
+    })
 }
 
 // unit tests
@@ -329,6 +1131,75 @@ mod verify_get_tests {
         assert_eq!(deps_list_children.len(), 0);
     }
 
+    #[test]
+    fn verify_get_inserts_python_after_other_env_keys() {
+        let ast = rnix::Root::parse(
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHONBIN = "${pkgs.python38Full}/bin/python3.8";
+    LANG = "en_US.UTF-8";
+  };
+}"#,
+        )
+        .syntax()
+        .clone_for_update();
+        assert!(verify_get(&ast, DepType::Python).is_ok());
+
+        assert_eq!(
+            ast.to_string(),
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHONBIN = "${pkgs.python38Full}/bin/python3.8";
+    LANG = "en_US.UTF-8";
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [];
+  };
+}"#
+        );
+    }
+
+    #[test]
+    fn parse_template_errors_instead_of_panicking() {
+        assert!(parse_template("{ deps = [; }").is_err());
+        assert!(parse_binding_template("{}").is_err());
+
+        let binding = parse_binding_template("{ deps = []; }").unwrap();
+        assert_eq!(binding.kind(), SyntaxKind::NODE_ATTRPATH_VALUE);
+        assert_eq!(binding.to_string(), "deps = [];");
+    }
+
+    #[test]
+    fn verify_get_node() {
+        let ast = rnix::Root::parse(
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+  };
+}"#,
+        )
+        .syntax()
+        .clone_for_update();
+        let deps_list = verify_get(&ast, DepType::Node).unwrap();
+        assert_eq!(deps_list.node.children().count(), 0);
+
+        assert_eq!(
+            ast.to_string(),
+            r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [
+      pkgs.zlib
+    ];
+    LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath [];
+  };
+}"#
+        );
+    }
+
     #[test]
     fn verify_get_python() {
         let deps_list = gets_ok(PYTHON_REPLIT_NIX, DepType::Python);
@@ -370,4 +1241,485 @@ mod verify_get_tests {
         assert_eq!(deps_list_children[0].text(), "pkgs.python38Full");
         assert_eq!(deps_list_children[0].kind(), SyntaxKind::NODE_SELECT);
     }
+
+    const FLAKE_NIX: &str = r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+
+  outputs = { self, nixpkgs }:
+    let
+      pkgs = nixpkgs.legacyPackages.x86_64-linux;
+    in {
+      devShells.x86_64-linux.default = pkgs.mkShell {
+        buildInputs = [
+          pkgs.cowsay
+          pkgs.ncdu
+        ];
+      };
+    };
+}"#;
+
+    #[test]
+    fn verify_get_flake() {
+        let deps_list = gets_ok(FLAKE_NIX, DepType::Flake).node;
+        let deps: Vec<String> = deps_list
+            .children()
+            .map(|child| child.to_string())
+            .collect();
+        assert_eq!(deps, vec!["pkgs.cowsay", "pkgs.ncdu"]);
+    }
+
+    #[test]
+    fn verify_get_flake_when_missing_build_inputs() {
+        let code = r#"{
+  outputs = { self, nixpkgs }: {
+    devShells.x86_64-linux.default = pkgs.mkShell {
+    };
+  };
+}"#;
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        let deps_list = verify_get(&ast, DepType::Flake);
+        assert!(deps_list.is_ok());
+        assert_eq!(deps_list.unwrap().node.to_string(), "[]");
+        assert!(ast.to_string().contains("buildInputs = [];"));
+    }
+
+    #[test]
+    fn verify_get_flake_errors_on_unknown_shape() {
+        let ast = rnix::Root::parse(PYTHON_REPLIT_NIX)
+            .syntax()
+            .clone_for_update();
+        assert!(verify_get(&ast, DepType::Flake).is_err());
+
+        let code = r#"{
+  outputs = { self, flake-utils }: flake-utils.lib.eachDefaultSystem (system: {});
+}"#;
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert!(verify_get(&ast, DepType::Flake).is_err());
+    }
+
+    #[test]
+    fn verify_get_errors_on_non_list_deps() {
+        for (value, kind) in [
+            ("import ./deps.nix", "a function call"),
+            ("pkgs.lib.foo", "an attribute lookup"),
+            ("with pkgs; foo", "a variable"),
+        ] {
+            let code = format!("{{ pkgs }}: {{\n  deps = {};\n}}", value);
+            let ast = rnix::Root::parse(&code).syntax().clone_for_update();
+            match verify_get(&ast, DepType::Regular) {
+                Err(NixEditorError::NotAList { key, kind: found }) => {
+                    assert_eq!(key, "deps");
+                    assert_eq!(found, kind);
+                }
+                res => panic!("expected NotAList, got {:?}", res),
+            }
+        }
+
+        let code = r#"{ pkgs }: {
+  deps = [];
+  env = {
+    PYTHON_LD_LIBRARY_PATH = pkgs.lib.makeLibraryPath libs;
+  };
+}"#;
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert_eq!(
+            verify_get(&ast, DepType::Python).unwrap_err().to_string(),
+            "Could not edit PYTHON_LD_LIBRARY_PATH: it is a variable rather than a literal list"
+        );
+    }
+
+    fn verify_get_kind(code: &str, file_kind: FileKind) -> error::Result<SyntaxNodeAndWhitespace> {
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        let options = VerifyOptions {
+            file_kind,
+            ..Default::default()
+        };
+        verify_get_with(&ast, DepType::Regular, &options)
+    }
+
+    #[test]
+    fn verify_get_shell() {
+        for code in [
+            "{ pkgs ? import <nixpkgs> {} }:\npkgs.mkShell {\n  buildInputs = [ pkgs.hello ];\n}",
+            "with import <nixpkgs> {};\nmkShell {\n  buildInputs = [ pkgs.hello ];\n}",
+            "let\n  pkgs = import <nixpkgs> {};\nin\npkgs.mkShell {\n  buildInputs = [ pkgs.hello ];\n}",
+        ] {
+            let deps_list = verify_get_kind(code, FileKind::Shell).unwrap();
+            assert_eq!(deps_list.node.to_string(), "[ pkgs.hello ]");
+        }
+
+        let code = "{ pkgs ? import <nixpkgs> {} }:\npkgs.mkShell {\n  name = \"dev\";\n}";
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        let options = VerifyOptions {
+            file_kind: FileKind::Shell,
+            ..Default::default()
+        };
+        assert!(verify_get_with(&ast, DepType::Regular, &options).is_ok());
+        assert!(ast.to_string().contains("buildInputs = [];"));
+    }
+
+    #[test]
+    fn verify_get_default() {
+        let code = r#"{ stdenv, pkgs }:
+stdenv.mkDerivation {
+  name = "hello";
+  buildInputs = [ pkgs.zlib ];
+}"#;
+        let deps_list = verify_get_kind(code, FileKind::Default).unwrap();
+        assert_eq!(deps_list.node.to_string(), "[ pkgs.zlib ]");
+
+        // a shell.nix is made with mkShell, not mkDerivation
+        let err = verify_get_kind(code, FileKind::Shell).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not verify and get: error: expected shell.nix to call mkShell but it calls stdenv.mkDerivation"
+        );
+    }
+
+    #[test]
+    fn verify_get_errors_on_wrong_file_kind() {
+        let err = verify_get_kind(PYTHON_REPLIT_NIX, FileKind::Shell).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not verify and get: error: expected shell.nix to call mkShell but got an attribute set"
+        );
+
+        let shell_nix = "{ pkgs ? import <nixpkgs> {} }:\npkgs.mkShell {\n  buildInputs = [];\n}";
+        let err = verify_get_kind(shell_nix, FileKind::Replit).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("use the shell or default file kind"));
+
+        let ast = rnix::Root::parse(shell_nix).syntax().clone_for_update();
+        let options = VerifyOptions {
+            file_kind: FileKind::Shell,
+            ..Default::default()
+        };
+        assert!(verify_get_with(&ast, DepType::Python, &options).is_err());
+    }
+
+    #[test]
+    fn verify_get_error_names_the_kinds() {
+        let ast = rnix::Root::parse("{ pkgs }: [ ]")
+            .syntax()
+            .clone_for_update();
+        assert_eq!(
+            verify_get(&ast, DepType::Regular).unwrap_err().to_string(),
+            "Could not verify and get: error: expected NODE_ATTR_SET but got NODE_LIST (from attr_set.kind())"
+        );
+
+        let ast = rnix::Root::parse("{ pkgs }: { deps = [ ]; }")
+            .syntax()
+            .clone_for_update();
+        assert_eq!(
+            verify_get(&ast, DepType::Flake).unwrap_err().to_string(),
+            "Could not verify and get: error: expected NODE_ATTR_SET but got NODE_LAMBDA (from flake.kind())"
+        );
+    }
+
+    #[test]
+    fn verify_get_through_lets() {
+        let code = r#"let
+  lang = "en_US.UTF-8";
+in
+{ pkgs }: let
+  python = pkgs.python310;
+in {
+  deps = [ python ];
+}"#;
+        let deps_list = gets_ok(code, DepType::Regular);
+        assert_eq!(deps_list.node.to_string(), "[ python ]");
+    }
+
+    #[test]
+    fn verify_get_follows_let_bound_deps() {
+        let code = r#"let
+  deps = pkgs: [
+    pkgs.cowsay
+  ];
+  myDeps = with pkgs; [
+    ncdu
+  ];
+in
+{ pkgs }: {
+  deps = otherDeps;
+  otherDeps = myDeps;
+}"#;
+        // otherDeps isn't bound by a let, the attr set isn't recursive
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert!(matches!(
+            verify_get(&ast, DepType::Regular),
+            Err(NixEditorError::NotAList { .. })
+        ));
+
+        let code = code.replace("deps = otherDeps;", "deps = myDeps;");
+        let deps_list = gets_ok(&code, DepType::Regular);
+        assert_eq!(deps_list.node.to_string(), "[\n    ncdu\n  ]");
+        assert!(is_with_pkgs(&deps_list.node, "pkgs"));
+
+        // variables bound to each other don't resolve to anything
+        let code = "let\n  a = b;\n  b = a;\nin\n{ pkgs }: {\n  deps = a;\n}";
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert!(verify_get(&ast, DepType::Regular).is_err());
+    }
+
+    #[test]
+    fn verify_get_existing_leaves_tree_alone() {
+        let code = "{ pkgs }: {\n  deps = [];\n}";
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+
+        assert!(verify_get_existing(&ast, DepType::Python).is_err());
+        assert_eq!(ast.to_string(), code);
+    }
+
+    #[test]
+    fn verify_get_with_extra_args() {
+        for pattern in [
+            "{ pkgs }",
+            "{ pkgs, ... }",
+            "{ lib, pkgs }",
+            "{ system, pkgs ? import <nixpkgs> { } }",
+        ] {
+            let code = format!("{}: {{\n  deps = [ pkgs.cowsay ];\n}}", pattern);
+            let deps_list = gets_ok(&code, DepType::Regular);
+            assert_eq!(deps_list.node.to_string(), "[ pkgs.cowsay ]");
+        }
+    }
+
+    #[test]
+    fn verify_get_without_pkgs_arg() {
+        for pattern in ["{ foo }", "{ pkgsFoo, ... }", "{ foo ? pkgs }"] {
+            let code = format!("{}: {{\n  deps = [];\n}}", pattern);
+            let ast = rnix::Root::parse(&code).syntax().clone_for_update();
+            assert!(verify_get(&ast, DepType::Regular).is_err());
+        }
+    }
+
+    #[test]
+    fn verify_get_merges_duplicate_deps() {
+        let code = r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+  ];
+  env = {};
+  deps = [
+    pkgs.ncdu
+    pkgs.htop
+    pkgs.cowsay
+  ];
+}"#;
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        let deps_list = verify_get(&ast, DepType::Regular);
+        assert!(deps_list.is_ok());
+
+        assert_eq!(
+            ast.to_string(),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.cowsay
+    pkgs.ncdu
+    pkgs.htop
+  ];
+  env = {};
+}"#
+        );
+
+        // without inserting anything, the duplicate is reported instead
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        let err = verify_get_existing(&ast, DepType::Regular).unwrap_err();
+        assert!(err.to_string().contains("deps is defined 2 times"));
+    }
+
+    #[test]
+    fn verify_get_merges_duplicate_deps_with_comments() {
+        let code = r#"{ pkgs }: {
+  deps = [
+    pkgs.a # first
+  ];
+  deps = [
+    # from the other branch
+    pkgs.b # keep me
+    pkgs.a
+    pkgs.c
+  ];
+}"#;
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert!(verify_get(&ast, DepType::Regular).is_ok());
+        assert_eq!(
+            ast.to_string(),
+            r#"{ pkgs }: {
+  deps = [
+    pkgs.a # first
+    # from the other branch
+    pkgs.b # keep me
+    pkgs.c
+  ];
+}"#
+        );
+
+        // bare names from a `with pkgs;` list get their pkgs. back
+        let code = "{ pkgs }: {\n  deps = [ pkgs.a ];\n  deps = with pkgs; [ b ];\n}";
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert!(verify_get(&ast, DepType::Regular).is_ok());
+        assert_eq!(
+            ast.to_string(),
+            "{ pkgs }: {\n  deps = [ pkgs.a pkgs.b ];\n}"
+        );
+    }
+
+    fn verify_get_target(
+        code: &str,
+        file_kind: FileKind,
+        target_path: &str,
+    ) -> (error::Result<SyntaxNodeAndWhitespace>, String) {
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        let options = VerifyOptions {
+            file_kind,
+            target_path: Some(target_path.to_string()),
+            ..Default::default()
+        };
+        let res = verify_get_with(&ast, DepType::Regular, &options);
+        (res, ast.to_string())
+    }
+
+    #[test]
+    fn verify_get_target_path() {
+        let (res, contents) = verify_get_target(
+            PYTHON_REPLIT_NIX,
+            FileKind::Replit,
+            "env.PYTHON_LD_LIBRARY_PATH",
+        );
+        let deps_list = res.unwrap().node.to_string();
+        assert!(deps_list.starts_with('[') && deps_list.contains("pkgs.xorg.libX11"));
+        assert_eq!(contents, PYTHON_REPLIT_NIX);
+
+        let shell_nix =
+            "{ pkgs ? import <nixpkgs> {} }:\npkgs.mkShell {\n  buildInputs = [ pkgs.hello ];\n}";
+        let (res, contents) = verify_get_target(shell_nix, FileKind::Shell, "nativeBuildInputs");
+        assert_eq!(res.unwrap().node.to_string(), "[]");
+        assert_eq!(
+            contents,
+            "{ pkgs ? import <nixpkgs> {} }:\npkgs.mkShell {\n  buildInputs = [ pkgs.hello ];\n  nativeBuildInputs = [];\n}"
+        );
+    }
+
+    #[test]
+    fn verify_get_target_path_inserts_missing_keys() {
+        let (res, contents) = verify_get_target(
+            "{ pkgs }: {\n  deps = [];\n}",
+            FileKind::Replit,
+            "env.EXTRA",
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            contents,
+            "{ pkgs }: {\n  deps = [];\n  env = {\n    EXTRA = [];\n  };\n}"
+        );
+    }
+
+    #[test]
+    fn verify_get_target_path_errors() {
+        let (res, _) = verify_get_target(PYTHON_REPLIT_NIX, FileKind::Replit, "deps.extra");
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Could not verify and get: error: expected deps to be an attribute set but got list"
+        );
+
+        for target_path in ["", "env..PATH", "\"quoted\"", "1st"] {
+            let (res, contents) =
+                verify_get_target(PYTHON_REPLIT_NIX, FileKind::Replit, target_path);
+            assert!(res
+                .unwrap_err()
+                .to_string()
+                .contains("is not a dotted path of keys"));
+            assert_eq!(contents, PYTHON_REPLIT_NIX);
+        }
+    }
+
+    #[test]
+    fn verify_get_starts_blank_files_from_template() {
+        for (code, expected) in [
+            ("", "{pkgs}: {\n  deps = [];\n}"),
+            ("  \n\n", "{pkgs}: {\n  deps = [];\n}"),
+            ("# TODO", "# TODO\n{pkgs}: {\n  deps = [];\n}"),
+            ("# TODO\n", "# TODO\n{pkgs}: {\n  deps = [];\n}"),
+        ] {
+            let ast = rnix::Root::parse(code).syntax().clone_for_update();
+            assert!(verify_get(&ast, DepType::Regular).is_ok());
+            assert_eq!(ast.to_string(), expected);
+        }
+
+        // anything else that isn't a replit.nix still fails
+        let ast = rnix::Root::parse("# TODO\nfoo").syntax().clone_for_update();
+        assert!(verify_get(&ast, DepType::Regular).is_err());
+        assert_eq!(ast.to_string(), "# TODO\nfoo");
+    }
+
+    #[test]
+    fn verify_get_accepts_make_library_path_variants() {
+        for select in [
+            "pkgs.lib.makeLibraryPath",
+            "lib.makeLibraryPath",
+            "pkgs.lib . makeLibraryPath",
+        ] {
+            let code = format!(
+                "{{ pkgs }}: {{\n  env = {{\n    PYTHON_LD_LIBRARY_PATH = {} [ pkgs.zlib ];\n  }};\n}}",
+                select
+            );
+            let deps_list = gets_ok(&code, DepType::Python);
+            assert_eq!(deps_list.node.to_string(), "[ pkgs.zlib ]");
+        }
+
+        // the configured arg name works too
+        let code = "{ nixpkgs }: {\n  env = {\n    LD_LIBRARY_PATH = nixpkgs.lib.makeLibraryPath [];\n  };\n}";
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        let options = VerifyOptions {
+            arg_name: "nixpkgs".to_string(),
+            ..Default::default()
+        };
+        assert!(verify_get_with(&ast, DepType::Node, &options).is_ok());
+
+        for select in [
+            "pkgs.lib.makeSearchPath",
+            "makeLibraryPath",
+            "other.lib.makeLibraryPath",
+        ] {
+            let code = format!(
+                "{{ pkgs }}: {{\n  env = {{\n    PYTHON_LD_LIBRARY_PATH = {} [];\n  }};\n}}",
+                select
+            );
+            let ast = rnix::Root::parse(&code).syntax().clone_for_update();
+            assert!(verify_get(&ast, DepType::Python).is_err());
+        }
+    }
+
+    #[test]
+    fn verify_get_concatenated_deps() {
+        for (value, list) in [
+            ("baseDeps ++ [ pkgs.extra ]", "[ pkgs.extra ]"),
+            ("[ pkgs.extra ] ++ baseDeps", "[ pkgs.extra ]"),
+            ("a ++ [ pkgs.extra ] ++ b", "[ pkgs.extra ]"),
+            ("[ pkgs.base ] ++ a ++ [ pkgs.extra ]", "[ pkgs.extra ]"),
+            ("with pkgs; baseDeps ++ [ extra ]", "[ extra ]"),
+        ] {
+            let code = format!("{{ pkgs }}: {{\n  deps = {};\n}}", value);
+            let deps_list = gets_ok(&code, DepType::Regular);
+            assert_eq!(deps_list.node.to_string(), list);
+        }
+
+        let code = "{ pkgs }: {\n  deps = baseDeps ++ moreDeps;\n}";
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert_eq!(
+            verify_get(&ast, DepType::Regular).unwrap_err().to_string(),
+            "Could not edit deps: it is a `++` concatenation of non-literal lists rather than a literal list"
+        );
+
+        // other operators still aren't lists
+        let code = "{ pkgs }: {\n  deps = a // b;\n}";
+        let ast = rnix::Root::parse(code).syntax().clone_for_update();
+        assert!(matches!(
+            verify_get(&ast, DepType::Regular),
+            Err(NixEditorError::NotAList { kind, .. }) if kind == "a binary operation"
+        ));
+    }
 }
@@ -0,0 +1,9 @@
+use wasm_bindgen::prelude::*;
+
+use crate::op_json::run_op_json;
+
+// apply_op(contents, op_json) for javascript, see run_op_json for what it returns
+#[wasm_bindgen(js_name = apply_op)]
+pub fn apply_op_js(contents: &str, op_json: &str) -> String {
+    run_op_json(contents, op_json)
+}